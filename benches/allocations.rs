@@ -0,0 +1,101 @@
+//! Counts the heap allocations made while processing generated transactions, to spot allocations made for every
+//! transaction on the hot path. Run with `cargo bench --bench allocations`.
+
+use gravenche::{source::CsvSource, Gravenche, GravencheBuilder, DEFAULT_TRANSACTIONS_ALLOWED};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+/// Number of generated transactions.
+const ROWS: usize = 1_000_000;
+/// Number of distinct clients the transactions are spread over.
+const CLIENTS: usize = 1_000;
+
+/// Passes allocations on to the system allocator and counts them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Generates CSV input of deposits, withdrawals, disputes and resolves in a repeating pattern.
+fn input() -> Vec<u8> {
+    let mut input = Vec::new();
+    writeln!(input, "type, client, tx, amount").unwrap();
+    for row in 0..ROWS {
+        let client = row % CLIENTS;
+        let tx = row + 1;
+        match row % 8 {
+            0..=3 => writeln!(input, "deposit, {}, {}, 10.0", client, tx),
+            4 | 5 => writeln!(input, "withdrawal, {}, {}, 1.5", client, tx),
+            6 => writeln!(input, "dispute, {}, {},", client, tx - 6),
+            _ => writeln!(input, "resolve, {}, {},", client, tx - 7),
+        }
+        .unwrap();
+    }
+    input
+}
+
+// Processes the input with a Gravenche set up by `configure` and prints the allocations made.
+fn run(
+    name: &str,
+    input: &[u8],
+    runtime: &tokio::runtime::Runtime,
+    configure: impl FnOnce(GravencheBuilder<io::Sink>) -> GravencheBuilder<io::Sink>,
+) {
+    let builder = Gravenche::builder(PathBuf::new(), io::sink())
+        .transactions_allowed(DEFAULT_TRANSACTIONS_ALLOWED);
+    let mut gravenche = configure(builder).build();
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    runtime.block_on(async {
+        gravenche
+            .start_with_source(CsvSource::new(input))
+            .await
+            .unwrap();
+    });
+    let elapsed = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+    println!(
+        "{}: {} rows in {:.2?}, {} allocations, {:.3} per row",
+        name,
+        ROWS,
+        elapsed,
+        allocations,
+        allocations as f64 / ROWS as f64
+    );
+}
+
+fn main() {
+    let input = input();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    run("plain", &input, &runtime, |builder| builder);
+
+    let wal_path = std::env::temp_dir().join("gravenche_bench_allocations.wal");
+    run("wal", &input, &runtime, |builder| {
+        builder.wal(wal_path.clone()).wal_sync_every(usize::MAX)
+    });
+    let _ = std::fs::remove_file(&wal_path);
+}
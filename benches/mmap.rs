@@ -0,0 +1,61 @@
+//! Compares reading an input file through a buffered reader with reading it through a memory map, as `--mmap` does.
+//! Run with `cargo bench --bench mmap --features mmap`.
+
+use gravenche::Gravenche;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Number of generated transactions.
+const ROWS: usize = 2_000_000;
+/// Number of distinct clients the transactions are spread over.
+const CLIENTS: usize = 1_000;
+
+// Writes CSV input of deposits and withdrawals to `path`.
+fn write_input(path: &Path) -> io::Result<()> {
+    let mut input = BufWriter::new(File::create(path)?);
+    writeln!(input, "type, client, tx, amount")?;
+    for row in 0..ROWS {
+        let client = row % CLIENTS;
+        let tx = row + 1;
+        match row % 4 {
+            0..=2 => writeln!(input, "deposit, {}, {}, 10.0", client, tx),
+            _ => writeln!(input, "withdrawal, {}, {}, 1.5", client, tx),
+        }?;
+    }
+    input.flush()
+}
+
+// Prints the rows processed per second by a run which took `elapsed`.
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{}: {} rows in {:.2?}, {:.0} rows/s",
+        name,
+        ROWS,
+        elapsed,
+        ROWS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("gravenche_bench_mmap.csv");
+    write_input(&path).unwrap();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    for (name, mmap) in [("buffered reader", false), ("memory map", true)] {
+        let mut gravenche = Gravenche::builder(path.clone(), io::sink())
+            .mmap(mmap)
+            .build();
+        let started = Instant::now();
+        runtime.block_on(gravenche.start()).unwrap();
+        report(name, started.elapsed());
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,66 @@
+//! Compares the throughput of parsing an input file in a reader task of its own, as [Gravenche::start] does, with
+//! parsing it on the task sending the transactions to the processor. Run with `cargo bench --bench throughput`.
+
+use gravenche::{source::CsvSource, Gravenche, DEFAULT_TRANSACTIONS_ALLOWED};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Number of generated transactions.
+const ROWS: usize = 2_000_000;
+/// Number of distinct clients the transactions are spread over.
+const CLIENTS: usize = 1_000;
+
+// Writes CSV input of deposits, withdrawals, disputes and resolves in a repeating pattern to `path`.
+fn write_input(path: &Path) -> io::Result<()> {
+    let mut input = BufWriter::new(File::create(path)?);
+    writeln!(input, "type, client, tx, amount")?;
+    for row in 0..ROWS {
+        let client = row % CLIENTS;
+        let tx = row + 1;
+        match row % 8 {
+            0..=3 => writeln!(input, "deposit, {}, {}, 10.0", client, tx),
+            4 | 5 => writeln!(input, "withdrawal, {}, {}, 1.5", client, tx),
+            6 => writeln!(input, "dispute, {}, {},", client, tx - 6),
+            _ => writeln!(input, "resolve, {}, {},", client, tx - 7),
+        }?;
+    }
+    input.flush()
+}
+
+// Prints the rows processed per second by a run which took `elapsed`.
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{}: {} rows in {:.2?}, {:.0} rows/s",
+        name,
+        ROWS,
+        elapsed,
+        ROWS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("gravenche_bench_throughput.csv");
+    write_input(&path).unwrap();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut gravenche = Gravenche::new(path.clone(), DEFAULT_TRANSACTIONS_ALLOWED, io::sink());
+    let started = Instant::now();
+    runtime.block_on(gravenche.start()).unwrap();
+    report("reader task", started.elapsed());
+
+    let mut gravenche = Gravenche::new(path.clone(), DEFAULT_TRANSACTIONS_ALLOWED, io::sink());
+    let started = Instant::now();
+    runtime
+        .block_on(gravenche.start_with_source(CsvSource::new(File::open(&path).unwrap())))
+        .unwrap();
+    report("same task", started.elapsed());
+
+    let _ = std::fs::remove_file(&path);
+}
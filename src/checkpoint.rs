@@ -0,0 +1,190 @@
+//! This module contains the saved state of a [Gravenche](crate::Gravenche), written by
+//! [Gravenche::save_snapshot](crate::Gravenche::save_snapshot) and by periodic checkpoints, and read back by
+//! [Gravenche::load_snapshot](crate::Gravenche::load_snapshot) and [Gravenche::resume](crate::Gravenche::resume).
+//!
+//! The state is encoded with bincode. A checkpoint also records how many input transactions the processor handled,
+//! so a resumed run knows where to continue.
+
+use crate::types::{
+    client::{Client, ClientStore},
+    error::GravencheError,
+    transaction::Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Version of the format. Saved states of other versions are refused.
+const STATE_VERSION: u32 = 15;
+
+/// A saved state as it is read back.
+#[derive(Deserialize)]
+pub(crate) struct SavedState {
+    version: u32,
+    pub clients: Vec<Client>,
+    pub transactions: Vec<Transaction>,
+    /// Number of input transactions handled before a checkpoint. Not set by
+    /// [Gravenche::save_snapshot](crate::Gravenche::save_snapshot).
+    pub position: Option<u64>,
+    /// Total of the withdrawal fees charged until the state was saved.
+    pub fees_collected: f32,
+}
+
+// A saved state as it is written, borrowing the data to avoid copying it.
+#[derive(Serialize)]
+struct SavedStateRef<'a> {
+    version: u32,
+    clients: Vec<&'a Client>,
+    transactions: Vec<&'a Transaction>,
+    position: Option<u64>,
+    fees_collected: f32,
+}
+
+/// Writes the clients and processed transactions, the position of a checkpoint and the withdrawal fees collected.
+pub(crate) fn write_state<W: Write, S: ClientStore>(
+    w: W,
+    clients: &S,
+    transactions: &HashMap<u32, Transaction>,
+    position: Option<u64>,
+    fees_collected: f32,
+) -> Result<(), GravencheError> {
+    let state = SavedStateRef {
+        version: STATE_VERSION,
+        clients: clients.iter().collect(),
+        transactions: transactions.values().collect(),
+        position,
+        fees_collected,
+    };
+    let mut writer = BufWriter::new(w);
+    bincode::serialize_into(&mut writer, &state)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a state written by [write_state].
+pub(crate) fn read_state<R: Read>(r: R) -> Result<SavedState, GravencheError> {
+    let state: SavedState = bincode::deserialize_from(BufReader::new(r))?;
+    if state.version != STATE_VERSION {
+        return Err(GravencheError::Parse(format!(
+            "Unsupported snapshot version {}",
+            state.version
+        )));
+    }
+    Ok(state)
+}
+
+/// Number of encoded checkpoints queued for the writing task of a [Checkpointer] before recording waits for it. Every
+/// queued checkpoint is a copy of the whole state, so only one waits while the previous one is written.
+const CHECKPOINT_QUEUE: usize = 1;
+
+/// Writes a checkpoint every time a number of transactions were applied since the previous one. The state is encoded
+/// by the processor, but written and synced to disk by a blocking task of its own, so the processor doesn't wait for
+/// the disk.
+pub(crate) struct Checkpointer {
+    /// Number of applied transactions between two checkpoints.
+    every: u64,
+    /// Number of input transactions handled so far, including the ones handled before a resume.
+    position: u64,
+    /// Number of transactions applied since the last checkpoint.
+    applied: u64,
+    /// Total of the withdrawal fees charged so far, including the ones charged before the run.
+    fees_collected: f32,
+    /// Queue of the encoded checkpoints to write with their position. Dropped to let the task finish.
+    sender: Option<mpsc::Sender<(u64, Vec<u8>)>>,
+    /// Writing task. Taken once it finished.
+    writer: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Checkpointer {
+    /// Creates a checkpointer for a run which starts after `position` input transactions and `fees_collected` in
+    /// withdrawal fees, and starts the task writing its checkpoints. Must be called within a Tokio runtime.
+    pub fn new(path: PathBuf, every: u64, position: u64, fees_collected: f32) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<(u64, Vec<u8>)>(CHECKPOINT_QUEUE);
+        let writer = tokio::task::spawn_blocking(move || {
+            while let Some((position, state)) = receiver.blocking_recv() {
+                write_checkpoint(&path, &state)?;
+                tracing::debug!(position, "Wrote a checkpoint");
+            }
+            Ok(())
+        });
+        Checkpointer {
+            every: every.max(1),
+            position,
+            applied: 0,
+            fees_collected,
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+
+    /// Records a handled transaction with the fee charged on it, and queues a checkpoint if it was the last one of a
+    /// batch of `every` applied transactions. Transactions still queued are not part of the checkpoint, a resumed run
+    /// reads them again. Returns the error which stopped the writing task, if any.
+    pub async fn record<S: ClientStore>(
+        &mut self,
+        applied: bool,
+        fee: f32,
+        clients: &S,
+        transactions: &HashMap<u32, Transaction>,
+    ) -> anyhow::Result<()> {
+        self.position += 1;
+        self.fees_collected += fee;
+        if applied {
+            self.applied += 1;
+        }
+        if self.applied >= self.every {
+            let mut state = Vec::new();
+            write_state(
+                &mut state,
+                clients,
+                transactions,
+                Some(self.position),
+                self.fees_collected,
+            )?;
+            let sent = match &self.sender {
+                Some(sender) => sender.send((self.position, state)).await.is_ok(),
+                None => false,
+            };
+            if !sent {
+                // The task only stops before it is finished on an error.
+                self.finish().await?;
+                anyhow::bail!("The checkpoint writer is closed");
+            }
+            self.applied = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the queued checkpoints and waits for the writing task to finish. Recording fails afterwards.
+    pub async fn finish(&mut self) -> anyhow::Result<()> {
+        self.sender = None;
+        match self.writer.take() {
+            Some(writer) => writer.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+// Writes an encoded checkpoint to a temporary file next to `path`, syncs it and renames it, so a crash never leaves a
+// partial checkpoint behind.
+fn write_checkpoint(path: &Path, state: &[u8]) -> anyhow::Result<()> {
+    let temp_path = temp_path(path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(state)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+// Returns the path of the temporary file a checkpoint is written to before it replaces `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
@@ -3,32 +3,139 @@
 //! uses MPSC channels instead of Mutex based locking mechanism. This helps in avoid time required
 //! to lock/unlock the mutex. Also, it avoids race condition and mutex poiosoning.
 //! Here is how it works.
-//! 1. It reads a csv file from the path provided in the constructor.
+//! 1. It reads a csv file from the path provided in the constructor, or any other [TransactionSource].
 //! 2. Starts a tokio task to process the csv.
 //! 3. It then starts reading entries one by one and sends them in same order to the input channel.
 //! 4. The tokio task processes each entries in the same order as received.
-//! 5. For each record if the record type is Deposit or Withdrawl then the tokio task adds entry to
-//! the [ProcessedTransactions].
+//! 5. For each record if the record type is Deposit or Withdrawal then the tokio task adds entry to
+//!    the [ProcessedTransactions].
 //! 6. It retrieves the existing client record from [Clients] if there is any or creates one. It does
-//! calculations in this step.
+//!    calculations in this step.
 //! 7. Output is shown using a method [Gravenche::show_output].
 
+use crate::checkpoint::{read_state, write_state, Checkpointer, SavedState};
+use crate::ledger::RunRules;
+use crate::metrics::Metrics;
+use crate::observer::{SharedObserver, TransactionObserver};
+use crate::output::{write_table, ConfiguredFormatter, OutputFormatter};
+use crate::server::handle_connection;
+use crate::source::{
+    BlockingCsvSource, CsvSource, FollowSource, NdjsonSource, SortedSource, TransactionSource,
+};
 use crate::types::{
-    client::{Client, Clients},
-    other::Command,
+    client::{Client, ClientId, ClientStore, Clients, InvariantViolation},
+    config::{
+        Column, Compression, Config, FeePolicy, InputFormat, NumberLocale, OutputFormat,
+        VelocityRule, WithdrawalDisputes,
+    },
+    currency::Currency,
+    error::GravencheError,
+    other::{ClientEvent, Command, FileSummary, ProcessingSummary, Report},
     transaction::{
-        ProcessedTransactions, Transaction, TransactionType, AMOUNT_INDEX, CLIENT_ID_INDEX,
-        TRANSACTION_ID_INDEX, TRANSACTION_TYPE_INDEX,
+        format_dispute_events, DisputeEvent, ProcessedTransactions, Transaction, TransactionType,
     },
 };
-use std::io::Write;
-use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf, str::FromStr, sync::Arc};
-use tokio::sync::{mpsc, Mutex};
+use crate::wal::{WalReader, WalWriter};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::bufread::MultiGzDecoder;
+use std::io::{self, BufRead, Read, Write};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    future::Future,
+    io::BufReader,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio::time::Interval;
+
+/// A path meaning that transactions are read from the standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// Bytes every gzip stream starts with.
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+/// Number of events kept for subscribers which are behind.
+pub const EVENTS_CAPACITY: usize = 1024;
+
+/// Default number of transactions allowed to be pushed in queue.
+pub const DEFAULT_TRANSACTIONS_ALLOWED: i32 = 100000;
+
+/// A callback told the number of input rows read so far, every `every` rows.
+struct Progress {
+    every: u64,
+    callback: Box<dyn Fn(u64) + Send + Sync>,
+}
+
+// Tells if CSV input is parsed in a blocking task of its own, which is the case when there is more than one core.
+fn parallel_reading() -> bool {
+    std::thread::available_parallelism().is_ok_and(|cores| cores.get() > 1)
+}
+
+// Returns the `n` clients with the highest total balance in descending order, those with equal totals by client ID.
+fn highest_totals(mut clients: Vec<Client>, n: usize) -> Vec<Client> {
+    clients.sort_by(|a, b| b.total.total_cmp(&a.total).then(a.id.cmp(&b.id)));
+    clients.truncate(n);
+    clients
+}
+
+// Waits for the next tick of a timer. Never completes without a timer.
+async fn next_tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// Returns true if a future has completed, without waiting for it. It must not be polled again once completed.
+async fn has_completed<F: Future<Output = ()>>(future: std::pin::Pin<&mut F>) -> bool {
+    tokio::select! {
+        biased;
+        _ = future => true,
+        _ = std::future::ready(()) => false,
+    }
+}
+
+// Records the processor keeps of the transactions it handles, besides the state itself.
+struct ProcessorRecords {
+    wal: Option<WalWriter>,
+    checkpointer: Option<Checkpointer>,
+    // Number of input transactions handled before the processor started, so dispute events get unique sequences.
+    position: u64,
+    // IDs of the deposits and withdrawals at or above the flag threshold, shared with [Gravenche].
+    flagged_transactions: Arc<Mutex<Vec<u32>>>,
+    // Observer told the outcome of every transaction.
+    observer: Option<SharedObserver>,
+}
+
+// Adds the statistics of a finished connection to the summary.
+fn add_connection_summary(
+    summary: &mut ProcessingSummary,
+    finished: Result<anyhow::Result<FileSummary>, JoinError>,
+) {
+    match finished {
+        Ok(Ok(connection_summary)) => {
+            summary.rows_read += connection_summary.rows_read;
+            summary.rows_skipped += connection_summary.rows_skipped;
+            summary.files.push(connection_summary);
+        }
+        Ok(Err(error)) => tracing::warn!("Connection failed: {}", error),
+        Err(error) => tracing::warn!("Connection task failed: {}", error),
+    }
+}
 
 /// The core of the whole crate. It processes all the transaction and update various data structures to reflect the transactions.
 pub struct Gravenche<T: Write> {
-    /// Path to the CSV file containing transactions.
-    csv_path: PathBuf,
+    /// Paths to the CSV files containing transactions, processed in order. [STDIN_PATH] reads transactions from the
+    /// standard input.
+    csv_paths: Vec<PathBuf>,
     /// Datastorage for all the clients.
     clients: Clients,
     /// A sender part of MPSC channel used to send transactions to the processor.
@@ -39,254 +146,6015 @@ pub struct Gravenche<T: Write> {
     num_transaction_allowed: i32,
     /// Output stream to write to.
     output_stream: T,
+    /// Handle of the transaction processor task.
+    processor_handle: Option<JoinHandle<anyhow::Result<ProcessingSummary>>>,
+    /// Options controlling processing and reporting.
+    config: Config,
+    /// Operational metrics of the transaction processor.
+    metrics: Arc<Metrics>,
+    /// Broadcasts an event for every applied transaction.
+    events: broadcast::Sender<ClientEvent>,
+    /// Shares the sender of the running processor with [ClientsHandle]s. `None` between runs.
+    running: watch::Sender<Option<mpsc::Sender<Command>>>,
+    /// Set to stop reading the input files once the run is interrupted.
+    stop_reading: watch::Sender<bool>,
+    /// Total of the withdrawal fees charged by all finished runs.
+    collected_fees: f32,
+    /// IDs of the deposits and withdrawals flagged for reaching the flag threshold, in the order they were applied.
+    flagged_transactions: Arc<Mutex<Vec<u32>>>,
+    /// Callback reporting the progress of reading the input.
+    progress: Option<Progress>,
+    /// Observer told the outcome of every transaction.
+    observer: Option<SharedObserver>,
+    /// Formatter writing the clients shown at the end instead of the configured output format.
+    formatter: Option<Box<dyn OutputFormatter + Send>>,
 }
 
-impl<T: Write> Gravenche<T> {
-    pub fn new(csv_path: PathBuf, transactions_allowed: i32, output_stream: T) -> Self {
+/// A handle reading the clients and processed transactions of a [Gravenche] from another task, including while it
+/// processes transactions.
+#[derive(Clone)]
+pub struct ClientsHandle {
+    /// Sender of the running processor, if any.
+    running: watch::Receiver<Option<mpsc::Sender<Command>>>,
+    /// Datastorage for all the clients.
+    clients: Clients,
+    /// List of processed transactions.
+    processed_transactions: ProcessedTransactions,
+}
+
+impl ClientsHandle {
+    /// Returns a copy of the clients reflecting all the transactions queued so far.
+    pub async fn snapshot_clients(&self) -> HashMap<ClientId, Client> {
+        // The processor holds the lock on the clients during a run, so it is asked for a copy instead.
+        let sender = self.running.borrow().clone();
+        if let Some(sender) = sender {
+            let (reply, snapshot) = oneshot::channel();
+            if sender.send(Command::Snapshot(reply)).await.is_ok() {
+                // No reply means the run ended in the meantime and the lock is free.
+                if let Ok(snapshot) = snapshot.await {
+                    return snapshot
+                        .into_iter()
+                        .map(|client| (client.id, client))
+                        .collect();
+                }
+            }
+        }
+        self.clients.lock().await.clone()
+    }
+
+    /// Returns a copy of the client with the ID reflecting all the transactions queued so far, or `None` if no
+    /// transaction created it.
+    pub async fn client(&self, id: ClientId) -> Option<Client> {
+        // Like snapshots, the client is asked from the processor while it holds the lock.
+        let sender = self.running.borrow().clone();
+        if let Some(sender) = sender {
+            let (reply, client) = oneshot::channel();
+            if sender.send(Command::Client(id, reply)).await.is_ok() {
+                if let Ok(client) = client.await {
+                    return client;
+                }
+            }
+        }
+        self.clients.lock().await.get(&id).cloned()
+    }
+
+    /// Returns a copy of the processed transaction with the ID, with its dispute state, reflecting all the
+    /// transactions queued so far. Returns `None` if no transaction with the ID was processed.
+    pub async fn transaction(&self, tx_id: u32) -> Option<Transaction> {
+        let sender = self.running.borrow().clone();
+        if let Some(sender) = sender {
+            let (reply, transaction) = oneshot::channel();
+            if sender.send(Command::Lookup(tx_id, reply)).await.is_ok() {
+                if let Ok(transaction) = transaction.await {
+                    return transaction;
+                }
+            }
+        }
+        self.processed_transactions
+            .lock()
+            .await
+            .get(&tx_id)
+            .cloned()
+    }
+}
+
+/// A builder to construct [Gravenche] with non-default options.
+pub struct GravencheBuilder<T: Write> {
+    /// Paths to the CSV files containing transactions.
+    csv_paths: Vec<PathBuf>,
+    /// Number of transactions allowed to be pushed in queue.
+    num_transaction_allowed: i32,
+    /// Output stream to write to.
+    output_stream: T,
+    /// Options controlling processing and reporting.
+    config: Config,
+    /// Callback reporting the progress of reading the input.
+    progress: Option<Progress>,
+    /// Observer told the outcome of every transaction.
+    observer: Option<SharedObserver>,
+    /// Formatter writing the clients shown at the end instead of the configured output format.
+    formatter: Option<Box<dyn OutputFormatter + Send>>,
+}
+
+impl<T: Write> GravencheBuilder<T> {
+    /// Sets the number of transactions allowed to be pushed in queue. A larger value lets the reader run further
+    /// ahead of the processor at the cost of memory, a smaller value applies back-pressure to the reader sooner.
+    pub fn transactions_allowed(mut self, transactions_allowed: i32) -> Self {
+        self.num_transaction_allowed = transactions_allowed;
+        self
+    }
+
+    /// Calls `callback` with the number of input rows read so far, counted over all the input files, every `every`
+    /// rows, e.g. to show progress through long files. Rows which can't be parsed are counted too. They are only known
+    /// in bulk, so a report may come a few rows past a multiple of `every`, but never more than once per multiple. An
+    /// `every` of 0 counts as 1.
+    pub fn progress(mut self, every: u64, callback: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Progress {
+            every: every.max(1),
+            callback: Box::new(callback),
+        });
+        self
+    }
+
+    /// Tells `observer` the outcome of every transaction, e.g. to react to chargebacks. It is kept for all the runs of
+    /// the built [Gravenche]. See [TransactionObserver] for the contract.
+    pub fn observer(mut self, observer: Box<dyn TransactionObserver + Send>) -> Self {
+        self.observer = Some(SharedObserver::new(observer));
+        self
+    }
+
+    /// Writes the clients shown by [show_output](Gravenche::show_output) and
+    /// [show_top_clients](Gravenche::show_top_clients) with `formatter` instead of the [output
+    /// format](Self::output_format), e.g. to add a format of your own.
+    pub fn formatter(mut self, formatter: Box<dyn OutputFormatter + Send>) -> Self {
+        self.formatter = Some(formatter);
+        self
+    }
+
+    /// Adds a CSV file to process after the ones already added.
+    pub fn add_source(mut self, csv_path: PathBuf) -> Self {
+        self.csv_paths.push(csv_path);
+        self
+    }
+
+    /// Adds the number of disputes and chargebacks of each client, and whether its available funds were ever negative,
+    /// to the table and Parquet output. Off by default, so the output has only the columns of the specification.
+    pub fn extended_output(mut self, extended_output: bool) -> Self {
+        self.config.extended_output = extended_output;
+        self
+    }
+
+    /// Shows `columns` of the client table in the given order instead of the client ID, available, held and total
+    /// funds and the locked flag. Only affects the table output.
+    pub fn columns(mut self, columns: Vec<Column>) -> Self {
+        self.config.columns = columns;
+        self
+    }
+
+    /// Shows held funds in the client table as a negative amount, with the available funds including them, for systems
+    /// which model held funds as a debit against available funds. Off by default, so available funds are net of held
+    /// funds and held funds are positive. Only affects the table output.
+    pub fn signed_held(mut self, signed_held: bool) -> Self {
+        self.config.signed_held = signed_held;
+        self
+    }
+
+    /// Restricts the output to clients whose account is locked.
+    pub fn only_locked(mut self, only_locked: bool) -> Self {
+        self.config.only_locked = only_locked;
+        self
+    }
+
+    /// Sets the format of the input files. Defaults to [InputFormat::Csv].
+    pub fn input_format(mut self, input_format: InputFormat) -> Self {
+        self.config.input_format = input_format;
+        self
+    }
+
+    /// Sets the format in which client data is shown. Defaults to [OutputFormat::Table].
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.config.output_format = output_format;
+        self
+    }
+
+    /// Sets the compression of the input files. By default it is detected from each file.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = Some(compression);
+        self
+    }
+
+    /// Sets the number of decimal places amounts are rounded to when they are read and when clients are shown, e.g. 8
+    /// for crypto assets. Amounts are `f32`, so only about seven significant digits are kept whatever the number of
+    /// decimals. Defaults to 4.
+    pub fn decimals(mut self, decimals: u32) -> Self {
+        self.config.decimals = decimals;
+        self
+    }
+
+    /// Writes amounts in the client table the way `locale` does, with thousands grouped and all the decimal places
+    /// shown, e.g. `1,234,567.8900` for [NumberLocale::En]. The amount columns widen to fit the longest amount.
+    /// Exports and other machine readable output are not affected. Defaults to [NumberLocale::Plain].
+    pub fn locale(mut self, locale: NumberLocale) -> Self {
+        self.config.locale = locale;
+        self
+    }
+
+    /// Sets the currency of transactions without one, e.g. of files without a `currency` column. Defaults to USD.
+    pub fn base_currency(mut self, base_currency: Currency) -> Self {
+        self.config.base_currency = base_currency;
+        self
+    }
+
+    /// Sets the field delimiter of CSV input. Defaults to `b','`.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.config.delimiter = delimiter;
+        self
+    }
+
+    /// Treats lines of CSV input files which start with `comment`, e.g. `b'#'`, as comments and skips them. Lines sent
+    /// over a socket or a message queue are not checked. No lines are comments by default.
+    pub fn comment(mut self, comment: u8) -> Self {
+        self.config.comment = Some(comment);
+        self
+    }
+
+    /// Sets the maximum amount a client may have held in dispute. Unlimited by default.
+    pub fn max_held_per_client(mut self, max_held: f32) -> Self {
+        self.config.max_held_per_client = Some(max_held);
+        self
+    }
+
+    /// Limits the number of withdrawals of every client. Withdrawals over the limit are rejected as
+    /// [Rejection::VelocityExceeded](crate::metrics::Rejection::VelocityExceeded), other transactions are not
+    /// affected. Withdrawals are not limited by default.
+    pub fn velocity_limit(mut self, velocity_limit: VelocityRule) -> Self {
+        self.config.velocity_limit = Some(velocity_limit);
+        self
+    }
+
+    /// Flags deposits and withdrawals of at least `threshold`. They are logged with a warning and listed by
+    /// [Gravenche::flagged_transactions], and applied like any other. Only applied transactions are flagged, a rejected
+    /// one such as a withdrawal over the available funds is not. Nothing is flagged by default.
+    pub fn flag_threshold(mut self, threshold: f32) -> Self {
+        self.config.flag_threshold = Some(threshold);
+        self
+    }
+
+    /// Lets available funds of every client go down to minus `limit` through withdrawals and transfers. No
+    /// overdraft is allowed by default, so they may go down to zero. Reversals ignore the limit.
+    pub fn overdraft_limit(mut self, limit: f32) -> Self {
+        self.config.overdraft_limit = limit;
+        self
+    }
+
+    /// Sets the overdraft limit of a single client, overriding [overdraft_limit](GravencheBuilder::overdraft_limit).
+    pub fn client_overdraft_limit(mut self, client_id: ClientId, limit: f32) -> Self {
+        self.config.client_overdraft_limits.insert(client_id, limit);
+        self
+    }
+
+    /// Sets how often a followed file is checked for new data. Defaults to 500 milliseconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.config.poll_interval = poll_interval;
+        self
+    }
+
+    /// Shows the client table every `snapshot_interval` while following a file.
+    pub fn snapshot_interval(mut self, snapshot_interval: Duration) -> Self {
+        self.config.snapshot_interval = Some(snapshot_interval);
+        self
+    }
+
+    /// Stops processing the input files at the first record which isn't a valid transaction, returning
+    /// [GravencheError::BadRow], instead of skipping it. Disabled by default.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// Answers malformed lines sent over TCP with an error. Disabled by default.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Reserves client IDs, e.g. `0..=0` for system accounts. Transactions of reserved clients are skipped. No IDs
+    /// are reserved by default.
+    pub fn reserved_client_ids(
+        mut self,
+        reserved_client_ids: Vec<RangeInclusive<ClientId>>,
+    ) -> Self {
+        self.config.reserved_client_ids = reserved_client_ids;
+        self
+    }
+
+    /// Allows only the given client IDs to transact, e.g. for a fixed set of registered accounts. Transactions of
+    /// other clients, including deposits which would create them, are skipped and logged. All clients are allowed by
+    /// default.
+    pub fn allowed_client_ids(mut self, allowed_client_ids: Vec<RangeInclusive<ClientId>>) -> Self {
+        self.config.allowed_client_ids = Some(allowed_client_ids.into_iter().collect());
+        self
+    }
+
+    /// Processes only the transactions whose ID is in `tx_window`, e.g. to reprocess part of the input. Disputes,
+    /// resolves, chargebacks and reversals are not filtered, they apply to transactions before the window only if those were
+    /// applied earlier, e.g. restored with [Gravenche::load_snapshot]. All transactions are processed by default.
+    pub fn tx_window(mut self, tx_window: RangeInclusive<u32>) -> Self {
+        self.config.tx_window = Some(tx_window);
+        self
+    }
+
+    /// Drops the rows with a timestamp before `since` before they reach the processor. They are counted as
+    /// [filtered](ProcessingSummary::rows_filtered), not as skipped. Rows without a timestamp are always processed.
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.config.since = Some(since);
+        self
+    }
+
+    /// Drops the rows with a timestamp at or after `until` like [since](GravencheBuilder::since).
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.config.until = Some(until);
+        self
+    }
+
+    /// Creates an unknown client with zero balance when it withdraws, so it is shown in the output even though the
+    /// withdrawal fails. By default a withdrawal of an unknown client is ignored.
+    pub fn create_on_withdrawal(mut self, create_on_withdrawal: bool) -> Self {
+        self.config.create_on_withdrawal = create_on_withdrawal;
+        self
+    }
+
+    /// Allows `unlock` transactions to clear the lock a chargeback put on an account. Unlocks are rejected by default,
+    /// so a locked account stays locked.
+    pub fn allow_unlock(mut self, allow_unlock: bool) -> Self {
+        self.config.allow_unlock = allow_unlock;
+        self
+    }
+
+    /// Allows a transaction to be disputed again after its dispute was resolved. By default a resolve settles the
+    /// transaction and further disputes of it are rejected. A chargeback always settles it for good.
+    pub fn redispute_resolved(mut self, redispute_resolved: bool) -> Self {
+        self.config.redispute_resolved = redispute_resolved;
+        self
+    }
+
+    /// Allows disputes of withdrawals and transfers. A disputed withdrawal holds the amount without taking it from the
+    /// available funds, a resolve then credits it to the available funds and a chargeback removes it again. By default
+    /// only deposits can be disputed and disputes of withdrawals are rejected. Shorthand for
+    /// [withdrawal_disputes](Self::withdrawal_disputes) with [WithdrawalDisputes::CreditOnResolve].
+    pub fn dispute_withdrawals(mut self, dispute_withdrawals: bool) -> Self {
+        self.config.withdrawal_disputes = if dispute_withdrawals {
+            WithdrawalDisputes::CreditOnResolve
+        } else {
+            WithdrawalDisputes::Rejected
+        };
+        self
+    }
+
+    /// Sets how disputes of withdrawals and transfers are handled, see [WithdrawalDisputes]. Defaults to
+    /// [WithdrawalDisputes::Rejected].
+    pub fn withdrawal_disputes(mut self, withdrawal_disputes: WithdrawalDisputes) -> Self {
+        self.config.withdrawal_disputes = withdrawal_disputes;
+        self
+    }
+
+    /// Skips transactions whose ID was handled before in the same run, applied or rejected, and counts them as
+    /// replayed, so an input which is accidentally fed twice, e.g. as two of the input files, has the same outcome as
+    /// fed once. Disputes, resolves, chargebacks and reversals refer to another transaction and are left to their own
+    /// checks. The handled IDs are not saved in snapshots or checkpoints. Off by default.
+    pub fn skip_replays(mut self, skip_replays: bool) -> Self {
+        self.config.skip_replays = skip_replays;
+        self
+    }
+
+    /// Allows `admin_credit` and `admin_debit` transactions, which adjust the funds of a client even if the account is
+    /// locked, e.g. for court-ordered refunds. They are rejected by default.
+    pub fn allow_admin_ops(mut self, allow_admin_ops: bool) -> Self {
+        self.config.allow_admin_ops = allow_admin_ops;
+        self
+    }
+
+    /// Charges a fee on every withdrawal. The client needs available funds for the amount and the fee together. Disputes
+    /// and chargebacks of a withdrawal only move the withdrawn amount, the fee is kept. Withdrawals are free by default.
+    pub fn fee_policy(mut self, fee_policy: FeePolicy) -> Self {
+        self.config.fee_policy = Some(fee_policy);
+        self
+    }
+
+    /// Processes the transactions of every input in timestamp order instead of file order. Every input is read into
+    /// memory before its first transaction is processed. Disabled by default.
+    pub fn order_by_timestamp(mut self, order_by_timestamp: bool) -> Self {
+        self.config.order_by_timestamp = order_by_timestamp;
+        self
+    }
+
+    /// Stops reading the input files after `max_rows` rows, counting skipped ones. The remaining rows and files are
+    /// not read and the returned [ProcessingSummary] is marked as truncated. No limit by default.
+    pub fn max_rows(mut self, max_rows: usize) -> Self {
+        self.config.max_rows = Some(max_rows);
+        self
+    }
+
+    /// Appends every transaction the processor handles to a write-ahead log at `path`, created if absent. See
+    /// [crate::wal]. No log is written by default.
+    pub fn wal(mut self, path: PathBuf) -> Self {
+        self.config.wal_path = Some(path);
+        self
+    }
+
+    /// Syncs the write-ahead log to disk every `records` records. Defaults to 100.
+    pub fn wal_sync_every(mut self, records: usize) -> Self {
+        self.config.wal_sync_every = records;
+        self
+    }
+
+    /// Writes a checkpoint of the state to `path` every `every` applied transactions, so an interrupted run can be
+    /// continued with [Gravenche::resume]. The checkpoint replaces the previous one atomically.
+    pub fn checkpoint(mut self, path: PathBuf, every: u64) -> Self {
+        self.config.checkpoint_path = Some(path);
+        self.config.checkpoint_every = Some(every);
+        self
+    }
+
+    /// Reads the input files through a memory map instead of a buffered reader, which saves copying the data of large
+    /// uncompressed files. The standard input is always read with a buffered reader. A file must not be modified while
+    /// it is mapped.
+    #[cfg(feature = "mmap")]
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.config.mmap = mmap;
+        self
+    }
+
+    /// Builds a [Gravenche] with the configured options.
+    pub fn build(self) -> Gravenche<T> {
         let clients = Arc::new(Mutex::new(HashMap::new()));
         let processed_transactions = Arc::new(Mutex::new(HashMap::new()));
 
         Gravenche {
-            csv_path,
+            csv_paths: self.csv_paths,
             clients,
             sender: None,
             processed_transactions,
-            num_transaction_allowed: transactions_allowed,
-            output_stream,
+            num_transaction_allowed: self.num_transaction_allowed,
+            output_stream: self.output_stream,
+            processor_handle: None,
+            config: self.config,
+            metrics: Arc::new(Metrics::default()),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            running: watch::channel(None).0,
+            stop_reading: watch::channel(false).0,
+            collected_fees: 0.0,
+            flagged_transactions: Arc::new(Mutex::new(Vec::new())),
+            progress: self.progress,
+            observer: self.observer,
+            formatter: self.formatter,
         }
     }
+}
 
-    // Read records from a CSV file and processes them.
-    async fn process_csv(&self) -> anyhow::Result<()> {
-        let file = File::open(&self.csv_path)?;
+impl<T: Write> Gravenche<T> {
+    pub fn new(csv_path: PathBuf, transactions_allowed: i32, output_stream: T) -> Self {
+        Self::builder(csv_path, output_stream)
+            .transactions_allowed(transactions_allowed)
+            .build()
+    }
 
-        // Use of BufReader makes reading efficient by reading large chuk, infrequent reads.
-        let buf_reader = BufReader::new(file);
+    /// Returns a [GravencheBuilder] to configure a [Gravenche] before building it.
+    pub fn builder(csv_path: PathBuf, output_stream: T) -> GravencheBuilder<T> {
+        GravencheBuilder {
+            csv_paths: vec![csv_path],
+            num_transaction_allowed: DEFAULT_TRANSACTIONS_ALLOWED,
+            output_stream,
+            config: Config::default(),
+            progress: None,
+            observer: None,
+            formatter: None,
+        }
+    }
 
-        // We re-use this to store record.
-        let mut record = csv::StringRecord::new();
+    // Opens the input to read transactions from. A path of "-" means the standard input. Unless the compression is
+    // configured, gzip compressed input is detected by its magic bytes and zstd compressed input by the `.zst`
+    // extension. Compressed input is decompressed transparently.
+    fn open_input(&self, csv_path: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+        let mut buf_reader: Box<dyn BufRead + Send> = if csv_path == Path::new(STDIN_PATH) {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            self.open_file(csv_path)?
+        };
 
-        let mut csv_reader = csv::Reader::from_reader(buf_reader);
+        let compression = match self.config.compression {
+            Some(compression) => compression,
+            #[cfg(feature = "zstd")]
+            None if csv_path.extension().is_some_and(|ext| ext == "zst") => Compression::Zstd,
+            None if buf_reader.fill_buf()?.starts_with(&GZIP_MAGIC_BYTES) => Compression::Gzip,
+            None => Compression::None,
+        };
 
-        let sender = self.sender.as_ref().expect("Unable to create a queue.");
-        // Using an existing variable to store a record prevents memory allocation every time.
-        while csv_reader.read_record(&mut record)? {
-            /*  Convert received data to appropriate type. If conversion fails, we move on. */
-            let trans_id: u32 = match &record[TRANSACTION_ID_INDEX].trim().parse() {
-                Ok(e) => *e,
-                Err(_) => continue,
-            };
+        match compression {
+            Compression::None => Ok(Box::new(buf_reader)),
+            Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(buf_reader))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(
+                buf_reader,
+            )?)),
+        }
+    }
 
-            // Extract Transaction Type
-            let _type = &record[TRANSACTION_TYPE_INDEX].trim().to_lowercase();
-            let _type = match TransactionType::from_str(_type) {
-                Ok(e) => e,
-                Err(_error) => continue,
-            };
+    // Opens an input file with a buffered reader, or maps it into memory if configured.
+    fn open_file(&self, path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+        let file = File::open(path)?;
+        #[cfg(feature = "mmap")]
+        if self.config.mmap {
+            // SAFETY: Mapping a file is unsafe because another process may change it while it is mapped. gravenche
+            // only reads the map, but a write to the file shows up in the parsed bytes and truncating it makes reads
+            // past the new end fault with SIGBUS, which aborts the run. Nothing here can rule that out, so callers
+            // must not modify a file while it is processed, as GravencheBuilder::mmap and the README require.
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            return Ok(Box::new(io::Cursor::new(map)));
+        }
+        Ok(Box::new(BufReader::new(file)))
+    }
 
-            // Extract Client ID
-            let client_id: u16 = match &record[CLIENT_ID_INDEX].trim().parse() {
-                Ok(e) => *e,
-                Err(_) => continue,
-            };
+    // Read records from all the inputs in order and processes them. The first `skip` transactions were handled
+    // before a resume and are not sent again.
+    async fn process_csv(
+        &self,
+        summary: &mut ProcessingSummary,
+        mut skip: u64,
+    ) -> anyhow::Result<()> {
+        for csv_path in self.csv_paths.iter() {
+            // Files after the row limit are not opened at all.
+            let max_rows = self
+                .config
+                .max_rows
+                .map(|max| max.saturating_sub(summary.rows_read));
+            if max_rows == Some(0) {
+                summary.truncated = true;
+                break;
+            }
 
-            // Extract amount
-            let amount: f32 = match &record[AMOUNT_INDEX].trim().parse() {
-                Ok(e) => *e,
-                Err(_) => 0.0,
+            let mut file_summary = FileSummary {
+                path: csv_path.clone(),
+                ..Default::default()
             };
+            self.process_file(
+                csv_path,
+                &mut file_summary,
+                summary.rows_read,
+                max_rows,
+                &mut skip,
+            )
+            .await?;
 
-            let transaction = Transaction::new(trans_id, client_id, _type, amount);
-            sender.send(Command::Transaction(transaction)).await?;
+            summary.rows_read += file_summary.rows_read;
+            summary.rows_skipped += file_summary.rows_skipped;
+            summary.rows_filtered += file_summary.rows_filtered;
+            summary.truncated |= file_summary.truncated;
+            summary.interrupted |= file_summary.interrupted;
+            summary.files.push(file_summary);
+            if summary.interrupted {
+                break;
+            }
         }
 
-        // Stop the Processor task
-        let _ = sender.send(Command::Exit).await;
+        if skip > 0 && !summary.truncated && !summary.interrupted {
+            return Err(GravencheError::Config(format!(
+                "The checkpoint is {} transactions past the end of the input, it belongs to other input files",
+                skip
+            ))
+            .into());
+        }
         Ok(())
     }
 
-    /// This method starts a transaction processor task and calls other required method(s) to start processing transaction.
-    pub async fn start(&mut self) -> anyhow::Result<()> {
-        self.start_transaction_processor().await;
-        self.process_csv().await?;
-        Ok(())
+    // Sends the transactions of an input file to the processor, at most `max_rows` of them. The first `skip`
+    // transactions are dropped. `rows_before` rows of earlier files were read, which counts for the progress.
+    async fn process_file(
+        &self,
+        path: &Path,
+        summary: &mut FileSummary,
+        rows_before: usize,
+        max_rows: Option<usize>,
+        skip: &mut u64,
+    ) -> anyhow::Result<()> {
+        let input = self.open_input(path)?;
+        match self.config.input_format {
+            InputFormat::Csv => {
+                // The limit is applied while reading, so skipped records count and a sorted input isn't buffered
+                // past it.
+                let source =
+                    CsvSource::with_format(input, self.config.delimiter, self.config.comment)
+                        .fail_fast(self.config.fail_fast)
+                        .max_rows(max_rows);
+                // Parsing in a task of its own only pays off with another core to run it on. The reader then runs
+                // ahead as far as the queue of the processor allows.
+                if !parallel_reading() {
+                    return self
+                        .pump_in_order(source, summary, rows_before, max_rows, skip)
+                        .await;
+                }
+                let read_ahead = self.num_transaction_allowed.max(0) as usize;
+                let source = BlockingCsvSource::spawn(source, read_ahead);
+                self.pump_in_order(source, summary, rows_before, max_rows, skip)
+                    .await
+            }
+            InputFormat::Ndjson => {
+                let source = NdjsonSource::new(input)
+                    .fail_fast(self.config.fail_fast)
+                    .max_rows(max_rows);
+                self.pump_in_order(source, summary, rows_before, max_rows, skip)
+                    .await
+            }
+        }
     }
 
-    // Start a tokio task that processes transactions.
-    async fn start_transaction_processor(&mut self) {
-        /*
-            Calculate channel buffer capacity. Each Transaction is of 12 bytes (calculated using std::mem::size_of).
-            Following is a formula for channel_capacity.
-            channel_capacity = (number of transaction allowed * 12) / 8.
-            Here (number of transaction allowed * 12) gives us total bytes required to store those transactions. Channel
-            constructore takes capacity in usize which has size 4 bytes on 32 bit system and 8 bytes on 64 bit system.
-            As 32 systems are becoming obsolte, we assume the machine is 64 bit system. That's why we divide by 8.
-        */
+    // Sends all the transactions of a source to the processor, in timestamp order if configured.
+    async fn pump_in_order<S: TransactionSource + Send>(
+        &self,
+        mut source: S,
+        summary: &mut FileSummary,
+        rows_before: usize,
+        max_rows: Option<usize>,
+        skip: &mut u64,
+    ) -> anyhow::Result<()> {
+        if self.config.order_by_timestamp {
+            let mut source = SortedSource::new(source);
+            self.pump(&mut source, summary, rows_before, max_rows, skip)
+                .await
+        } else {
+            self.pump(&mut source, summary, rows_before, max_rows, skip)
+                .await
+        }
+    }
+
+    // Sends the transactions of a source to the processor until it is exhausted, `max_rows` rows are read or the run
+    // is interrupted. The first `skip` transactions are read but not sent. Progress is reported counting `rows_before`
+    // rows of earlier inputs.
+    async fn pump<S: TransactionSource>(
+        &self,
+        source: &mut S,
+        summary: &mut FileSummary,
+        rows_before: usize,
+        max_rows: Option<usize>,
+        skip: &mut u64,
+    ) -> anyhow::Result<()> {
+        let sender = self.sender.as_ref().expect("Unable to create a queue.");
+        // Skipped rows are only known in bulk, so the report is due once the count reaches the next multiple.
+        let mut next_report = self
+            .progress
+            .as_ref()
+            .map(|progress| (rows_before as u64 / progress.every + 1) * progress.every);
+        let mut stop_reading = self.stop_reading.subscribe();
+        loop {
+            let transaction = tokio::select! {
+                biased;
+                _ = stop_reading.wait_for(|stop| *stop) => {
+                    summary.interrupted = true;
+                    break;
+                }
+                transaction = source.next() => transaction,
+            };
+            let Some(transaction) = transaction else {
+                break;
+            };
+            // A transaction past the limit shows that there was more to read. It is dropped. Sources limiting their
+            // records themselves never get here.
+            if max_rows.is_some_and(|max| summary.rows_read + source.rows_skipped() >= max) {
+                summary.truncated = true;
+                break;
+            }
+            summary.rows_read += 1;
+            if let (Some(progress), Some(next)) = (&self.progress, next_report.as_mut()) {
+                let rows = (rows_before + summary.rows_read + source.rows_skipped()) as u64;
+                if rows >= *next {
+                    (progress.callback)(rows);
+                    *next = (rows / progress.every + 1) * progress.every;
+                }
+            }
+            // Rows outside the time window are not part of the input resumed from a checkpoint either.
+            if self.config.outside_time_window(transaction.timestamp) {
+                summary.rows_filtered += 1;
+                continue;
+            }
+            if *skip > 0 {
+                *skip -= 1;
+                continue;
+            }
+            sender.send(Command::Transaction(transaction)).await?;
+        }
 
-        let channel_capacity = (self.num_transaction_allowed * 12) / 8;
+        summary.rows_skipped = source.rows_skipped();
+        summary.rows_read += summary.rows_skipped;
+        summary.truncated |= source.truncated();
+        match source.take_error() {
+            Some(error) => Err(error.context(format!(
+                "Reading input failed after {} rows were read",
+                summary.rows_read
+            ))),
+            None => Ok(()),
+        }
+    }
 
-        let clients = self.clients.clone();
-        let processed_transactions = self.processed_transactions.clone();
+    // Stops the processor once all the transactions are sent and waits for it to apply them.
+    async fn finish_processing(
+        &mut self,
+        mut summary: ProcessingSummary,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.metrics.record_skipped(summary.rows_skipped);
 
-        let (sender, receiver) = mpsc::channel::<Command>(channel_capacity as usize);
-        self.sender = Some(sender);
+        // Stop the Processor task
+        let sender = self.sender.as_ref().expect("Unable to create a queue.");
+        let _ = sender.send(Command::Exit).await;
+        self.running.send_replace(None);
 
-        // Start a tokio task for transaction processing
-        let processor_task = async move {
-            Self::process_transaction(clients, processed_transactions, receiver).await
-        };
-        let _processor_task_handle = tokio::spawn(processor_task);
+        // Wait for the processor to apply all the queued transactions.
+        if let Some(processor_handle) = self.processor_handle.take() {
+            let processor_summary = processor_handle.await??;
+            summary.transactions_applied = processor_summary.transactions_applied;
+            summary.clients_touched = processor_summary.clients_touched;
+            summary.fees_collected = processor_summary.fees_collected;
+            summary.transactions_flagged = processor_summary.transactions_flagged;
+            self.collected_fees += processor_summary.fees_collected;
+        }
+        Ok(summary)
     }
 
-    // A method that runs in tokio task and processes transactions.
-    async fn process_transaction(
-        clients: Clients,
-        processed_transactions: ProcessedTransactions,
-        mut rx: tokio::sync::mpsc::Receiver<Command>,
-    ) -> anyhow::Result<()> {
-        let clients = clients.clone();
-        let mut clients = clients.lock().await;
+    /// This method starts a transaction processor task and calls other required method(s) to start processing transaction.
+    /// Returns a [ProcessingSummary] describing the work done.
+    pub async fn start(&mut self) -> Result<ProcessingSummary, GravencheError> {
+        self.start_until(std::future::pending()).await
+    }
 
-        let processed_transactions = processed_transactions.clone();
-        let mut processed_transactions = processed_transactions.lock().await;
+    /// Processes the input files like [Gravenche::start] until `shutdown` completes, e.g. on Ctrl-C. Reading then
+    /// stops, the transactions already read are applied and the summary is marked
+    /// [interrupted](ProcessingSummary::interrupted). The clients reflect exactly the rows counted as read.
+    pub async fn start_until<F: Future<Output = ()>>(
+        &mut self,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.start_transaction_processor().await?;
+        let mut summary = ProcessingSummary::default();
+        let reading = self.process_csv_until(&mut summary, 0, shutdown).await;
+        self.finish_reading(summary, reading).await
+    }
 
-        while let Some(cmd) = rx.recv().await {
-            match cmd {
-                Command::Transaction(transaction) => {
-                    match transaction._type {
-                        TransactionType::Deposit => {
-                            // TODO: User HashMap's entry method here. Check clippy suggestion.
-                            let client_id = transaction.client_id;
-                            let amount = transaction.amount;
-                            let transaction_id = transaction.id;
-
-                            // Record a transaction. Required for dispute resolution.
-                            processed_transactions.insert(transaction_id, transaction);
-
-                            if clients.contains_key(&client_id) {
-                                let current_client = clients.get_mut(&client_id).unwrap();
-                                // We ignore the error here. So no need to bubble it up the call hierarchy.
-                                let _ = current_client.deposit(amount);
-                            } else {
-                                let new_client = Client::new(client_id, amount);
-                                clients.insert(client_id, new_client);
-                            }
-                        }
-                        TransactionType::Withdrawl => {
-                            // TODO: User HashMap's entry method here. Check clippy suggestion.
-                            let client_id = transaction.client_id;
-                            let withdrawl_amount = transaction.amount;
-                            let transaction_id = transaction.id;
-
-                            // Record a transaction. Required for dispute resolution.
-                            processed_transactions.insert(transaction_id, transaction);
-
-                            if clients.contains_key(&client_id) {
-                                let current_client = clients.get_mut(&client_id).unwrap();
-                                // Modify client data only if Client is not locked.
-                                let _ = current_client.withdraw(withdrawl_amount);
-                            } /* else {
-                                  // Log this transaction.
-                              } */
-                        }
-                        TransactionType::Dispute => {
-                            let client_id = transaction.client_id;
-                            let transaction_id = transaction.id;
-                            if processed_transactions.contains_key(&transaction_id) {
-                                let disputed_transaction =
-                                    processed_transactions.get_mut(&transaction_id).unwrap();
-                                let disputed_amount = disputed_transaction.amount;
-
-                                if clients.contains_key(&client_id) {
-                                    // Modify client data only if Client is not locked.
-                                    let current_client = clients.get_mut(&client_id).unwrap();
-                                    let _ = current_client.raise_dispute(disputed_amount);
-
-                                    // Flag the transaction as disputed
-                                    disputed_transaction.mark_disputed();
-                                } /* else {
-                                      // Log this transaction.
-                                  } */
-                            } /* else {
-                                  // Log this transaction.
-                              } */
-                        }
-                        TransactionType::Resolve => {
-                            let client_id = transaction.client_id;
-                            let transaction_id = transaction.id;
-                            if processed_transactions.contains_key(&transaction_id) {
-                                let disputed_transaction =
-                                    processed_transactions.get_mut(&transaction_id).unwrap();
-                                if disputed_transaction.is_disputed() {
-                                    let disputed_amount = disputed_transaction.amount;
-
-                                    if clients.contains_key(&client_id) {
-                                        let current_client = clients.get_mut(&client_id).unwrap();
-                                        // Modify client data only if Client is not locked.
-                                        let _ = current_client.resolve_dispute(disputed_amount);
-                                    } /* else {
-                                          // Log this transaction.
-                                      } */
-
-                                    // Flag the transaction as resolved
-                                    disputed_transaction.mark_resolved();
-                                } /* else {
-                                      // Log this transaction.
-                                  } */
-                            } /* else {
-                                  // Log this transaction.
-                              } */
-                        }
-                        TransactionType::Chargeback => {
-                            let client_id = transaction.client_id;
-                            let transaction_id = transaction.id;
-                            if processed_transactions.contains_key(&transaction_id) {
-                                let disputed_transaction =
-                                    processed_transactions.get_mut(&transaction_id).unwrap();
-                                if disputed_transaction.is_disputed() {
-                                    let disputed_amount = disputed_transaction.amount;
-
-                                    // Modify client data
-                                    if clients.contains_key(&client_id) {
-                                        let current_client = clients.get_mut(&client_id).unwrap();
-                                        let _ = current_client.chargeback(disputed_amount);
-                                    } /* else {
-                                          Log this transaction.
-                                      } */
-                                } /* else {
-                                        Log this transaction.
-                                  } */
-                            }
-                        }
-                    }
-                }
-                Command::Exit => {
-                    break;
+    // Stops the processor like finish_processing whether reading the input succeeded or not, so a failed run doesn't
+    // leave the processor waiting with the clients locked. Returns the reading error first.
+    async fn finish_reading(
+        &mut self,
+        summary: ProcessingSummary,
+        reading: anyhow::Result<()>,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        let finished = self.finish_processing(summary).await;
+        reading?;
+        finished
+    }
+
+    // Reads the input files like process_csv until `shutdown` completes. Reading then stops at the next transaction.
+    async fn process_csv_until<F: Future<Output = ()>>(
+        &self,
+        summary: &mut ProcessingSummary,
+        skip: u64,
+        shutdown: F,
+    ) -> anyhow::Result<()> {
+        self.stop_reading.send_replace(false);
+        let mut reading = std::pin::pin!(self.process_csv(summary, skip));
+        let mut shutdown = std::pin::pin!(shutdown);
+        let mut interrupted = false;
+        loop {
+            tokio::select! {
+                result = &mut reading => return result,
+                // Reading goes on until it notices, so the summary counts what was read.
+                _ = &mut shutdown, if !interrupted => {
+                    interrupted = true;
+                    self.stop_reading.send_replace(true);
                 }
             }
         }
-        Ok(())
     }
 
-    /// Show client data in tabular format.
-    pub async fn show_output(&mut self) -> anyhow::Result<()> {
-        let clients = self.clients.clone();
-        let clients = clients.lock().await;
+    /// Continues an interrupted run from the checkpoint configured with [GravencheBuilder::checkpoint]. The state is
+    /// restored and the transactions handled before the checkpoint are skipped in the input files, which must be the
+    /// same as in the interrupted run. Starts from the beginning like [Gravenche::start] if there is no checkpoint yet.
+    /// Returns a [ProcessingSummary] describing the work done, counting the skipped rows as read and the fees of the
+    /// checkpoint as collected.
+    pub async fn resume(&mut self) -> Result<ProcessingSummary, GravencheError> {
+        self.resume_until(std::future::pending()).await
+    }
 
-        writeln!(
-            self.output_stream,
-            "{0: >6} | {1: >10} | {2: >10} | {3: >10} | {4: >6}",
-            "client", "available", "held", "total", "locked"
-        )?;
+    /// Continues an interrupted run like [Gravenche::resume] until `shutdown` completes, which interrupts it like
+    /// [Gravenche::start_until].
+    pub async fn resume_until<F: Future<Output = ()>>(
+        &mut self,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        let Some(path) = self.config.checkpoint_path.clone() else {
+            return Err(GravencheError::Config(String::from(
+                "Resuming requires a checkpoint path",
+            )));
+        };
+        let (position, restored_fees) = match File::open(&path) {
+            Ok(file) => {
+                let state = read_state(file)?;
+                let Some(position) = state.position else {
+                    return Err(GravencheError::Config(format!(
+                        "{} is a snapshot, not a checkpoint",
+                        path.display()
+                    )));
+                };
+                let restored_fees = state.fees_collected;
+                self.restore(state).await;
+                (position, restored_fees)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (0, 0.0),
+            Err(error) => return Err(error.into()),
+        };
+        tracing::info!(position, "Resuming after {} transactions", position);
 
-        for client in clients.iter() {
-            writeln!(
-                self.output_stream,
-                "{0: >6} | {1: >10} | {2: >10} | {3: >10} | {4: >6}",
-                client.0, client.1.available, client.1.held, client.1.total, client.1.locked
-            )?;
-        }
+        self.start_transaction_processor_at(position).await?;
+        let mut summary = ProcessingSummary::default();
+        let reading = self
+            .process_csv_until(&mut summary, position, shutdown)
+            .await;
+        let mut summary = self.finish_reading(summary, reading).await?;
+        summary.fees_collected += restored_fees;
+        Ok(summary)
+    }
 
-        Ok(())
+    /// Processes all the transactions of a [TransactionSource] instead of the CSV files.
+    /// Returns a [ProcessingSummary] describing the work done.
+    pub async fn start_with_source<S: TransactionSource>(
+        &mut self,
+        mut source: S,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.start_transaction_processor().await?;
+        let mut source_summary = FileSummary::default();
+        let reading = self
+            .pump(&mut source, &mut source_summary, 0, None, &mut 0)
+            .await;
+
+        let summary = ProcessingSummary {
+            rows_read: source_summary.rows_read,
+            rows_skipped: source_summary.rows_skipped,
+            rows_filtered: source_summary.rows_filtered,
+            ..Default::default()
+        };
+        self.finish_reading(summary, reading).await
+    }
+
+    /// Rebuilds the clients and processed transactions from the write-ahead log at `path` instead of the input files.
+    /// The replay itself is not logged.
+    /// Returns a [ProcessingSummary] describing the work done.
+    pub async fn replay_wal(&mut self, path: &Path) -> Result<ProcessingSummary, GravencheError> {
+        let source = WalReader::open(path)?;
+        let wal_path = self.config.wal_path.take();
+        let result = self.start_with_source(source).await;
+        self.config.wal_path = wal_path;
+        result
+    }
+
+    /// Follows the first input file like `tail -f`, applying transactions as they are appended, until `shutdown`
+    /// completes. Only complete lines are processed and a truncated file is read again from the start. The client
+    /// table is shown every snapshot interval if one is configured. The file is read as it is, so a configured
+    /// compression other than [Compression::None] is refused.
+    /// Returns a [ProcessingSummary] describing the work done.
+    pub async fn follow<F: Future<Output = ()>>(
+        &mut self,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        let follow_path = &self.csv_paths[0];
+        if follow_path == Path::new(STDIN_PATH) {
+            return Err(GravencheError::Config(String::from(
+                "The standard input can't be followed",
+            )));
+        }
+        // Appended bytes can't be decompressed on their own, so a compressed file can't be followed.
+        if self
+            .config
+            .compression
+            .is_some_and(|compression| compression != Compression::None)
+        {
+            return Err(GravencheError::Config(String::from(
+                "Compressed input can't be followed",
+            )));
+        }
+        let mut source = FollowSource::new(
+            follow_path,
+            self.config.input_format,
+            self.config.delimiter,
+            self.config.comment,
+            self.config.poll_interval,
+        )?;
+        let mut file_summary = FileSummary {
+            path: follow_path.clone(),
+            ..Default::default()
+        };
+
+        self.start_transaction_processor().await?;
+        let sender = self.sender.clone().expect("Unable to create a queue.");
+
+        let mut snapshot_timer = self
+            .config
+            .snapshot_interval
+            .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+        let mut shutdown = std::pin::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = next_tick(&mut snapshot_timer) => self.show_snapshot(&sender).await?,
+                transaction = source.next() => match transaction {
+                    Some(transaction) => {
+                        file_summary.rows_read += 1;
+                        if self.config.outside_time_window(transaction.timestamp) {
+                            file_summary.rows_filtered += 1;
+                        } else {
+                            sender.send(Command::Transaction(transaction)).await?;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        file_summary.rows_skipped = source.rows_skipped();
+        file_summary.rows_read += file_summary.rows_skipped;
+        if let Some(error) = source.take_error() {
+            return Err(error
+                .context(format!(
+                    "Reading input failed after {} rows were read",
+                    file_summary.rows_read
+                ))
+                .into());
+        }
+
+        let summary = ProcessingSummary {
+            rows_read: file_summary.rows_read,
+            rows_skipped: file_summary.rows_skipped,
+            rows_filtered: file_summary.rows_filtered,
+            files: vec![file_summary],
+            ..Default::default()
+        };
+        self.finish_processing(summary).await
+    }
+
+    /// Runs as a service processing files dropped into the `incoming` directory of `spool_dir`, in name order, until
+    /// `shutdown` completes. Once all the transactions of a file are applied it is moved to `done`, or to `failed` if
+    /// reading it failed. A file being processed when `shutdown` completes is finished first.
+    /// Returns a [ProcessingSummary] describing the work done.
+    pub async fn spool<F: Future<Output = ()>>(
+        &mut self,
+        spool_dir: &Path,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        let incoming_dir = spool_dir.join("incoming");
+        let done_dir = spool_dir.join("done");
+        let failed_dir = spool_dir.join("failed");
+        for dir in [&incoming_dir, &done_dir, &failed_dir] {
+            std::fs::create_dir_all(dir)?;
+        }
+        let extension = match self.config.input_format {
+            InputFormat::Csv => "csv",
+            InputFormat::Ndjson => "ndjson",
+        };
+
+        self.start_transaction_processor().await?;
+        let sender = self.sender.clone().expect("Unable to create a queue.");
+
+        let mut summary = ProcessingSummary::default();
+        let mut shutdown = std::pin::pin!(shutdown);
+        'spool: loop {
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(&incoming_dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_file() && path.extension().is_some_and(|ext| ext == extension)
+                })
+                .collect();
+            paths.sort();
+
+            for path in paths {
+                let mut file_summary = FileSummary {
+                    path: path.clone(),
+                    ..Default::default()
+                };
+                let result = self
+                    .process_file(&path, &mut file_summary, summary.rows_read, None, &mut 0)
+                    .await;
+
+                // Move the file only once the processor has applied all of its transactions.
+                let (reply, flushed) = oneshot::channel();
+                sender.send(Command::Flush(reply)).await?;
+                flushed.await?;
+
+                let file_name = path.file_name().expect("Spooled files have a name.");
+                match result {
+                    Ok(()) => {
+                        tracing::info!(
+                            "{}: {} rows read, {} rows skipped",
+                            path.display(),
+                            file_summary.rows_read,
+                            file_summary.rows_skipped
+                        );
+                        std::fs::rename(&path, done_dir.join(file_name))?;
+                    }
+                    Err(error) => {
+                        tracing::error!("{}: {:#}", path.display(), error);
+                        std::fs::rename(&path, failed_dir.join(file_name))?;
+                    }
+                }
+
+                summary.rows_read += file_summary.rows_read;
+                summary.rows_skipped += file_summary.rows_skipped;
+                summary.rows_filtered += file_summary.rows_filtered;
+                summary.files.push(file_summary);
+
+                if has_completed(shutdown.as_mut()).await {
+                    break 'spool;
+                }
+            }
+
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = tokio::time::sleep(self.config.poll_interval) => {}
+            }
+        }
+
+        self.finish_processing(summary).await
+    }
+
+    /// Accepts TCP connections streaming transactions, as described in [crate::server], until `shutdown` completes.
+    /// All the connections update the same clients. Once `shutdown` completes no new connections are accepted and
+    /// the connected clients are served until they disconnect.
+    /// Returns a [ProcessingSummary] with statistics of every connection.
+    pub async fn serve<F: Future<Output = ()>>(
+        &mut self,
+        listener: TcpListener,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.start_transaction_processor().await?;
+        let sender = self.sender.clone().expect("Unable to create a queue.");
+
+        let mut summary = ProcessingSummary::default();
+        let mut connections = JoinSet::new();
+        let mut shutdown = std::pin::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(handle_connection(
+                            stream,
+                            sender.clone(),
+                            self.config.delimiter,
+                            self.config.strict,
+                            self.config.decimals,
+                        ));
+                    }
+                    Err(error) => tracing::warn!("Unable to accept a connection: {}", error),
+                },
+                Some(finished) = connections.join_next() => add_connection_summary(&mut summary, finished),
+            }
+        }
+
+        // Stop listening and wait for the connected clients.
+        drop(listener);
+        while let Some(finished) = connections.join_next().await {
+            add_connection_summary(&mut summary, finished);
+        }
+        self.finish_processing(summary).await
+    }
+
+    /// Serves the HTTP API described in [crate::http] until `shutdown` completes, then finishes the requests in
+    /// progress. Requires the `http` feature.
+    /// Returns a [ProcessingSummary] describing the work done.
+    #[cfg(feature = "http")]
+    pub async fn serve_http<F: Future<Output = ()> + Send + 'static>(
+        &mut self,
+        listener: TcpListener,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.start_transaction_processor().await?;
+        let sender = self.sender.clone().expect("Unable to create a queue.");
+
+        let state = crate::http::HttpState::new(sender, self.events.clone(), self.config.decimals);
+        axum::serve(listener, crate::http::router(state.clone()))
+            .with_graceful_shutdown(shutdown)
+            .await?;
+
+        let summary = ProcessingSummary {
+            rows_read: state.rows_read(),
+            ..Default::default()
+        };
+        self.finish_processing(summary).await
+    }
+
+    /// Consumes messages carrying one CSV record each, e.g. from [KafkaConsumer](crate::kafka::KafkaConsumer), until
+    /// `shutdown` completes. Offsets are committed only after the processor has applied the transactions of the
+    /// consumed messages, every [COMMIT_BATCH_SIZE](crate::kafka::COMMIT_BATCH_SIZE) messages or when no message
+    /// arrives for a poll interval. Malformed messages are logged, skipped and committed. Requires the `kafka` feature.
+    /// Returns a [ProcessingSummary] describing the work done.
+    #[cfg(feature = "kafka")]
+    pub async fn consume<C: crate::kafka::MessageConsumer, F: Future<Output = ()>>(
+        &mut self,
+        mut consumer: C,
+        shutdown: F,
+    ) -> Result<ProcessingSummary, GravencheError> {
+        self.start_transaction_processor().await?;
+        let sender = self.sender.clone().expect("Unable to create a queue.");
+
+        let mut summary = ProcessingSummary::default();
+        // Number of messages consumed since the last commit.
+        let mut uncommitted = 0;
+        let mut shutdown = std::pin::pin!(shutdown);
+        loop {
+            // Set when no message arrived for a poll interval.
+            let mut idle = false;
+            tokio::select! {
+                _ = &mut shutdown => break,
+                message = tokio::time::timeout(self.config.poll_interval, consumer.recv()) => match message {
+                    Ok(Ok(payload)) => {
+                        summary.rows_read += 1;
+                        uncommitted += 1;
+                        let line = String::from_utf8_lossy(&payload);
+                        match crate::source::parse_csv_line(&line, self.config.delimiter) {
+                            Ok(transaction) => sender.send(Command::Transaction(transaction)).await?,
+                            Err(error) => {
+                                tracing::error!("Skipping message {}: {}", line.trim(), error);
+                                summary.rows_skipped += 1;
+                            }
+                        }
+                    }
+                    // The consumer reconnects by itself, so errors are only reported.
+                    Ok(Err(error)) => tracing::warn!("Unable to consume a message: {}", error),
+                    Err(_) => idle = true,
+                },
+            }
+
+            // Nothing arriving for a while is a good moment to commit what was consumed.
+            if uncommitted >= crate::kafka::COMMIT_BATCH_SIZE || (idle && uncommitted > 0) {
+                Self::commit_consumed(&sender, &mut consumer).await?;
+                uncommitted = 0;
+            }
+        }
+
+        if uncommitted > 0 {
+            Self::commit_consumed(&sender, &mut consumer).await?;
+        }
+        self.finish_processing(summary).await
+    }
+
+    // Commits the consumed messages once the processor has applied all the transactions sent so far.
+    #[cfg(feature = "kafka")]
+    async fn commit_consumed<C: crate::kafka::MessageConsumer>(
+        sender: &mpsc::Sender<Command>,
+        consumer: &mut C,
+    ) -> anyhow::Result<()> {
+        let (reply, flushed) = oneshot::channel();
+        sender.send(Command::Flush(reply)).await?;
+        flushed.await?;
+        consumer.commit()
+    }
+
+    // Shows the client table reflecting all the transactions sent to the processor so far.
+    async fn show_snapshot(&mut self, sender: &mpsc::Sender<Command>) -> anyhow::Result<()> {
+        let (reply, snapshot) = oneshot::channel();
+        sender.send(Command::Snapshot(reply)).await?;
+        let snapshot = snapshot.await?;
+
+        write_table(
+            &mut self.output_stream,
+            snapshot.iter().filter(|client| self.config.shows(client)),
+            &self.config,
+        )?;
+        self.output_stream.flush()?;
+        Ok(())
+    }
+
+    /// Returns a copy of the clients. During a run it reflects all the transactions queued so far. Use a
+    /// [ClientsHandle] to take snapshots from another task while this one is processing.
+    pub async fn snapshot_clients(&self) -> HashMap<ClientId, Client> {
+        self.clients_handle().snapshot_clients().await
+    }
+
+    /// Returns a copy of the client with the ID, or `None` if no transaction created it. During a run it reflects all
+    /// the transactions queued so far. Use [ClientsHandle::client] to look clients up from another task while this one
+    /// is processing.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), gravenche::GravencheError> {
+    /// use gravenche::Gravenche;
+    ///
+    /// let mut gravenche = Gravenche::new("tests/data/disputes.csv".into(), 100, std::io::sink());
+    /// gravenche.start().await?;
+    /// let client = gravenche.client(1).await.expect("client 1 made deposits");
+    /// assert_eq!(client.total, 14.0);
+    /// assert!(gravenche.client(42).await.is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn client(&self, id: ClientId) -> Option<Client> {
+        self.clients_handle().client(id).await
+    }
+
+    /// Returns a copy of the processed transaction with the ID, e.g. to see its amount, client and dispute state, or
+    /// `None` if no transaction with the ID was processed. During a run it reflects all the transactions queued so far.
+    /// Use [ClientsHandle::transaction] to look transactions up from another task while this one is processing.
+    pub async fn transaction(&self, tx_id: u32) -> Option<Transaction> {
+        self.clients_handle().transaction(tx_id).await
+    }
+
+    /// Returns a copy of all the clients ordered by client ID, the data [show_output](Gravenche::show_output) reports.
+    /// During a run it reflects all the transactions queued so far.
+    pub async fn clients_snapshot(&self) -> Vec<Client> {
+        let mut clients: Vec<Client> = self.snapshot_clients().await.into_values().collect();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+
+    /// Returns a handle taking snapshots of the clients from another task.
+    pub fn clients_handle(&self) -> ClientsHandle {
+        ClientsHandle {
+            running: self.running.subscribe(),
+            clients: self.clients.clone(),
+            processed_transactions: self.processed_transactions.clone(),
+        }
+    }
+
+    /// Returns the total of the withdrawal fees charged by the runs finished so far, including the ones of a restored
+    /// snapshot or checkpoint. Charged fees are kept when the instance is [reset](Gravenche::reset).
+    pub fn collected_fees(&self) -> f32 {
+        self.collected_fees
+    }
+
+    /// Returns the IDs of the deposits and withdrawals flagged for reaching the
+    /// [flag threshold](GravencheBuilder::flag_threshold), in the order they were applied.
+    pub async fn flagged_transactions(&self) -> Vec<u32> {
+        self.flagged_transactions.lock().await.clone()
+    }
+
+    /// Returns the operational metrics of the transaction processor. They are updated while transactions are
+    /// processed.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Returns a receiver of an event for every transaction applied from now on, in the order they are applied, with
+    /// the balances of the client afterwards. Rejected transactions have no event. The processor never waits for
+    /// subscribers: a receiver that falls more than [EVENTS_CAPACITY] events behind misses the oldest ones, its next
+    /// `recv` returns [RecvError::Lagged](broadcast::error::RecvError::Lagged) with the number of missed events and the
+    /// one after continues with the oldest event still kept.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// Forgets all the clients and processed transactions so the same instance can process another independent
+    /// dataset. A processor still running is stopped without applying its queued transactions.
+    pub async fn reset(&mut self) {
+        if let Some(processor_handle) = self.processor_handle.take() {
+            processor_handle.abort();
+        }
+        self.sender = None;
+        self.running.send_replace(None);
+        self.clients.lock().await.clear();
+        self.processed_transactions.lock().await.clear();
+        self.flagged_transactions.lock().await.clear();
+    }
+
+    // Start a tokio task that processes transactions.
+    async fn start_transaction_processor(&mut self) -> anyhow::Result<()> {
+        self.start_transaction_processor_at(0).await
+    }
+
+    // Start a tokio task that processes transactions, the first of which is the transaction at `position` of the
+    // input.
+    async fn start_transaction_processor_at(&mut self, position: u64) -> anyhow::Result<()> {
+        /*
+            Calculate channel buffer capacity. Each Transaction is of 12 bytes (calculated using std::mem::size_of).
+            Following is a formula for channel_capacity.
+            channel_capacity = (number of transaction allowed * 12) / 8.
+            Here (number of transaction allowed * 12) gives us total bytes required to store those transactions. Channel
+            constructore takes capacity in usize which has size 4 bytes on 32 bit system and 8 bytes on 64 bit system.
+            As 32 systems are becoming obsolte, we assume the machine is 64 bit system. That's why we divide by 8.
+        */
+
+        // Channel needs a capacity of at least one.
+        let channel_capacity = ((self.num_transaction_allowed * 12) / 8).max(1);
+
+        let clients = self.clients.clone();
+        let processed_transactions = self.processed_transactions.clone();
+        let config = self.config.clone();
+        let metrics = self.metrics.clone();
+        let events = self.events.clone();
+        let records = ProcessorRecords {
+            wal: match &self.config.wal_path {
+                Some(path) => Some(WalWriter::open(path, self.config.wal_sync_every)?),
+                None => None,
+            },
+            checkpointer: match (&self.config.checkpoint_path, self.config.checkpoint_every) {
+                (Some(path), Some(every)) => Some(Checkpointer::new(
+                    path.clone(),
+                    every,
+                    position,
+                    self.collected_fees,
+                )),
+                _ => None,
+            },
+            position,
+            flagged_transactions: self.flagged_transactions.clone(),
+            observer: self.observer.clone(),
+        };
+
+        let (sender, receiver) = mpsc::channel::<Command>(channel_capacity as usize);
+        self.running.send_replace(Some(sender.clone()));
+        self.sender = Some(sender);
+
+        // Start a tokio task for transaction processing
+        let processor_task = async move {
+            Self::process_transaction(
+                clients,
+                processed_transactions,
+                config,
+                metrics,
+                events,
+                records,
+                receiver,
+            )
+            .await
+        };
+        self.processor_handle = Some(tokio::spawn(processor_task));
+        Ok(())
+    }
+
+    // A method that runs in tokio task and processes transactions. Clients are only reached through [ClientStore], the
+    // processor keeps them in whatever store `clients` holds.
+    async fn process_transaction<S: ClientStore>(
+        clients: Arc<Mutex<S>>,
+        processed_transactions: ProcessedTransactions,
+        config: Config,
+        metrics: Arc<Metrics>,
+        events: broadcast::Sender<ClientEvent>,
+        records: ProcessorRecords,
+        mut rx: tokio::sync::mpsc::Receiver<Command>,
+    ) -> anyhow::Result<ProcessingSummary> {
+        let mut summary = ProcessingSummary::default();
+        let mut touched_clients = HashSet::new();
+        let mut rules = RunRules::new(&config);
+        let ProcessorRecords {
+            mut wal,
+            mut checkpointer,
+            mut position,
+            flagged_transactions,
+            observer,
+        } = records;
+
+        let clients = clients.clone();
+        let mut clients = clients.lock().await;
+
+        let processed_transactions = processed_transactions.clone();
+        let mut processed_transactions = processed_transactions.lock().await;
+
+        while let Some(cmd) = rx.recv().await {
+            match cmd {
+                Command::Transaction(transaction) => {
+                    metrics.set_queue_depth(rx.len());
+                    let started = Instant::now();
+                    let client_id = transaction.client_id;
+                    let transaction_id = transaction.id;
+                    let _type = transaction._type;
+                    let counterparty = transaction.counterparty;
+                    position += 1;
+                    // Disputes can cover part of a transaction, their amount is the change of held funds.
+                    let held_before = clients.get(client_id).map_or(0.0, |client| client.held);
+                    let result = {
+                        let mut observer = observer.as_ref().map(SharedObserver::lock);
+                        rules.apply(
+                            &mut *clients,
+                            &mut processed_transactions,
+                            &config,
+                            &transaction,
+                            position,
+                            observer
+                                .as_deref_mut()
+                                .map(|observer| observer.as_mut() as &mut dyn TransactionObserver),
+                        )
+                    };
+                    // Set when the transaction modifies client data.
+                    let applied = result.is_ok();
+                    // Fee charged on the transaction, zero unless it is an applied withdrawal.
+                    let fee = result.as_ref().map_or(0.0, |outcome| outcome.fee);
+                    summary.fees_collected += fee;
+                    match result {
+                        Ok(_) if _type == TransactionType::Chargeback => {
+                            metrics.record_account_locked()
+                        }
+                        Ok(_) => {}
+                        Err(rejection) => metrics.record_rejection(rejection),
+                    }
+
+                    if let Ok(outcome) = &result {
+                        if outcome.flagged {
+                            flagged_transactions.lock().await.push(transaction_id);
+                            summary.transactions_flagged += 1;
+                        }
+                        summary.transactions_applied += 1;
+                        touched_clients.insert(client_id);
+                        touched_clients.extend(counterparty);
+
+                        // Build events only when somebody listens.
+                        if events.receiver_count() > 0 {
+                            if let Some(client) = clients.get(client_id) {
+                                // An unlock refers to no stored transaction and has no amount.
+                                let amount = match _type {
+                                    TransactionType::Unlock => 0.0,
+                                    TransactionType::Dispute
+                                    | TransactionType::Resolve
+                                    | TransactionType::Chargeback => {
+                                        (client.held - held_before).abs()
+                                    }
+                                    _ => processed_transactions
+                                        .get(&transaction_id)
+                                        .map_or(0.0, |applied_transaction| {
+                                            applied_transaction.amount
+                                        }),
+                                };
+                                // Lagging subscribers miss events instead of slowing processing down.
+                                let _ = events.send(ClientEvent::new(
+                                    &_type,
+                                    transaction_id,
+                                    amount,
+                                    client,
+                                ));
+                            }
+                        }
+                    }
+                    if let Some(wal) = wal.as_mut() {
+                        wal.append(transaction, applied).await?;
+                    }
+                    if let Some(checkpointer) = checkpointer.as_mut() {
+                        checkpointer
+                            .record(applied, fee, &*clients, &processed_transactions)
+                            .await?;
+                    }
+                    metrics.record_processed(&_type, applied, started.elapsed());
+                }
+                Command::Snapshot(reply) => {
+                    let mut snapshot: Vec<Client> = clients.iter().cloned().collect();
+                    snapshot.sort_by_key(|client| client.id);
+                    // Nobody to reply to if the requester went away.
+                    let _ = reply.send(snapshot);
+                }
+                Command::Client(id, reply) => {
+                    let _ = reply.send(clients.get(id).cloned());
+                }
+                Command::Lookup(tx_id, reply) => {
+                    let _ = reply.send(processed_transactions.get(&tx_id).cloned());
+                }
+                Command::Flush(reply) => {
+                    let _ = reply.send(());
+                }
+                Command::Exit => {
+                    break;
+                }
+            }
+        }
+
+        if let Some(wal) = wal.as_mut() {
+            wal.finish().await?;
+        }
+        if let Some(checkpointer) = checkpointer.as_mut() {
+            checkpointer.finish().await?;
+        }
+        summary.clients_touched = touched_clients.len();
+        Ok(summary)
+    }
+
+    /// Checks the invariants of every client, see [Client::check_invariants]. Returns the violations ordered by client
+    /// ID if there are any.
+    pub async fn verify(&self) -> Result<(), Vec<InvariantViolation>> {
+        let clients = self.clients.lock().await;
+        let mut violations: Vec<InvariantViolation> = clients
+            .values()
+            .filter_map(|client| client.check_invariants(self.config.decimals).err())
+            .collect();
+        if violations.is_empty() {
+            return Ok(());
+        }
+        violations.sort_by_key(|violation| violation.client_id);
+        Err(violations)
+    }
+
+    /// Returns clients whose account is locked, ordered by client ID.
+    pub async fn locked_clients(&self) -> Vec<Client> {
+        let clients = self.clients.lock().await;
+        let mut locked_clients: Vec<Client> = clients
+            .values()
+            .filter(|client| client.locked)
+            .cloned()
+            .collect();
+        locked_clients.sort_by_key(|client| client.id);
+        locked_clients
+    }
+
+    /// Returns `n` clients with the highest total balance in descending order. Clients with equal total
+    /// balance are ordered by client ID.
+    pub async fn top_clients(&self, n: usize) -> Vec<Client> {
+        let clients = self.clients.lock().await;
+        highest_totals(clients.values().cloned().collect(), n)
+    }
+
+    /// Writes all processed transactions as CSV, ordered by transaction ID. The disputed flag reflects the state at
+    /// the end of the run and the dispute history lists every dispute, resolve and chargeback, e.g.
+    /// `dispute@1041;resolve@2310`. If any transaction has a timestamp a `timestamp` column holds them in RFC 3339, and
+    /// if any has a memo a `memo` column holds them. With [extended output](GravencheBuilder::extended_output) a
+    /// `source_line` column holds the line of the input file each transaction was read from, empty if it wasn't read
+    /// from a file.
+    pub async fn export_transactions<W: Write>(&self, w: W) -> Result<(), GravencheError> {
+        let processed_transactions = self.processed_transactions.lock().await;
+        let mut transactions: Vec<&Transaction> = processed_transactions.values().collect();
+        transactions.sort_by_key(|transaction| transaction.id);
+        let timestamps = transactions
+            .iter()
+            .any(|transaction| transaction.timestamp.is_some());
+        let memos = transactions
+            .iter()
+            .any(|transaction| transaction.memo.is_some());
+
+        let mut csv_writer = csv::Writer::from_writer(w);
+        let mut header = vec![
+            "tx",
+            "client",
+            "type",
+            "amount",
+            "disputed",
+            "dispute_history",
+        ];
+        if timestamps {
+            header.push("timestamp");
+        }
+        if memos {
+            header.push("memo");
+        }
+        if self.config.extended_output {
+            header.push("source_line");
+        }
+        csv_writer.write_record(header)?;
+        for transaction in transactions {
+            let mut record = vec![
+                transaction.id.to_string(),
+                transaction.client_id.to_string(),
+                transaction._type.to_string(),
+                transaction.amount.to_string(),
+                transaction.is_disputed().to_string(),
+                format_dispute_events(transaction.dispute_events()),
+            ];
+            if timestamps {
+                record.push(transaction.timestamp.map_or_else(String::new, |timestamp| {
+                    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                }));
+            }
+            if memos {
+                record.push(transaction.memo.as_deref().unwrap_or_default().to_string());
+            }
+            if self.config.extended_output {
+                record.push(
+                    transaction
+                        .source_line
+                        .map_or_else(String::new, |line| line.to_string()),
+                );
+            }
+            csv_writer.write_record(record)?;
+        }
+        csv_writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns the disputes, resolves and chargebacks applied to a processed transaction, oldest first, or `None` if
+    /// no transaction with the ID was processed. Each event carries the position of the row in the input.
+    pub async fn transaction_history(&self, tx_id: u32) -> Option<Vec<DisputeEvent>> {
+        let processed_transactions = self.processed_transactions.lock().await;
+        processed_transactions
+            .get(&tx_id)
+            .map(|transaction| transaction.dispute_events().to_vec())
+    }
+
+    /// Saves the clients, the processed transactions and the [collected fees](Gravenche::collected_fees), so a later run
+    /// can continue from them with [Gravenche::load_snapshot].
+    pub async fn save_snapshot<W: Write>(&self, w: W) -> Result<(), GravencheError> {
+        let clients = self.clients.lock().await;
+        let processed_transactions = self.processed_transactions.lock().await;
+        write_state(
+            w,
+            &*clients,
+            &processed_transactions,
+            None,
+            self.collected_fees,
+        )
+    }
+
+    /// Replaces the clients, the processed transactions and the collected fees with a state saved by
+    /// [Gravenche::save_snapshot].
+    /// Transactions processed afterwards apply on top of it, e.g. locked accounts stay locked and a transaction
+    /// disputed before the snapshot can be resolved.
+    pub async fn load_snapshot<R: Read>(&mut self, r: R) -> Result<(), GravencheError> {
+        self.restore(read_state(r)?).await;
+        Ok(())
+    }
+
+    // Replaces the clients, the processed transactions and the collected fees with a saved state.
+    async fn restore(&mut self, snapshot: SavedState) {
+        self.collected_fees = snapshot.fees_collected;
+        let mut clients = self.clients.lock().await;
+        let mut processed_transactions = self.processed_transactions.lock().await;
+        *clients = snapshot
+            .clients
+            .into_iter()
+            .map(|client| (client.id, client))
+            .collect();
+        *processed_transactions = snapshot
+            .transactions
+            .into_iter()
+            .map(|transaction| (transaction.id, transaction))
+            .collect();
+    }
+
+    /// Writes the clients and the processed transactions into the `clients` and `transactions` tables of a new SQLite
+    /// database at `path`. An existing file is replaced if `replace` is set and refused otherwise. Requires the
+    /// `sqlite` feature.
+    #[cfg(feature = "sqlite")]
+    pub async fn export_sqlite(&self, path: &Path, replace: bool) -> Result<(), GravencheError> {
+        if path.exists() {
+            if !replace {
+                return Err(GravencheError::Config(format!(
+                    "{} already exists",
+                    path.display()
+                )));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        let clients = self.clients.lock().await;
+        let processed_transactions = self.processed_transactions.lock().await;
+        crate::output::sqlite::write_state(
+            path,
+            clients.values(),
+            processed_transactions.values(),
+        )?;
+        Ok(())
+    }
+
+    /// Writes all processed transactions to a Parquet file at `path`, ordered by transaction ID. Requires the `parquet`
+    /// feature.
+    #[cfg(feature = "parquet")]
+    pub async fn export_transactions_parquet(&self, path: &Path) -> Result<(), GravencheError> {
+        let processed_transactions = self.processed_transactions.lock().await;
+        crate::output::parquet::write_transactions(
+            path,
+            processed_transactions.values(),
+            self.config.decimals,
+        )?;
+        Ok(())
+    }
+
+    /// Returns transactions still in dispute at the end of the run, ordered by transaction ID.
+    pub async fn open_disputes(&self) -> Vec<Transaction> {
+        let processed_transactions = self.processed_transactions.lock().await;
+        let mut open_disputes: Vec<Transaction> = processed_transactions
+            .values()
+            .filter(|transaction| transaction.is_disputed())
+            .cloned()
+            .collect();
+        open_disputes.sort_by_key(|transaction| transaction.id);
+        open_disputes
+    }
+
+    /// Returns `(client_id, transaction_id)` pairs of transactions still in dispute at the end of the run, ordered by
+    /// transaction ID.
+    pub async fn open_dispute_ids(&self) -> Vec<(ClientId, u32)> {
+        self.open_disputes()
+            .await
+            .iter()
+            .map(|transaction| (transaction.client_id, transaction.id))
+            .collect()
+    }
+
+    /// Writes transactions still in dispute as CSV with their disputed amount, along with the amount currently held for
+    /// the owning client. If any of them has a memo a `memo` column holds them.
+    pub async fn export_disputes<W: Write>(&self, w: W) -> Result<(), GravencheError> {
+        let open_disputes = self.open_disputes().await;
+        let clients = self.clients.lock().await;
+        let memos = open_disputes
+            .iter()
+            .any(|transaction| transaction.memo.is_some());
+
+        let mut csv_writer = csv::Writer::from_writer(w);
+        let mut header = vec!["tx", "client", "amount", "held"];
+        if memos {
+            header.push("memo");
+        }
+        csv_writer.write_record(header)?;
+        for transaction in open_disputes {
+            let held = clients
+                .get(&transaction.client_id)
+                .map_or(0.0, |client| client.held);
+            let mut record = vec![
+                transaction.id.to_string(),
+                transaction.client_id.to_string(),
+                transaction.disputed_amount.to_string(),
+                held.to_string(),
+            ];
+            if memos {
+                record.push(transaction.memo.as_deref().unwrap_or_default().to_string());
+            }
+            csv_writer.write_record(record)?;
+        }
+        csv_writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Exports the flagged transactions in CSV format to `w`, in the order they were applied. During a run it reflects
+    /// all the transactions queued so far.
+    pub async fn export_flagged<W: Write>(&self, w: W) -> Result<(), GravencheError> {
+        // The IDs are copied so their lock is released before the transactions are looked up. The processor takes it
+        // for every flagged transaction while it holds the processed transactions.
+        let flagged_ids = self.flagged_transactions().await;
+
+        let mut csv_writer = csv::Writer::from_writer(w);
+        csv_writer.write_record(["tx", "client", "type", "amount"])?;
+        for id in flagged_ids {
+            let Some(transaction) = self.transaction(id).await else {
+                continue;
+            };
+            csv_writer.write_record([
+                transaction.id.to_string(),
+                transaction.client_id.to_string(),
+                transaction._type.to_string(),
+                transaction.amount.to_string(),
+            ])?;
+        }
+        csv_writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns the clients [show_output](Gravenche::show_output) shows, only the locked ones if
+    /// [only_locked](GravencheBuilder::only_locked) is set, and totals over all the clients, shown or not. During a run
+    /// it reflects all the transactions queued so far.
+    pub async fn report(&self) -> Report {
+        let mut report = Report::new(self.clients_snapshot().await, self.config.decimals);
+        // Skip unlocked clients if only locked clients are requested, the totals still cover them.
+        report.clients.retain(|client| self.config.shows(client));
+        report
+    }
+
+    /// Show client data in the configured output format, or with the [formatter](GravencheBuilder::formatter) if one
+    /// is set.
+    pub async fn show_output(&mut self) -> Result<(), GravencheError> {
+        let report = self.report().await;
+        self.write_clients(&report.clients)
+    }
+
+    /// Show `n` clients with the highest total balance, highest first, in the configured output format or with the
+    /// [formatter](GravencheBuilder::formatter) if one is set. If [only_locked](GravencheBuilder::only_locked) is set
+    /// only locked clients are ranked. Only the clients are written, see [report](Gravenche::report) for the totals of
+    /// all the clients.
+    pub async fn show_top_clients(&mut self, n: usize) -> Result<(), GravencheError> {
+        let report = self.report().await;
+        let top_clients = highest_totals(report.clients, n);
+        self.write_clients(&top_clients)
+    }
+
+    // Writes clients to the output stream with the formatter, or in the configured output format if none is set.
+    fn write_clients(&mut self, clients: &[Client]) -> Result<(), GravencheError> {
+        let mut configured = ConfiguredFormatter(&self.config);
+        let formatter: &mut dyn OutputFormatter = match self.formatter.as_mut() {
+            Some(formatter) => formatter.as_mut(),
+            None => &mut configured,
+        };
+        formatter.write(clients, &mut self.output_stream)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ledger::apply_transaction;
+    use crate::metrics::{Outcome, Rejection};
+    use crate::source::READ_BATCH;
+    use crate::types::client::{
+        amounts_equal, snapshots, ClientSnapshot, ClientStore, CurrencyBalance,
+    };
+    use crate::types::config::DEFAULT_DECIMALS;
+    use crate::types::transaction::{DisputeState, JsonTransaction};
+
+    // Returns a path named `name` in the temporary directory which is unique to the test process, so runs of the tests
+    // side by side don't share files.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gravenche_test_{}_{}", std::process::id(), name))
+    }
+
+    // Only clients locked by a chargeback are reported when only_locked is set.
+    #[tokio::test]
+    async fn test_only_locked() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/locked_clients.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .only_locked(true)
+            .build();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let locked_ids: Vec<ClientId> = gravenche
+            .locked_clients()
+            .await
+            .iter()
+            .map(|client| client.id)
+            .collect();
+        assert_eq!(locked_ids, vec![2, 4]);
+
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        let mut reported_ids: Vec<&str> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split('|').next().unwrap().trim())
+            .collect();
+        reported_ids.sort();
+        assert_eq!(reported_ids, vec!["2", "4"]);
+    }
+
+    // Client IDs above 65535 are processed like any other and shown in full.
+    #[tokio::test]
+    async fn test_large_client_ids() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/large_client_ids.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_skipped, 0);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1_000_000].total, 8.5);
+        assert_eq!(clients[&70_000].total, 4.0);
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        assert!(output.contains("1000000 |        8.5 |"));
+    }
+
+    // Only the selected columns are shown, in the selected order.
+    #[tokio::test]
+    async fn test_columns() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .columns(vec![Column::Client, Column::Total])
+            .build();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines[1..].sort();
+        assert_eq!(
+            lines,
+            vec![
+                "client |      total",
+                "     1 |         14",
+                "     2 |        2.5"
+            ]
+        );
+    }
+
+    // Held funds are shown either separately from the available funds or as a negative amount included in them.
+    #[tokio::test]
+    async fn test_signed_held() {
+        // Prepare
+        let transactions = || {
+            VecSource(
+                vec![
+                    Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                    Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+                    Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+                ]
+                .into_iter(),
+            )
+        };
+        let mut separate = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let mut signed = Gravenche::builder(PathBuf::new(), Vec::new())
+            .signed_held(true)
+            .build();
+
+        // Execute
+        separate.start_with_source(transactions()).await.unwrap();
+        separate.show_output().await.unwrap();
+        signed.start_with_source(transactions()).await.unwrap();
+        signed.show_output().await.unwrap();
+
+        // Assert
+        let separate = String::from_utf8(separate.output_stream).unwrap();
+        let signed = String::from_utf8(signed.output_stream).unwrap();
+        assert_eq!(
+            separate.lines().nth(1).unwrap(),
+            "     1 |         10 |          5 |         15 |  false"
+        );
+        assert_eq!(
+            signed.lines().nth(1).unwrap(),
+            "     1 |         15 |         -5 |         15 |  false"
+        );
+    }
+
+    // Amounts in the table are grouped by thousands with all the decimal places shown when a locale is set, and the
+    // amount columns widen to the longest amount so the rows stay aligned.
+    #[tokio::test]
+    async fn test_locale() {
+        // Prepare
+        let transactions = || {
+            VecSource(
+                vec![
+                    Transaction::new(1, 1, TransactionType::Deposit, 1234567.5),
+                    Transaction::new(2, 1, TransactionType::Deposit, 1000.0),
+                    Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+                    Transaction::new(3, 2, TransactionType::Deposit, 12.25),
+                ]
+                .into_iter(),
+            )
+        };
+        let mut plain = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let mut en = Gravenche::builder(PathBuf::new(), Vec::new())
+            .locale(NumberLocale::En)
+            .build();
+        let mut de = Gravenche::builder(PathBuf::new(), Vec::new())
+            .locale(NumberLocale::De)
+            .build();
+
+        // Execute
+        for gravenche in [&mut plain, &mut en, &mut de] {
+            gravenche.start_with_source(transactions()).await.unwrap();
+            gravenche.show_output().await.unwrap();
+        }
+
+        // Assert
+        let plain = String::from_utf8(plain.output_stream).unwrap();
+        let en = String::from_utf8(en.output_stream).unwrap();
+        let de = String::from_utf8(de.output_stream).unwrap();
+        assert_eq!(
+            plain.lines().skip(1).collect::<Vec<_>>(),
+            vec![
+                "     1 |  1234567.5 |       1000 |  1235567.5 |  false",
+                "     2 |      12.25 |          0 |      12.25 |  false"
+            ]
+        );
+        assert_eq!(
+            en.lines().collect::<Vec<_>>(),
+            vec![
+                "client |      available |       held |          total | locked",
+                "     1 | 1,234,567.5000 | 1,000.0000 | 1,235,567.5000 |  false",
+                "     2 |        12.2500 |     0.0000 |        12.2500 |  false"
+            ]
+        );
+        assert_eq!(
+            de.lines().nth(1).unwrap(),
+            "     1 | 1.234.567,5000 | 1.000,0000 | 1.235.567,5000 |  false"
+        );
+    }
+
+    // Disputes and chargebacks are counted per client across transactions, and shown only in the extended output.
+    #[tokio::test]
+    async fn test_dispute_counts() {
+        // Prepare
+        let transactions = || {
+            VecSource(
+                vec![
+                    Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                    Transaction::new(2, 1, TransactionType::Deposit, 20.0),
+                    Transaction::new(3, 1, TransactionType::Deposit, 30.0),
+                    Transaction::new(4, 2, TransactionType::Deposit, 5.0),
+                    Transaction::new(5, 2, TransactionType::Withdrawal, 4.0),
+                    Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+                    Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+                    Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+                    Transaction::new(3, 1, TransactionType::Dispute, 5.0),
+                    Transaction::new(2, 1, TransactionType::Chargeback, 0.0),
+                    Transaction::new(4, 2, TransactionType::Dispute, 0.0),
+                ]
+                .into_iter(),
+            )
+        };
+        let mut extended = Gravenche::builder(PathBuf::new(), Vec::new())
+            .extended_output(true)
+            .build();
+        let mut plain = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        extended.start_with_source(transactions()).await.unwrap();
+        extended.show_top_clients(1).await.unwrap();
+        plain.start_with_source(transactions()).await.unwrap();
+        plain.show_top_clients(1).await.unwrap();
+
+        // Assert
+        let clients = extended.snapshot_clients().await;
+        assert_eq!(
+            (clients[&1].dispute_count, clients[&1].chargeback_count),
+            (3, 1)
+        );
+        assert_eq!(
+            (clients[&2].dispute_count, clients[&2].chargeback_count),
+            (0, 0)
+        );
+        let output = String::from_utf8(extended.output_stream).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with(&format!(
+            " | {: >8} | {: >11} | {: >12}",
+            "disputes", "chargebacks", "was_negative"
+        )));
+        assert!(lines[1].ends_with(&format!("true | {: >8} | {: >11} | {: >12}", 3, 1, false)));
+        let output = String::from_utf8(plain.output_stream).unwrap();
+        assert!(output.lines().next().unwrap().ends_with("locked"));
+        assert!(output.lines().nth(1).unwrap().ends_with("true"));
+    }
+
+    // Every client of a clean run satisfies the invariants, while a client broken afterwards is reported.
+    #[tokio::test]
+    async fn test_verify() {
+        // Prepare
+        let csv_path = PathBuf::from("test_data.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+        gravenche.start().await.unwrap();
+
+        // Execute
+        let result = gravenche.verify().await;
+        gravenche.clients.lock().await.get_mut(&1).unwrap().total += 1.0;
+        let broken_result = gravenche.verify().await;
+
+        // Assert
+        assert!(result.is_ok());
+        let violations = broken_result.unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].client_id, 1);
+    }
+
+    // A store wrapping the default one which counts how often the state machine reaches for clients to modify.
+    #[derive(Default)]
+    struct CountingStore {
+        clients: HashMap<ClientId, Client>,
+        modified: usize,
+        created: usize,
+    }
+
+    impl ClientStore for CountingStore {
+        fn get(&self, id: ClientId) -> Option<&Client> {
+            self.clients.get(&id)
+        }
+
+        fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+            self.modified += 1;
+            self.clients.get_mut(&id)
+        }
+
+        fn get_or_create(&mut self, id: ClientId) -> &mut Client {
+            self.created += 1;
+            ClientStore::get_or_create(&mut self.clients, id)
+        }
+
+        fn iter(&self) -> impl Iterator<Item = &Client> {
+            self.clients.values()
+        }
+    }
+
+    // Transactions are applied to any client store the same way as to the default one.
+    #[test]
+    fn test_client_store() {
+        // Prepare
+        let transactions = [
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 4.0),
+            Transaction::new(4, 3, TransactionType::Withdrawal, 1.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 2.0),
+        ];
+        let config = Config::default();
+        let mut store = CountingStore::default();
+        let mut store_transactions = HashMap::new();
+        let mut clients = HashMap::new();
+        let mut processed_transactions = HashMap::new();
+
+        // Execute
+        let mut results = Vec::new();
+        for (position, transaction) in (1..).zip(transactions) {
+            let stored = apply_transaction(
+                &mut store,
+                &mut store_transactions,
+                &config,
+                transaction.clone(),
+                position,
+            );
+            let default = apply_transaction(
+                &mut clients,
+                &mut processed_transactions,
+                &config,
+                transaction,
+                position,
+            );
+            results.push((stored, default));
+        }
+
+        // Assert
+        for (stored, default) in results {
+            assert_eq!(stored, default);
+        }
+        assert_eq!(store.created, 2);
+        assert_eq!(store.modified, 3);
+        assert_eq!(store.iter().count(), 2);
+        for client in store.iter() {
+            assert_eq!(client, &clients[&client.id]);
+        }
+        assert_eq!(store.clients[&1].held, 2.0);
+    }
+
+    // The processor task runs on any client store and answers requests from it.
+    #[tokio::test]
+    async fn test_processor_client_store() {
+        // Prepare
+        let store = Arc::new(Mutex::new(CountingStore::default()));
+        let records = ProcessorRecords {
+            wal: None,
+            checkpointer: None,
+            position: 0,
+            flagged_transactions: Arc::default(),
+            observer: None,
+        };
+        let (sender, receiver) = mpsc::channel(10);
+        let processor = tokio::spawn(Gravenche::<Vec<u8>>::process_transaction(
+            store.clone(),
+            Arc::default(),
+            Config::default(),
+            Arc::default(),
+            broadcast::channel(EVENTS_CAPACITY).0,
+            records,
+            receiver,
+        ));
+
+        // Execute
+        for transaction in [
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 4.0),
+        ] {
+            sender
+                .send(Command::Transaction(transaction))
+                .await
+                .unwrap();
+        }
+        let (reply, snapshot) = oneshot::channel();
+        sender.send(Command::Snapshot(reply)).await.unwrap();
+        let snapshot = snapshot.await.unwrap();
+        let (reply, client) = oneshot::channel();
+        sender.send(Command::Client(1, reply)).await.unwrap();
+        let client = client.await.unwrap();
+        sender.send(Command::Exit).await.unwrap();
+        let summary = processor.await.unwrap().unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 3);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(client.unwrap().available, 6.0);
+        let store = store.lock().await;
+        assert_eq!(store.created, 2);
+        assert_eq!(store.modified, 1);
+    }
+
+    // The summary returned from start reflects the work done on a known fixture.
+    #[tokio::test]
+    async fn test_processing_summary() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/summary.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            summary,
+            ProcessingSummary {
+                rows_read: 10,
+                transactions_applied: 6,
+                rows_skipped: 2,
+                rows_filtered: 0,
+                clients_touched: 2,
+                fees_collected: 0.0,
+                transactions_flagged: 0,
+                files: vec![FileSummary {
+                    path: PathBuf::from("tests/data/summary.csv"),
+                    rows_read: 10,
+                    rows_skipped: 2,
+                    rows_filtered: 0,
+                    truncated: false,
+                    interrupted: false,
+                }],
+                truncated: false,
+                interrupted: false,
+            }
+        );
+    }
+
+    // A deposit with an unparseable amount is skipped while a dispute without an amount is applied.
+    #[tokio::test]
+    async fn test_invalid_amount() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/invalid_amount.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_skipped, 1);
+        assert_eq!(summary.transactions_applied, 4);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 10.0);
+        assert_eq!(clients[&2].held, 5.0);
+        assert_eq!(clients[&2].available, 1.0);
+    }
+
+    // With fail fast, processing stops at the deposit with an unparseable amount and the error names its line.
+    #[tokio::test]
+    async fn test_fail_fast() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/invalid_amount.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .fail_fast(true)
+            .build();
+
+        // Execute
+        let result = gravenche.start().await;
+
+        // Assert
+        match result {
+            Err(GravencheError::BadRow { line, reason }) => {
+                assert_eq!(line, 3);
+                assert!(!reason.is_empty());
+            }
+            other => panic!("Expected a bad row, got {:?}", other),
+        }
+    }
+
+    // A file which can't be opened fails the run, but the processor still applies the rows of the files before it and
+    // stops, releasing the clients.
+    #[tokio::test]
+    async fn test_unreadable_second_file() {
+        // Prepare
+        let missing_path = std::env::temp_dir().join("gravenche_test_unreadable_second_file.csv");
+        let _ = std::fs::remove_file(&missing_path);
+        let mut gravenche =
+            Gravenche::builder(PathBuf::from("tests/data/disputes.csv"), Vec::new())
+                .add_source(missing_path)
+                .build();
+
+        // Execute
+        let result = gravenche.start().await;
+
+        // Assert
+        assert!(result.is_err());
+        assert!(gravenche.processor_handle.is_none());
+        let clients = tokio::time::timeout(Duration::from_secs(5), gravenche.clients.lock())
+            .await
+            .expect("The processor still holds the clients");
+        let mut ids: Vec<ClientId> = clients.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    // Progress is reported every two rows, counting the rows of both files.
+    #[tokio::test]
+    async fn test_progress() {
+        // Prepare
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = reports.clone();
+        let mut gravenche =
+            Gravenche::builder(PathBuf::from("tests/data/disputes_1.csv"), Vec::new())
+                .add_source(PathBuf::from("tests/data/disputes_2.csv"))
+                .progress(2, move |rows| reported.lock().unwrap().push(rows))
+                .build();
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 9);
+        assert_eq!(*reports.lock().unwrap(), vec![2, 4, 6, 8]);
+    }
+
+    // Transaction ID and the total of the client after it was applied, or why it was rejected.
+    type ObservedEvent = (u32, Result<f32, Rejection>);
+
+    // Records the outcome of every transaction it is told about.
+    struct RecordingObserver {
+        events: Arc<std::sync::Mutex<Vec<ObservedEvent>>>,
+    }
+
+    impl TransactionObserver for RecordingObserver {
+        fn on_applied(&mut self, transaction: &Transaction, client: &Client) {
+            let mut events = self.events.lock().unwrap();
+            events.push((transaction.id, Ok(client.total)));
+        }
+
+        fn on_rejected(&mut self, transaction: &Transaction, rejection: Rejection) {
+            let mut events = self.events.lock().unwrap();
+            events.push((transaction.id, Err(rejection)));
+        }
+    }
+
+    // The observer is told about every applied and rejected transaction in order, with the client after the change.
+    #[tokio::test]
+    async fn test_observer() {
+        // Prepare
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observer = RecordingObserver {
+            events: events.clone(),
+        };
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .observer(Box::new(observer))
+            .build();
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 20.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(9, 2, TransactionType::Dispute, 0.0),
+        ];
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (1, Ok(10.0)),
+                (2, Ok(15.0)),
+                (3, Err(Rejection::InsufficientFunds)),
+                (1, Ok(15.0)),
+                (1, Ok(5.0)),
+                (4, Err(Rejection::AccountLocked)),
+                (9, Err(Rejection::UnknownTransaction)),
+            ]
+        );
+    }
+
+    // Panics of the observer don't stop the processor from applying later transactions.
+    #[tokio::test]
+    async fn test_observer_panics() {
+        // Prepare
+        struct PanickingObserver;
+        impl TransactionObserver for PanickingObserver {
+            fn on_applied(&mut self, transaction: &Transaction, _client: &Client) {
+                if transaction.id == 1 {
+                    panic!("observer failed");
+                }
+            }
+        }
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .observer(Box::new(PanickingObserver))
+            .build();
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+        ];
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 2);
+        assert_eq!(gravenche.client(1).await.unwrap().total, 15.0);
+    }
+
+    // A BOM-prefixed file with tab and non-breaking space padded cells is processed completely.
+    #[tokio::test]
+    async fn test_bom_and_padded_fields() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/bom.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_skipped, 0);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].available, 7.5);
+        assert_eq!(clients[&2].available, 3.0);
+    }
+
+    // Clients are ordered by total balance and ties are broken by client ID.
+    #[tokio::test]
+    async fn test_top_clients() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/top_clients.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+        let top_clients = gravenche.top_clients(3).await;
+
+        // Assert
+        let top_ids: Vec<ClientId> = top_clients.iter().map(|client| client.id).collect();
+        assert_eq!(top_ids, vec![2, 4, 3]);
+    }
+
+    // Asking for more clients than available returns all the clients.
+    #[tokio::test]
+    async fn test_top_clients_more_than_available() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/top_clients.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+        let top_clients = gravenche.top_clients(20).await;
+
+        // Assert
+        let top_ids: Vec<ClientId> = top_clients.iter().map(|client| client.id).collect();
+        assert_eq!(top_ids, vec![2, 4, 3, 1, 5]);
+    }
+
+    // Asking for zero clients returns nothing and shows only the header.
+    #[tokio::test]
+    async fn test_top_clients_zero() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/top_clients.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_top_clients(0).await.unwrap();
+
+        // Assert
+        assert!(gravenche.top_clients(0).await.is_empty());
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        assert_eq!(output.lines().count(), 1);
+    }
+
+    // With only_locked the top clients are ranked among the locked ones, and only the table is written.
+    #[tokio::test]
+    async fn test_top_clients_only_locked() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/locked_clients.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .only_locked(true)
+            .build();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_top_clients(1).await.unwrap();
+
+        // Assert
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].trim_start().starts_with("client |"));
+        assert!(lines[1].trim_start().starts_with("2 |"));
+    }
+
+    // Exported transactions are ordered by ID and carry the end of run dispute flag and the dispute history.
+    #[tokio::test]
+    async fn test_export_transactions() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+        let mut exported = Vec::new();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.export_transactions(&mut exported).await.unwrap();
+
+        // Assert
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(
+            exported,
+            "tx,client,type,amount,disputed,dispute_history\n\
+             1,1,deposit,10,false,dispute@6;resolve@7\n\
+             2,1,deposit,5,false,\n\
+             3,2,deposit,20,false,dispute@8;chargeback@9\n\
+             4,2,deposit,2.5,false,\n\
+             5,1,withdrawal,1,false,\n"
+        );
+    }
+
+    // With extended output, exported transactions carry the line of the fixture they were read from.
+    #[tokio::test]
+    async fn test_export_transactions_source_lines() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .extended_output(true)
+            .build();
+        let mut exported = Vec::new();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.export_transactions(&mut exported).await.unwrap();
+
+        // Assert
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(
+            exported,
+            "tx,client,type,amount,disputed,dispute_history,source_line\n\
+             1,1,deposit,10,false,dispute@6;resolve@7,2\n\
+             2,1,deposit,5,false,,3\n\
+             3,2,deposit,20,false,dispute@8;chargeback@9,4\n\
+             4,2,deposit,2.5,false,,5\n\
+             5,1,withdrawal,1,false,,6\n"
+        );
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        assert_eq!(processed_transactions[&5].source_line, Some(6));
+    }
+
+    // Memos read from a description column are kept for the export of transactions and open disputes only.
+    #[tokio::test]
+    async fn test_export_memos() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/memos.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+        let mut exported = Vec::new();
+        let mut exported_disputes = Vec::new();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.export_transactions(&mut exported).await.unwrap();
+        gravenche
+            .export_disputes(&mut exported_disputes)
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 15.0);
+        assert_eq!(clients[&1].held, 10.0);
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "tx,client,type,amount,disputed,dispute_history,memo\n\
+             1,1,deposit,10,true,dispute@3,\"Salary, March\"\n\
+             2,1,deposit,5,false,,\n"
+        );
+        assert_eq!(
+            String::from_utf8(exported_disputes).unwrap(),
+            "tx,client,amount,held,memo\n1,1,10,10,\"Salary, March\"\n"
+        );
+    }
+
+    // Only disputes which are neither resolved nor charged back are open.
+    #[tokio::test]
+    async fn test_open_disputes() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/open_disputes.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+        let mut exported = Vec::new();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.export_disputes(&mut exported).await.unwrap();
+
+        // Assert
+        let open_ids: Vec<u32> = gravenche
+            .open_disputes()
+            .await
+            .iter()
+            .map(|transaction| transaction.id)
+            .collect();
+        assert_eq!(open_ids, vec![5]);
+
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(exported, "tx,client,amount,held\n5,3,7,7\n");
+    }
+
+    // A deposit creates an unknown client and later deposits add to it.
+    #[tokio::test]
+    async fn test_deposit_creates_client() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/withdrawals.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+
+        // Assert
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].available, 12.0);
+        assert_eq!(clients[&1].total, 12.0);
+    }
+
+    // A withdrawal for an unknown client does not create it.
+    #[tokio::test]
+    async fn test_withdrawal_unknown_client() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/withdrawals.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients.len(), 1);
+        assert!(!clients.contains_key(&2));
+        assert_eq!(summary.transactions_applied, 3);
+        assert_eq!(gravenche.metrics().rejected(Rejection::UnknownClient), 1);
+    }
+
+    // An amount with eight decimal places is kept with decimals set to 8 and rounded to four by default.
+    #[tokio::test]
+    async fn test_decimals() {
+        for (decimals, expected_amount) in [(8, "0.12345678"), (4, "0.1235")] {
+            // Prepare
+            let transactions = vec![Transaction::new(1, 1, TransactionType::Deposit, 0.12345678)];
+            let mut builder = Gravenche::builder(PathBuf::new(), Vec::new());
+            if decimals != 4 {
+                builder = builder.decimals(decimals);
+            }
+            let mut gravenche = builder.build();
+
+            // Execute
+            gravenche
+                .start_with_source(VecSource(transactions.into_iter()))
+                .await
+                .unwrap();
+            gravenche.show_output().await.unwrap();
+
+            // Assert
+            let output = String::from_utf8(gravenche.output_stream.clone()).unwrap();
+            let row = output.lines().nth(1).unwrap();
+            assert_eq!(
+                row,
+                format!(
+                    "{0: >6} | {1: >10} | {2: >10} | {1: >10} | {3: >6}",
+                    1, expected_amount, 0, false
+                )
+            );
+        }
+    }
+
+    // With create_on_withdrawal a withdrawal for an unknown client creates it at zero, and the withdrawal still fails.
+    #[tokio::test]
+    async fn test_withdrawal_unknown_client_created() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/withdrawals.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .create_on_withdrawal(true)
+            .build();
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients.len(), 2);
+        assert_eq!(
+            clients[&2].snapshot(DEFAULT_DECIMALS),
+            Client::new(2).snapshot(DEFAULT_DECIMALS)
+        );
+        assert_eq!(summary.transactions_applied, 3);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::InsufficientFunds),
+            1
+        );
+        let output = String::from_utf8(gravenche.output_stream.clone()).unwrap();
+        assert!(output.contains("     2 |          0 |          0 |          0 |  false"));
+    }
+
+    // Processing a file split in two halves gives the same result as processing it whole.
+    #[tokio::test]
+    async fn test_multiple_sources() {
+        // Prepare
+        let mut whole = Gravenche::new(PathBuf::from("tests/data/disputes.csv"), 100, Vec::new());
+        let mut halves = Gravenche::builder(PathBuf::from("tests/data/disputes_1.csv"), Vec::new())
+            .add_source(PathBuf::from("tests/data/disputes_2.csv"))
+            .build();
+
+        // Execute
+        whole.start().await.unwrap();
+        let summary = halves.start().await.unwrap();
+
+        // Assert
+        let whole_clients = whole.clients.lock().await;
+        let halves_clients = halves.clients.lock().await;
+        assert_eq!(
+            snapshots(whole_clients.values(), DEFAULT_DECIMALS),
+            snapshots(halves_clients.values(), DEFAULT_DECIMALS)
+        );
+
+        let rows_read: Vec<usize> = summary.files.iter().map(|file| file.rows_read).collect();
+        assert_eq!(rows_read, vec![5, 4]);
+        assert_eq!(summary.rows_read, 9);
+    }
+
+    // NDJSON input gives the same result as the equivalent CSV input.
+    #[tokio::test]
+    async fn test_ndjson_input() {
+        // Prepare
+        let mut csv = Gravenche::new(PathBuf::from("tests/data/three.csv"), 100, Vec::new());
+        let mut ndjson = Gravenche::builder(PathBuf::from("tests/data/three.ndjson"), Vec::new())
+            .input_format(InputFormat::Ndjson)
+            .build();
+
+        // Execute
+        csv.start().await.unwrap();
+        let summary = ndjson.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 3);
+        assert_eq!(summary.transactions_applied, 3);
+        let csv_clients = csv.clients.lock().await;
+        let ndjson_clients = ndjson.clients.lock().await;
+        assert_eq!(
+            snapshots(csv_clients.values(), DEFAULT_DECIMALS),
+            snapshots(ndjson_clients.values(), DEFAULT_DECIMALS)
+        );
+    }
+
+    // Clients written to SQLite can be read back and a second run updates the existing rows.
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_output() {
+        // Prepare
+        let db_path = std::env::temp_dir().join("gravenche_test_sqlite_output.db");
+        let _ = std::fs::remove_file(&db_path);
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+
+        // Execute
+        for _ in 0..2 {
+            let mut gravenche = Gravenche::builder(csv_path.clone(), Vec::new())
+                .output_format(OutputFormat::Sqlite(db_path.clone()))
+                .build();
+            gravenche.start().await.unwrap();
+            gravenche.show_output().await.unwrap();
+            assert!(gravenche.output_stream.is_empty());
+        }
+
+        // Assert
+        let connection = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = connection
+            .prepare("SELECT id, available, held, total, locked FROM clients ORDER BY id")
+            .unwrap();
+        let rows: Vec<(ClientId, f32, f32, f32, bool)> = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(1, 14.0, 0.0, 14.0, false), (2, 2.5, 0.0, 2.5, true)]
+        );
+    }
+
+    // The final state is exported to a new database, which is refused unless it may be replaced.
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_export_sqlite() {
+        // Prepare
+        let db_path = std::env::temp_dir().join("gravenche_test_export_sqlite.db");
+        std::fs::write(&db_path, "not a database").unwrap();
+        let mut gravenche =
+            Gravenche::new(PathBuf::from("tests/data/disputes.csv"), 100, Vec::new());
+        gravenche.start().await.unwrap();
+
+        // Execute
+        let refused = gravenche.export_sqlite(&db_path, false).await;
+        let replaced = gravenche.export_sqlite(&db_path, true).await;
+
+        // Assert
+        assert!(matches!(refused, Err(GravencheError::Config(_))));
+        replaced.unwrap();
+        let connection = rusqlite::Connection::open(&db_path).unwrap();
+        let count = |table: &str| -> usize {
+            connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count("clients"), 2);
+        assert_eq!(count("transactions"), 5);
+        let balance: (f32, bool) = connection
+            .query_row(
+                "SELECT total, locked FROM clients WHERE id = 2",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(balance, (2.5, true));
+        let deposit: (ClientId, String, f32, bool) = connection
+            .query_row(
+                "SELECT client_id, type, amount, disputed FROM transactions WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(deposit, (1, String::from("deposit"), 10.0, false));
+        std::fs::remove_file(&db_path).unwrap();
+    }
+
+    // Clients and transactions written to Parquet read back with their column types.
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn test_parquet_output() {
+        use arrow_array::{
+            cast::AsArray,
+            types::{Decimal128Type, UInt32Type},
+        };
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        // Prepare
+        let clients_path = std::env::temp_dir().join("gravenche_test_parquet_clients.parquet");
+        let transactions_path =
+            std::env::temp_dir().join("gravenche_test_parquet_transactions.parquet");
+        let mut gravenche =
+            Gravenche::builder(PathBuf::from("tests/data/disputes.csv"), Vec::new())
+                .output_format(OutputFormat::Parquet(clients_path.clone()))
+                .build();
+        gravenche.start().await.unwrap();
+
+        // Execute
+        gravenche.show_output().await.unwrap();
+        gravenche
+            .export_transactions_parquet(&transactions_path)
+            .await
+            .unwrap();
+
+        // Assert
+        let read = |path: &Path| {
+            let mut reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+            reader.next().unwrap().unwrap()
+        };
+        let clients = read(&clients_path);
+        assert!(gravenche.output_stream.is_empty());
+        assert_eq!(clients.num_rows(), 2);
+        let ids = clients.column(0).as_primitive::<UInt32Type>();
+        let totals = clients.column(3).as_primitive::<Decimal128Type>();
+        let locked = clients.column(4).as_boolean();
+        assert_eq!(ids.values(), &[1, 2]);
+        assert_eq!(totals.value_as_string(0), "14.0000");
+        assert_eq!(totals.value_as_string(1), "2.5000");
+        assert_eq!((locked.value(0), locked.value(1)), (false, true));
+
+        let transactions = read(&transactions_path);
+        assert_eq!(transactions.num_rows(), 5);
+        let tx_ids = transactions.column(0).as_primitive::<UInt32Type>();
+        let types = transactions.column(2).as_string::<i32>();
+        assert_eq!(tx_ids.values(), &[1, 2, 3, 4, 5]);
+        assert_eq!(types.value(4), "withdrawal");
+        std::fs::remove_file(&clients_path).unwrap();
+        std::fs::remove_file(&transactions_path).unwrap();
+    }
+
+    // A gzip compressed file gives the same result as the uncompressed one.
+    #[tokio::test]
+    async fn test_gzip_input() {
+        // Prepare
+        let gz_path = std::env::temp_dir().join("gravenche_test_gzip_input.csv.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz_path).unwrap(), Default::default());
+        encoder
+            .write_all(&std::fs::read("test_data.csv").unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+        let mut plain = Gravenche::new(PathBuf::from("test_data.csv"), 100, Vec::new());
+        let mut gzip = Gravenche::new(gz_path, 100, Vec::new());
+
+        // Execute
+        let plain_summary = plain.start().await.unwrap();
+        let gzip_summary = gzip.start().await.unwrap();
+
+        // Assert
+        assert_eq!(plain_summary.rows_read, gzip_summary.rows_read);
+        assert_eq!(
+            plain_summary.transactions_applied,
+            gzip_summary.transactions_applied
+        );
+        let plain_clients = plain.clients.lock().await;
+        let gzip_clients = gzip.clients.lock().await;
+        assert_eq!(
+            snapshots(plain_clients.values(), DEFAULT_DECIMALS),
+            snapshots(gzip_clients.values(), DEFAULT_DECIMALS)
+        );
+    }
+
+    // A source backed by a vector of transactions.
+    struct VecSource(std::vec::IntoIter<Transaction>);
+
+    impl TransactionSource for VecSource {
+        async fn next(&mut self) -> Option<Transaction> {
+            self.0.next()
+        }
+    }
+
+    // Transactions of any source are processed like the ones read from CSV.
+    #[tokio::test]
+    async fn test_vec_source() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 3.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 4);
+        assert_eq!(summary.transactions_applied, 4);
+        let clients = gravenche.clients.lock().await;
+        let expected_client = ClientSnapshot {
+            id: 1,
+            total: 12.0,
+            available: 2.0,
+            held: 10.0,
+            locked: false,
+            decimals: DEFAULT_DECIMALS,
+        };
+        assert_eq!(clients[&1].snapshot(DEFAULT_DECIMALS), expected_client);
+    }
+
+    // A zstd compressed file gives the same result as the uncompressed one.
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_zstd_input() {
+        // Prepare
+        let zst_path = std::env::temp_dir().join("gravenche_test_zstd_input.csv.zst");
+        let csv = std::fs::read("test_data.csv").unwrap();
+        std::fs::write(&zst_path, zstd::encode_all(csv.as_slice(), 0).unwrap()).unwrap();
+        let mut plain = Gravenche::new(PathBuf::from("test_data.csv"), 100, Vec::new());
+        let mut zstd = Gravenche::new(zst_path, 100, Vec::new());
+
+        // Execute
+        let plain_summary = plain.start().await.unwrap();
+        let zstd_summary = zstd.start().await.unwrap();
+
+        // Assert
+        assert_eq!(plain_summary.rows_read, zstd_summary.rows_read);
+        assert_eq!(
+            plain_summary.transactions_applied,
+            zstd_summary.transactions_applied
+        );
+        let plain_clients = plain.clients.lock().await;
+        let zstd_clients = zstd.clients.lock().await;
+        assert_eq!(
+            snapshots(plain_clients.values(), DEFAULT_DECIMALS),
+            snapshots(zstd_clients.values(), DEFAULT_DECIMALS)
+        );
+    }
+
+    // A zstd stream cut short fails the run and reports how far reading got.
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_zstd_input_truncated() {
+        // Prepare
+        let zst_path = std::env::temp_dir().join("gravenche_test_zstd_truncated.csv");
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=2000 {
+            csv.push_str(&format!("deposit, {}, {}, 1.0\n", tx % 7, tx));
+        }
+        let compressed = zstd::encode_all(csv.as_bytes(), 0).unwrap();
+        std::fs::write(&zst_path, &compressed[..compressed.len() - 8]).unwrap();
+        let mut gravenche = Gravenche::builder(zst_path, Vec::new())
+            .compression(Compression::Zstd)
+            .build();
+
+        // Execute
+        let error = gravenche.start().await.unwrap_err();
+
+        // Assert
+        assert!(matches!(error, GravencheError::Io(_)));
+        assert!(error.to_string().starts_with("Reading input failed after"));
+        assert!(error.to_string().contains("rows were read: "));
+    }
+
+    // Comma, semicolon and tab separated files with the same transactions give the same balances.
+    #[tokio::test]
+    async fn test_delimiters() {
+        let mut results = Vec::new();
+        for (csv_path, delimiter) in [
+            ("tests/data/disputes.csv", b','),
+            ("tests/data/disputes_semicolon.csv", b';'),
+            ("tests/data/disputes_tab.tsv", b'\t'),
+        ] {
+            // Prepare
+            let mut gravenche = Gravenche::builder(PathBuf::from(csv_path), Vec::new())
+                .delimiter(delimiter)
+                .build();
+
+            // Execute
+            let summary = gravenche.start().await.unwrap();
+            assert_eq!(summary.rows_skipped, 0);
+            let clients = gravenche.clients.lock().await;
+            let mut balances: Vec<(ClientId, f32, f32, f32, bool)> = clients
+                .values()
+                .map(|c| (c.id, c.available, c.held, c.total, c.locked))
+                .collect();
+            balances.sort_by_key(|balance| balance.0);
+            results.push(balances);
+        }
+
+        // Assert
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0], results[2]);
+    }
+
+    // Comment lines are skipped when a comment character is set and fail to parse otherwise.
+    #[tokio::test]
+    async fn test_comments() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/comments.csv");
+        let mut gravenche = Gravenche::builder(csv_path.clone(), Vec::new())
+            .comment(b'#')
+            .build();
+        let mut uncommented = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+        let uncommented_summary = uncommented.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 4);
+        assert_eq!(summary.rows_skipped, 0);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 12.5);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&2].available, 15.0);
+        // The first comment is taken as the header, after which the real header fails to parse like the comments.
+        assert_eq!(uncommented_summary.rows_skipped, 3);
+    }
+
+    // A resolved dispute is not reported while one left open is.
+    #[tokio::test]
+    async fn test_open_dispute_ids() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/open_disputes.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(gravenche.open_dispute_ids().await, vec![(3, 5)]);
+    }
+
+    // Disputes are held up to and including the cap and rejected above it.
+    #[tokio::test]
+    async fn test_max_held_per_client() {
+        for (cap, expected_held) in [(5.0, 5.0), (5.5, 5.0), (4.5, 0.0)] {
+            // Prepare
+            let transactions = vec![
+                Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+                Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            ];
+            let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+                .max_held_per_client(cap)
+                .build();
+
+            // Execute
+            gravenche
+                .start_with_source(VecSource(transactions.into_iter()))
+                .await
+                .unwrap();
+
+            // Assert
+            let clients = gravenche.clients.lock().await;
+            assert_eq!(clients[&1].held, expected_held, "cap {}", cap);
+            assert_eq!(clients[&1].total, 15.0);
+            let processed_transactions = gravenche.processed_transactions.lock().await;
+            assert_eq!(
+                processed_transactions[&2].is_disputed(),
+                expected_held > 0.0
+            );
+        }
+    }
+
+    // Appends text to a file.
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    // Sends the ID of every applied transaction, so a test can wait for the processor instead of sleeping.
+    struct NotifyingObserver(mpsc::UnboundedSender<u32>);
+
+    impl TransactionObserver for NotifyingObserver {
+        fn on_applied(&mut self, transaction: &Transaction, _client: &Client) {
+            let _ = self.0.send(transaction.id);
+        }
+    }
+
+    // Rows appended to a followed file are applied, and a partial line waits for its newline.
+    #[tokio::test]
+    async fn test_follow() {
+        // Prepare
+        let path = temp_path("follow.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 1.0\n").unwrap();
+        let (notify, mut applied) = mpsc::unbounded_channel();
+        let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+            .poll_interval(Duration::from_millis(5))
+            .observer(Box::new(NotifyingObserver(notify)))
+            .build();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let writer = async {
+            assert_eq!(applied.recv().await, Some(1));
+            append(&path, "deposit, 1, 2, 5.0\ndeposit, 1, 3,");
+            assert_eq!(applied.recv().await, Some(2));
+            append(&path, " 2.0\n");
+            assert_eq!(applied.recv().await, Some(3));
+            stop.send(()).unwrap();
+        };
+
+        // Execute
+        let (summary, _) = tokio::join!(
+            gravenche.follow(async {
+                let _ = stopped.await;
+            }),
+            writer
+        );
+
+        // Assert
+        let summary = summary.unwrap();
+        assert_eq!(summary.rows_read, 3);
+        assert_eq!(summary.rows_skipped, 0);
+        assert_eq!(summary.transactions_applied, 3);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].total, 8.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // A truncated file is read again from the start.
+    #[tokio::test]
+    async fn test_follow_truncated() {
+        // Prepare
+        let path = temp_path("follow_truncated.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 10.0\n").unwrap();
+        let (notify, mut applied) = mpsc::unbounded_channel();
+        let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+            .poll_interval(Duration::from_millis(5))
+            .observer(Box::new(NotifyingObserver(notify)))
+            .build();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let writer = async {
+            assert_eq!(applied.recv().await, Some(1));
+            // The new content is shorter than what was read, so it is seen as a truncation.
+            std::fs::write(&path, "type, client, tx, amount\ndeposit, 2, 2, 3.0\n").unwrap();
+            assert_eq!(applied.recv().await, Some(2));
+            stop.send(()).unwrap();
+        };
+
+        // Execute
+        let (summary, _) = tokio::join!(
+            gravenche.follow(async {
+                let _ = stopped.await;
+            }),
+            writer
+        );
+
+        // Assert
+        let summary = summary.unwrap();
+        assert_eq!(summary.rows_read, 2);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].total, 10.0);
+        assert_eq!(clients[&2].total, 3.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // The client table is shown periodically while following a file. The clock is paused, so the shutdown comes after
+    // exactly two snapshots.
+    #[tokio::test(start_paused = true)]
+    async fn test_follow_snapshots() {
+        // Prepare
+        let path = temp_path("follow_snapshots.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 1.0\n").unwrap();
+        let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+            .poll_interval(Duration::from_millis(5))
+            .snapshot_interval(Duration::from_millis(100))
+            .build();
+
+        // Execute
+        gravenche
+            .follow(tokio::time::sleep(Duration::from_millis(250)))
+            .await
+            .unwrap();
+
+        // Assert
+        let output = String::from_utf8(gravenche.output_stream.clone()).unwrap();
+        assert_eq!(output.matches("client").count(), 2);
+        assert_eq!(output.lines().count(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Following a file is refused with a configured compression, since appended bytes can't be decompressed alone.
+    #[tokio::test]
+    async fn test_follow_compressed() {
+        // Prepare
+        let path = temp_path("follow_compressed.csv");
+        std::fs::write(&path, "type, client, tx, amount\n").unwrap();
+        let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+            .compression(Compression::Gzip)
+            .build();
+
+        // Execute
+        let result = gravenche.follow(std::future::pending()).await;
+
+        // Assert
+        assert!(matches!(result, Err(GravencheError::Config(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Files dropped into the spool directory are processed and moved to done or failed.
+    #[tokio::test]
+    async fn test_spool() {
+        // Prepare
+        let spool_dir = std::env::temp_dir().join("gravenche_test_spool");
+        let _ = std::fs::remove_dir_all(&spool_dir);
+        let incoming_dir = spool_dir.join("incoming");
+        std::fs::create_dir_all(&incoming_dir).unwrap();
+        std::fs::write(
+            incoming_dir.join("1.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 1.0\n",
+        )
+        .unwrap();
+        // Invalid UTF-8 makes reading the file fail.
+        std::fs::write(
+            incoming_dir.join("2.csv"),
+            b"type, client, tx, amount\ndeposit, 1, 2, \xff\n",
+        )
+        .unwrap();
+        std::fs::write(incoming_dir.join("ignored.txt"), "").unwrap();
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .poll_interval(Duration::from_millis(5))
+            .build();
+        // Drops another file while the service runs and then stops it.
+        let shutdown = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            std::fs::write(
+                incoming_dir.join("3.csv"),
+                "type, client, tx, amount\ndeposit, 1, 3, 2.0\n",
+            )
+            .unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+
+        // Execute
+        let summary = gravenche.spool(&spool_dir, shutdown).await.unwrap();
+
+        // Assert
+        assert_eq!(summary.files.len(), 3);
+        assert_eq!(summary.transactions_applied, 2);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].total, 3.0);
+        assert!(spool_dir.join("done/1.csv").exists());
+        assert!(spool_dir.join("failed/2.csv").exists());
+        assert!(spool_dir.join("done/3.csv").exists());
+        assert!(incoming_dir.join("ignored.txt").exists());
+        std::fs::remove_dir_all(&spool_dir).unwrap();
+    }
+
+    // Nothing of a dataset processed before reset leaks into the next one.
+    #[tokio::test]
+    async fn test_reset() {
+        // Prepare
+        let first_dataset = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+        ];
+        // The dispute refers to a transaction of the first dataset.
+        let second_dataset = vec![
+            Transaction::new(3, 1, TransactionType::Deposit, 2.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        gravenche
+            .start_with_source(VecSource(first_dataset.into_iter()))
+            .await
+            .unwrap();
+
+        // Execute
+        gravenche.reset().await;
+        let summary = gravenche
+            .start_with_source(VecSource(second_dataset.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 1);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients.len(), 1);
+        let expected_client = ClientSnapshot {
+            id: 1,
+            total: 2.0,
+            available: 2.0,
+            held: 0.0,
+            locked: false,
+            decimals: DEFAULT_DECIMALS,
+        };
+        assert_eq!(clients[&1].snapshot(DEFAULT_DECIMALS), expected_client);
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        assert_eq!(processed_transactions.len(), 1);
+        assert!(processed_transactions.contains_key(&3));
+    }
+
+    // Metrics count every transaction by type and every rejection by reason.
+    #[tokio::test]
+    async fn test_metrics() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 20.0),
+            Transaction::new(3, 2, TransactionType::Withdrawal, 1.0),
+            Transaction::new(9, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(5, 1, TransactionType::Deposit, 1.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.processed(&TransactionType::Deposit), 3);
+        assert_eq!(metrics.processed(&TransactionType::Withdrawal), 2);
+        assert_eq!(metrics.processed(&TransactionType::Dispute), 2);
+        assert_eq!(metrics.processed(&TransactionType::Resolve), 1);
+        assert_eq!(metrics.processed(&TransactionType::Chargeback), 1);
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::UnknownClient), 1);
+        assert_eq!(metrics.rejected(Rejection::UnknownTransaction), 1);
+        assert_eq!(metrics.rejected(Rejection::NotDisputed), 1);
+        assert_eq!(metrics.rejected(Rejection::AccountLocked), 1);
+        let text = metrics.render();
+        assert!(text.contains("gravenche_accounts_locked_total 1\n"));
+        assert!(text.contains("gravenche_processing_latency_seconds_bucket{le=\"+Inf\"} 9\n"));
+        assert!(text.contains("gravenche_processing_latency_seconds_count 9\n"));
+    }
+
+    // Metrics count the outcome of every row of a mixed file, by transaction type.
+    #[tokio::test]
+    async fn test_metrics_outcomes() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/summary.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+
+        // Assert
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.processed(&TransactionType::Deposit), 3);
+        assert_eq!(metrics.applied(&TransactionType::Deposit), 3);
+        assert_eq!(metrics.processed(&TransactionType::Withdrawal), 3);
+        assert_eq!(metrics.applied(&TransactionType::Withdrawal), 1);
+        assert_eq!(metrics.processed(&TransactionType::Dispute), 1);
+        assert_eq!(metrics.applied(&TransactionType::Dispute), 1);
+        assert_eq!(metrics.processed(&TransactionType::Resolve), 1);
+        assert_eq!(metrics.applied(&TransactionType::Resolve), 1);
+        assert_eq!(metrics.processed(&TransactionType::Chargeback), 0);
+        assert_eq!(metrics.outcome(Outcome::Applied), 6);
+        assert_eq!(metrics.outcome(Outcome::Rejected), 2);
+        assert_eq!(metrics.outcome(Outcome::Skipped), 2);
+        let text = metrics.render();
+        assert!(text.contains("gravenche_transactions_applied_total{type=\"withdrawal\"} 1\n"));
+        assert!(text.contains("gravenche_rows_skipped_total 2\n"));
+    }
+
+    // An allowed unlock reopens a charged back account for deposits. Unlocks of unknown clients are rejected.
+    #[tokio::test]
+    async fn test_unlock() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(3, 1, TransactionType::Unlock, 0.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 2.0),
+            Transaction::new(5, 2, TransactionType::Unlock, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .allow_unlock(true)
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 6);
+        let clients = gravenche.snapshot_clients().await;
+        assert!(!clients[&1].locked);
+        assert_eq!(clients[&1].total, 7.0);
+        assert!(!clients.contains_key(&2));
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.processed(&TransactionType::Unlock), 2);
+        assert_eq!(metrics.rejected(Rejection::UnknownClient), 1);
+    }
+
+    // A resolve of a locked client is rejected and leaves the dispute and its history alone, so it can be resolved
+    // after an unlock.
+    #[tokio::test]
+    async fn test_resolve_locked_client() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(2, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(4, 1, TransactionType::Unlock, 0.0),
+            Transaction::new(2, 1, TransactionType::Resolve, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .allow_unlock(true)
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 6.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&1].total, 6.0);
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        assert_eq!(
+            processed_transactions[&2].dispute_state,
+            DisputeState::Resolved
+        );
+        // The rejected resolve is not in the history.
+        assert_eq!(
+            format_dispute_events(processed_transactions[&2].dispute_events()),
+            "dispute@5;resolve@9"
+        );
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::AccountLocked), 1);
+        assert_eq!(metrics.applied(&TransactionType::Resolve), 1);
+    }
+
+    // A reversal takes a deposit back without a hold phase, after which the deposit can't be disputed.
+    #[tokio::test]
+    async fn test_reversal() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Reversal, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Reversal, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 4);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 5.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&1].total, 5.0);
+        assert_eq!(clients[&2].total, 5.0);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::Reversed), 1);
+        assert_eq!(metrics.rejected(Rejection::NotReversible), 1);
+    }
+
+    // The only deposit of a client can be reversed, which takes all of its available funds.
+    #[tokio::test]
+    async fn test_reversal_single_deposit() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(1, 1, TransactionType::Reversal, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 2);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 0.0);
+        assert_eq!(clients[&1].total, 0.0);
+        assert!(gravenche.transaction(1).await.unwrap().is_reversed());
+    }
+
+    // A deposit is reversed only once.
+    #[tokio::test]
+    async fn test_reversal_twice() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 20.0),
+            Transaction::new(1, 1, TransactionType::Reversal, 0.0),
+            Transaction::new(1, 1, TransactionType::Reversal, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(gravenche.snapshot_clients().await[&1].total, 20.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::Reversed), 1);
+    }
+
+    // A deposit partly withdrawn since can't be reversed, and stays disputable.
+    #[tokio::test]
+    async fn test_reversal_insufficient_funds() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 4.0),
+            Transaction::new(1, 1, TransactionType::Reversal, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 2);
+        assert_eq!(gravenche.snapshot_clients().await[&1].available, 6.0);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::InsufficientFunds),
+            1
+        );
+        assert!(!gravenche.processed_transactions.lock().await[&1].is_reversed());
+    }
+
+    // A withdrawal needs funds for the amount and the fee, and the fees of applied withdrawals add up.
+    #[tokio::test]
+    async fn test_withdrawal_fee() {
+        for (fee_policy, expected_fees, expected_total) in [
+            (FeePolicy::Flat(1.0), 2.0, 1.0),
+            (FeePolicy::Percent(10.0), 0.85, 0.65),
+        ] {
+            // Prepare
+            let transactions = vec![
+                Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                Transaction::new(2, 1, TransactionType::Withdrawal, 5.0),
+                Transaction::new(3, 1, TransactionType::Withdrawal, 3.5),
+                Transaction::new(4, 1, TransactionType::Withdrawal, 2.0),
+            ];
+            let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+                .fee_policy(fee_policy)
+                .build();
+
+            // Execute
+            let summary = gravenche
+                .start_with_source(VecSource(transactions.into_iter()))
+                .await
+                .unwrap();
+
+            // Assert
+            assert!(amounts_equal(
+                summary.fees_collected,
+                expected_fees,
+                DEFAULT_DECIMALS
+            ));
+            assert!(amounts_equal(
+                gravenche.collected_fees(),
+                expected_fees,
+                DEFAULT_DECIMALS
+            ));
+            let clients = gravenche.snapshot_clients().await;
+            assert!(amounts_equal(
+                clients[&1].total,
+                expected_total,
+                DEFAULT_DECIMALS
+            ));
+        }
+    }
+
+    // A withdrawal the available funds would cover on their own fails because of the fee.
+    #[tokio::test]
+    async fn test_withdrawal_fee_insufficient_funds() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 9.5),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .fee_policy(FeePolicy::Flat(1.0))
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 1);
+        assert_eq!(summary.fees_collected, 0.0);
+        assert_eq!(gravenche.collected_fees(), 0.0);
+        assert_eq!(gravenche.snapshot_clients().await[&1].available, 10.0);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::InsufficientFunds),
+            1
+        );
+    }
+
+    // A client at the velocity limit keeps all its withdrawals, one past it has the extra one rejected while its
+    // deposits and disputes are still applied.
+    #[tokio::test]
+    async fn test_velocity_limit() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 100.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 1.0),
+            Transaction::new(4, 1, TransactionType::Withdrawal, 1.0),
+            Transaction::new(5, 2, TransactionType::Withdrawal, 1.0),
+            Transaction::new(6, 2, TransactionType::Withdrawal, 1.0),
+            Transaction::new(7, 2, TransactionType::Withdrawal, 1.0),
+            Transaction::new(8, 2, TransactionType::Deposit, 10.0),
+            Transaction::new(8, 2, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .velocity_limit(VelocityRule {
+                max_withdrawals: 2,
+                window_rows: None,
+            })
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 8);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 98.0);
+        assert_eq!(clients[&2].available, 98.0);
+        assert_eq!(clients[&2].held, 10.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::VelocityExceeded), 1);
+    }
+
+    // Withdrawals are counted from zero again in every window of input rows.
+    #[tokio::test]
+    async fn test_velocity_limit_window() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 1.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 1.0),
+            Transaction::new(4, 1, TransactionType::Withdrawal, 1.0),
+            Transaction::new(5, 1, TransactionType::Withdrawal, 1.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .velocity_limit(VelocityRule {
+                max_withdrawals: 1,
+                window_rows: Some(3),
+            })
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(gravenche.snapshot_clients().await[&1].available, 98.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::VelocityExceeded), 2);
+    }
+
+    // Deposits and withdrawals at or above the flag threshold are flagged and exported, and applied like the others.
+    #[tokio::test]
+    async fn test_flag_threshold() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 99.5),
+            Transaction::new(2, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(3, 2, TransactionType::Deposit, 150.0),
+            Transaction::new(4, 2, TransactionType::Withdrawal, 100.0),
+            Transaction::new(5, 1, TransactionType::Withdrawal, 500.0),
+        ];
+        let mut plain = Gravenche::builder(PathBuf::new(), Vec::new()).build();
+        let mut flagging = Gravenche::builder(PathBuf::new(), Vec::new())
+            .flag_threshold(100.0)
+            .build();
+
+        // Execute
+        plain
+            .start_with_source(VecSource(transactions.clone().into_iter()))
+            .await
+            .unwrap();
+        let summary = flagging
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+        let mut exported = Vec::new();
+        flagging.export_flagged(&mut exported).await.unwrap();
+
+        // Assert
+        assert_eq!(flagging.flagged_transactions().await, vec![2, 3, 4]);
+        assert_eq!(summary.transactions_flagged, 3);
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(
+            exported,
+            "tx,client,type,amount\n2,1,deposit,100\n3,2,deposit,150\n4,2,withdrawal,100\n"
+        );
+        let flagged_clients = flagging.snapshot_clients().await;
+        for (id, client) in plain.snapshot_clients().await {
+            assert_eq!(
+                client.snapshot(DEFAULT_DECIMALS),
+                flagged_clients[&id].snapshot(DEFAULT_DECIMALS)
+            );
+        }
+    }
+
+    // USD and EUR rows of the same client are kept apart, disputes apply in the currency of the disputed deposit, and
+    // the table gets a row per client per currency.
+    #[tokio::test]
+    async fn test_currencies() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/currencies.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        let eur: Currency = "EUR".parse().unwrap();
+        assert_eq!(clients[&1].available, 100.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(
+            clients[&1].currencies,
+            vec![CurrencyBalance {
+                currency: eur,
+                total: 40.0,
+                available: 10.0,
+                held: 30.0,
+            }]
+        );
+        assert!(clients[&2].currencies.is_empty());
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::CurrencyMismatch), 1);
+        let output = String::from_utf8(gravenche.output_stream.clone()).unwrap();
+        assert!(output.starts_with("client | currency |  available |"));
+        assert!(
+            output.contains("     1 |      USD |        100 |          0 |        100 |  false\n")
+        );
+        assert!(
+            output.contains("     1 |      EUR |         10 |         30 |         40 |  false\n")
+        );
+        assert!(
+            output.contains("     2 |      USD |         10 |          0 |         10 |  false\n")
+        );
+    }
+
+    // Withdrawals may overdraw the available funds up to the overdraft limit, and a later deposit brings them back.
+    #[tokio::test]
+    async fn test_overdraft() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 15.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 10.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 20.0),
+            Transaction::new(5, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(6, 2, TransactionType::Withdrawal, 20.0),
+            Transaction::new(7, 3, TransactionType::Deposit, 5.0),
+            Transaction::new(8, 3, TransactionType::Withdrawal, 6.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .overdraft_limit(10.0)
+            .client_overdraft_limit(2, 20.0)
+            .client_overdraft_limit(3, 0.0)
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 15.0);
+        assert_eq!(clients[&1].total, 15.0);
+        assert!(clients[&1].was_negative);
+        assert_eq!(clients[&2].available, -15.0);
+        assert_eq!(clients[&3].available, 5.0);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::InsufficientFunds),
+            2
+        );
+        let output = String::from_utf8(gravenche.output_stream.clone()).unwrap();
+        assert!(output.contains("     2 |        -15 |          0 |        -15 |  false"));
+    }
+
+    // A transfer moves available funds from one client to another and creates the receiving client.
+    #[tokio::test]
+    async fn test_transfer() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Transfer, 4.0).with_counterparty(2),
+            Transaction::new(3, 1, TransactionType::Transfer, 1.5).with_counterparty(2),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 3);
+        assert_eq!(summary.clients_touched, 2);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 4.5);
+        assert_eq!(clients[&1].total, 4.5);
+        assert_eq!(clients[&2].available, 5.5);
+        assert_eq!(clients[&2].total, 5.5);
+        assert_eq!(gravenche.metrics().processed(&TransactionType::Transfer), 2);
+    }
+
+    // A transfer exceeding the available funds of the source leaves both clients untouched.
+    #[tokio::test]
+    async fn test_transfer_insufficient_funds() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 3.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 1.0),
+            Transaction::new(3, 1, TransactionType::Transfer, 5.0).with_counterparty(2),
+            Transaction::new(4, 3, TransactionType::Transfer, 1.0).with_counterparty(2),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 3.0);
+        assert_eq!(clients[&2].available, 1.0);
+        assert!(!clients.contains_key(&3));
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::UnknownClient), 1);
+    }
+
+    // A transfer to a locked client is rejected before the source is debited.
+    #[tokio::test]
+    async fn test_transfer_locked_destination() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 2, TransactionType::Deposit, 1.0),
+            Transaction::new(2, 2, TransactionType::Dispute, 0.0),
+            Transaction::new(2, 2, TransactionType::Chargeback, 0.0),
+            Transaction::new(4, 1, TransactionType::Transfer, 2.0).with_counterparty(2),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 10.0);
+        assert!(clients[&2].locked);
+        assert_eq!(clients[&2].available, 1.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::AccountLocked), 1);
+    }
+
+    // A disputed transfer holds the amount at the client it was taken from.
+    #[tokio::test]
+    async fn test_dispute_transfer() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Transfer, 4.0).with_counterparty(2),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .dispute_withdrawals(true)
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 6.0);
+        assert_eq!(clients[&1].held, 4.0);
+        assert_eq!(clients[&1].total, 10.0);
+        assert_eq!(clients[&2].available, 4.0);
+        assert_eq!(clients[&2].held, 0.0);
+    }
+
+    // Explicit zero amounts are applied as no-ops, while missing and unparseable amounts are skipped rows.
+    #[tokio::test]
+    async fn test_zero_amounts() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/zero_amounts.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 5);
+        assert_eq!(summary.rows_skipped, 3);
+        assert_eq!(summary.transactions_applied, 2);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 0.0);
+        assert_eq!(clients[&1].available, 0.0);
+        assert!(!clients.contains_key(&2));
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        let mut processed_ids: Vec<u32> = processed_transactions.keys().copied().collect();
+        processed_ids.sort();
+        assert_eq!(processed_ids, vec![1, 2]);
+    }
+
+    // A withdrawal without the funds and a deposit to a locked account are not recorded, so disputes of them refer to
+    // unknown transactions and leave the balances untouched.
+    #[tokio::test]
+    async fn test_rejected_transactions_not_disputable() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 10.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(3, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 2.0),
+            Transaction::new(4, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .dispute_withdrawals(true)
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 5.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&1].total, 5.0);
+        assert!(clients[&1].locked);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::AccountLocked), 1);
+        assert_eq!(metrics.rejected(Rejection::UnknownTransaction), 2);
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        assert!(!processed_transactions.contains_key(&2));
+        assert!(!processed_transactions.contains_key(&4));
+    }
+
+    // A partial dispute of a withdrawal keeps total equal to available plus held at every step: the dispute adds the
+    // amount to held and total, the resolve moves it to available and the chargeback takes it back out.
+    #[tokio::test]
+    async fn test_withdrawal_dispute_accounting() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 4.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 1.5),
+            Transaction::new(2, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 2.0),
+            Transaction::new(3, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Chargeback, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .dispute_withdrawals(true)
+            .build();
+        let mut events = gravenche.subscribe();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let mut balances = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            let amount = |value: &str| value.parse::<f32>().unwrap();
+            balances.push((
+                amount(&event.available),
+                amount(&event.held),
+                amount(&event.total),
+            ));
+        }
+        assert_eq!(
+            balances,
+            vec![
+                (10.0, 0.0, 10.0),
+                (6.0, 0.0, 6.0),
+                (6.0, 1.5, 7.5),
+                (7.5, 0.0, 7.5),
+                (5.5, 0.0, 5.5),
+                (5.5, 2.0, 7.5),
+                (5.5, 0.0, 5.5),
+            ]
+        );
+        let clients = gravenche.snapshot_clients().await;
+        assert!(clients[&1].locked);
+        assert!(clients[&1].check_invariants(DEFAULT_DECIMALS).is_ok());
+    }
+
+    // Admin adjustments change a locked account and can't be disputed.
+    #[tokio::test]
+    async fn test_admin_ops() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(3, 1, TransactionType::AdminCredit, 20.0),
+            Transaction::new(4, 1, TransactionType::AdminDebit, 30.0),
+            Transaction::new(5, 1, TransactionType::AdminDebit, 25.0),
+            Transaction::new(3, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .allow_admin_ops(true)
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 6);
+        let clients = gravenche.snapshot_clients().await;
+        assert!(clients[&1].locked);
+        assert_eq!(clients[&1].available, 0.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&1].total, 0.0);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::AdminOperation), 1);
+    }
+
+    // Admin adjustments are rejected and counted unless allowed.
+    #[tokio::test]
+    async fn test_admin_ops_not_allowed() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::AdminCredit, 20.0),
+            Transaction::new(3, 1, TransactionType::AdminDebit, 5.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 1);
+        assert_eq!(gravenche.snapshot_clients().await[&1].total, 10.0);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::AdminOpsNotAllowed),
+            2
+        );
+        assert!(!gravenche
+            .processed_transactions
+            .lock()
+            .await
+            .contains_key(&2));
+    }
+
+    // Disputes of withdrawals are rejected by default. Otherwise they hold the amount on top of the available funds
+    // until a resolve or a chargeback settles them, and the policy decides which of the two credits it to the client.
+    #[tokio::test]
+    async fn test_withdrawal_dispute_policies() {
+        for (withdrawal_disputes, expected_clients, expected_rejected) in [
+            (
+                WithdrawalDisputes::Rejected,
+                [
+                    (6.0, 0.0, 6.0, false),
+                    (3.0, 0.0, 3.0, false),
+                    (5.0, 0.0, 5.0, false),
+                ],
+                3,
+            ),
+            (
+                WithdrawalDisputes::CreditOnResolve,
+                [
+                    (6.0, 4.0, 10.0, false),
+                    (5.0, 0.0, 5.0, false),
+                    (5.0, 0.0, 5.0, true),
+                ],
+                0,
+            ),
+            (
+                WithdrawalDisputes::CreditOnChargeback,
+                [
+                    (6.0, 4.0, 10.0, false),
+                    (3.0, 0.0, 3.0, false),
+                    (8.0, 0.0, 8.0, true),
+                ],
+                0,
+            ),
+        ] {
+            // Prepare
+            let csv_path = PathBuf::from("tests/data/withdrawal_disputes.csv");
+            let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+                .withdrawal_disputes(withdrawal_disputes)
+                .build();
+
+            // Execute
+            gravenche.start().await.unwrap();
+
+            // Assert
+            let clients = gravenche.snapshot_clients().await;
+            for (client_id, (available, held, total, locked)) in (1..).zip(expected_clients) {
+                let client = &clients[&client_id];
+                assert_eq!(
+                    (client.available, client.held, client.total, client.locked),
+                    (available, held, total, locked),
+                    "client {} with {:?}",
+                    client_id,
+                    withdrawal_disputes
+                );
+            }
+            assert_eq!(
+                gravenche
+                    .metrics()
+                    .rejected(Rejection::WithdrawalNotDisputable),
+                expected_rejected
+            );
+        }
+    }
+
+    // Every dispute, resolve, chargeback and reversal which doesn't follow the dispute lifecycle is rejected and
+    // leaves the balances alone.
+    #[tokio::test]
+    async fn test_illegal_dispute_transitions() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 20.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 30.0),
+            // Undisputed -> Resolved, Undisputed -> ChargedBack
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            // Resolved -> Disputed, Resolved -> Resolved, Resolved -> ChargedBack
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(2, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(2, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(2, 1, TransactionType::Chargeback, 0.0),
+            // ChargedBack -> Disputed, ChargedBack -> Resolved, ChargedBack -> ChargedBack, reversal of a charged
+            // back deposit
+            Transaction::new(3, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(3, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(3, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(3, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(3, 1, TransactionType::Reversal, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 7);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 30.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&1].total, 30.0);
+        assert!(clients[&1].locked);
+        let processed_transactions = gravenche.processed_transactions.lock().await;
+        assert_eq!(
+            processed_transactions[&1].dispute_state,
+            DisputeState::Undisputed
+        );
+        assert_eq!(
+            processed_transactions[&2].dispute_state,
+            DisputeState::Resolved
+        );
+        assert_eq!(
+            processed_transactions[&3].dispute_state,
+            DisputeState::ChargedBack
+        );
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::NotDisputed), 2);
+        assert_eq!(metrics.rejected(Rejection::AlreadyResolved), 3);
+        assert_eq!(metrics.rejected(Rejection::ChargedBack), 4);
+    }
+
+    // A resolved transaction can be disputed again only with redispute_resolved, and a resolve or chargeback can then
+    // follow as after the first dispute.
+    #[tokio::test]
+    async fn test_redispute_resolved() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .redispute_resolved(true)
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 6);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 5.0);
+        assert!(clients[&1].locked);
+        assert_eq!(
+            gravenche.processed_transactions.lock().await[&1].dispute_state,
+            DisputeState::ChargedBack
+        );
+    }
+
+    // The history of a transaction lists every applied dispute, resolve and chargeback with its position in the input,
+    // while rejected ones are left out.
+    #[tokio::test]
+    async fn test_transaction_history() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .redispute_resolved(true)
+            .build();
+        let mut exported = Vec::new();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+        gravenche.export_transactions(&mut exported).await.unwrap();
+
+        // Assert
+        let event = |kind, sequence| DisputeEvent {
+            kind,
+            sequence,
+            timestamp: None,
+        };
+        assert_eq!(
+            gravenche.transaction_history(1).await,
+            Some(vec![
+                event(TransactionType::Dispute, 3),
+                event(TransactionType::Resolve, 4),
+                event(TransactionType::Dispute, 6),
+                event(TransactionType::Chargeback, 7),
+            ])
+        );
+        assert_eq!(gravenche.transaction_history(2).await, Some(Vec::new()));
+        assert_eq!(gravenche.transaction_history(3).await, None);
+        assert!(gravenche.processed_transactions.lock().await[&2]
+            .dispute_events
+            .is_none());
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "tx,client,type,amount,disputed,dispute_history\n\
+             1,1,deposit,10,false,dispute@3;resolve@4;dispute@6;chargeback@7\n\
+             2,1,deposit,5,false,\n"
+        );
+    }
+
+    // With replays skipped, a file given twice ends in the same state as a single pass over it.
+    #[tokio::test]
+    async fn test_skip_replays() {
+        // Prepare
+        let path = PathBuf::from("tests/data/disputes.csv");
+        let mut twice = Gravenche::builder(path.clone(), Vec::new())
+            .add_source(path.clone())
+            .skip_replays(true)
+            .build();
+        let mut once = Gravenche::new(path, 100, Vec::new());
+        once.start().await.unwrap();
+
+        // Execute
+        let summary = twice.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 18);
+        assert_eq!(summary.transactions_applied, 9);
+        assert_eq!(
+            snapshots(twice.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+            snapshots(once.snapshot_clients().await.values(), DEFAULT_DECIMALS)
+        );
+        let metrics = twice.metrics();
+        assert_eq!(metrics.rejected(Rejection::Replayed), 5);
+        assert_eq!(metrics.rejected(Rejection::AlreadyResolved), 2);
+        assert_eq!(metrics.rejected(Rejection::ChargedBack), 2);
+    }
+
+    // A withdrawal rejected on the first pass is skipped on the replay too, even though the balance would now cover it.
+    #[tokio::test]
+    async fn test_skip_replays_rejected() {
+        // Prepare
+        let input = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 5.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 10.0),
+        ];
+        let transactions = input.iter().chain(&input).cloned().collect::<Vec<_>>();
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .skip_replays(true)
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 2);
+        assert_eq!(gravenche.snapshot_clients().await[&1].total, 11.0);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::InsufficientFunds), 1);
+        assert_eq!(metrics.rejected(Rejection::Replayed), 3);
+    }
+
+    // A second chargeback for a client is logged and leaves the account and the other dispute alone.
+    #[tokio::test]
+    async fn test_chargeback_locked_account() {
+        // Prepare
+        #[derive(Clone, Default)]
+        struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl Write for LogBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let log = LogBuffer::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(2, 1, TransactionType::Chargeback, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 6);
+        let clients = gravenche.snapshot_clients().await;
+        assert!(clients[&1].locked);
+        assert_eq!(clients[&1].held, 5.0);
+        assert_eq!(clients[&1].total, 6.0);
+        assert!(gravenche.processed_transactions.lock().await[&2].is_disputed());
+        assert_eq!(gravenche.metrics().rejected(Rejection::AccountLocked), 1);
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains("Chargeback skipped: the account is already locked"));
+    }
+
+    // A dispute with an amount holds only that part of the transaction, further disputes are limited to the rest.
+    #[tokio::test]
+    async fn test_partial_dispute() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 50.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 30.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 80.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 20.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 100.0);
+        assert_eq!(clients[&1].held, 50.0);
+        assert_eq!(clients[&1].total, 150.0);
+        assert_eq!(
+            gravenche.processed_transactions.lock().await[&1].disputed_amount,
+            50.0
+        );
+        assert_eq!(
+            gravenche
+                .metrics()
+                .rejected(Rejection::DisputeExceedsAmount),
+            1
+        );
+    }
+
+    // A dispute with a negative amount is rejected instead of disputing the whole transaction.
+    #[tokio::test]
+    async fn test_negative_dispute_amount() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(1, 1, TransactionType::Dispute, -30.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 100.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(
+            gravenche
+                .metrics()
+                .rejected(Rejection::NegativeDisputeAmount),
+            1
+        );
+    }
+
+    // With eight decimals a dispute exceeding the undisputed part by less than the default tolerance is rejected.
+    #[tokio::test]
+    async fn test_dispute_tolerance_decimals() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 1.00005),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .decimals(8)
+            .build();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(gravenche.snapshot_clients().await[&1].held, 0.0);
+        assert_eq!(
+            gravenche
+                .metrics()
+                .rejected(Rejection::DisputeExceedsAmount),
+            1
+        );
+    }
+
+    // A resolve releases the disputed part, after which the whole transaction can be disputed again if allowed.
+    #[tokio::test]
+    async fn test_resolve_partial_dispute() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 100.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 50.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 30.0),
+            Transaction::new(1, 1, TransactionType::Resolve, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .redispute_resolved(true)
+            .build();
+        let mut events = gravenche.subscribe();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].available, 50.0);
+        assert_eq!(clients[&1].held, 100.0);
+        let amounts: Vec<String> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.amount)
+            .collect();
+        assert_eq!(amounts, vec!["100.0", "50.0", "30.0", "30.0", "100.0"]);
+    }
+
+    // A full dispute of a transaction already in dispute is rejected instead of holding the amount twice.
+    #[tokio::test]
+    async fn test_dispute_twice() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 50.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(gravenche.snapshot_clients().await[&1].held, 10.0);
+        assert_eq!(
+            gravenche
+                .metrics()
+                .rejected(Rejection::DisputeExceedsAmount),
+            1
+        );
+    }
+
+    // Unlocks are rejected unless allowed, so a charged back account stays locked.
+    #[tokio::test]
+    async fn test_unlock_not_allowed() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Chargeback, 0.0),
+            Transaction::new(3, 1, TransactionType::Unlock, 0.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 2.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let clients = gravenche.snapshot_clients().await;
+        assert!(clients[&1].locked);
+        assert_eq!(clients[&1].total, 5.0);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::UnlockNotAllowed), 1);
+        assert_eq!(metrics.rejected(Rejection::AccountLocked), 1);
+    }
+
+    // Transactions of reserved clients are skipped while others are processed.
+    #[tokio::test]
+    async fn test_reserved_client_ids() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 0, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 9001, TransactionType::Deposit, 1.0),
+            Transaction::new(4, 10000, TransactionType::Deposit, 2.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .reserved_client_ids(vec![0..=0, 9000..=9999])
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 2);
+        let clients = gravenche.clients.lock().await;
+        let mut client_ids: Vec<ClientId> = clients.keys().copied().collect();
+        client_ids.sort();
+        assert_eq!(client_ids, vec![1, 10000]);
+        assert_eq!(gravenche.metrics().rejected(Rejection::ReservedClient), 2);
+    }
+
+    // Transactions of allowed clients are applied as usual, including transfers between them.
+    #[tokio::test]
+    async fn test_allowed_client_ids_hit() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 5, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 2.0),
+            Transaction::new(4, 5, TransactionType::Transfer, 1.0).with_counterparty(1),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .allowed_client_ids(vec![1..=1, 5..=9])
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 4);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients.get(&1).unwrap().available, 9.0);
+        assert_eq!(clients.get(&5).unwrap().available, 4.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::NotAllowedClient), 0);
+    }
+
+    // Deposits of clients which aren't allowed are skipped instead of creating the client, as are transfers to them.
+    #[tokio::test]
+    async fn test_allowed_client_ids_miss() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            Transaction::new(3, 1, TransactionType::Transfer, 1.0).with_counterparty(3),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .allowed_client_ids(vec![1..=1])
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 1);
+        let clients = gravenche.clients.lock().await;
+        let client_ids: Vec<ClientId> = clients.keys().copied().collect();
+        assert_eq!(client_ids, vec![1]);
+        assert_eq!(clients.get(&1).unwrap().available, 10.0);
+        assert_eq!(gravenche.metrics().rejected(Rejection::NotAllowedClient), 2);
+    }
+
+    // Only transactions in the window, including both bounds, are applied, while disputes refer to earlier ones.
+    #[tokio::test]
+    async fn test_tx_window() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 1.0),
+            Transaction::new(2, 1, TransactionType::Deposit, 2.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 4.0),
+            Transaction::new(4, 1, TransactionType::Deposit, 8.0),
+            Transaction::new(5, 1, TransactionType::Deposit, 16.0),
+            Transaction::new(2, 1, TransactionType::Dispute, 0.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .tx_window(2..=4)
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.transactions_applied, 4);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 14.0);
+        assert_eq!(clients[&1].held, 2.0);
+        let metrics = gravenche.metrics();
+        assert_eq!(metrics.rejected(Rejection::OutsideTxWindow), 2);
+        assert_eq!(metrics.rejected(Rejection::UnknownTransaction), 1);
+    }
+
+    // Rows outside the time window are dropped and counted apart from skipped rows. The dispute of the dropped deposit
+    // has no timestamp, so it is processed and refers to an unknown transaction.
+    #[tokio::test]
+    async fn test_time_window() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/time_window.csv");
+        let mut unfiltered = Gravenche::new(csv_path.clone(), 100, Vec::new());
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .since("2024-03-01T00:00:00Z".parse().unwrap())
+            .until("2024-04-01T00:00:00Z".parse().unwrap())
+            .build();
+
+        // Execute
+        unfiltered.start().await.unwrap();
+        let summary = gravenche.start().await.unwrap();
+        let mut exported = Vec::new();
+        gravenche.export_transactions(&mut exported).await.unwrap();
+
+        // Assert
+        let unfiltered_clients = unfiltered.snapshot_clients().await;
+        assert_eq!(unfiltered_clients[&1].total, 2.5);
+        assert_eq!(unfiltered_clients[&1].held, 1.0);
+        assert_eq!(summary.rows_read, 7);
+        assert_eq!(summary.rows_filtered, 2);
+        assert_eq!(summary.rows_skipped, 0);
+        let clients = gravenche.snapshot_clients().await;
+        assert_eq!(clients[&1].total, 2.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert_eq!(clients[&2].total, 12.0);
+        assert_eq!(clients[&2].held, 4.0);
+        assert_eq!(
+            gravenche.metrics().rejected(Rejection::UnknownTransaction),
+            1
+        );
+        assert_eq!(
+            gravenche.transaction_history(3).await.unwrap()[0].timestamp,
+            Some("2024-03-20T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "tx,client,type,amount,disputed,dispute_history,timestamp\n\
+             2,1,deposit,2,false,,2024-03-01T00:00:00Z\n\
+             3,2,deposit,4,true,dispute@4,2024-03-15T10:00:00Z\n\
+             5,2,deposit,8,false,,2024-03-31T23:59:59Z\n"
+        );
+    }
+
+    // Shuffled rows processed in timestamp order give the same result as rows sorted by time.
+    #[tokio::test]
+    async fn test_order_by_timestamp() {
+        // Prepare
+        let sorted_path = std::env::temp_dir().join("gravenche_test_sorted.csv");
+        let shuffled_path = std::env::temp_dir().join("gravenche_test_shuffled.csv");
+        std::fs::write(
+            &sorted_path,
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 10.0, 100\n\
+             withdrawal, 1, 2, 4.0, 200\n\
+             deposit, 1, 3, 1.0, 300\n\
+             dispute, 1, 3, , 400\n\
+             chargeback, 1, 3, , 500\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &shuffled_path,
+            "type, client, tx, amount, timestamp\n\
+             chargeback, 1, 3, , 500\n\
+             withdrawal, 1, 2, 4.0, 200\n\
+             dispute, 1, 3, , 400\n\
+             deposit, 1, 3, 1.0, 300\n\
+             deposit, 1, 1, 10.0, 100\n",
+        )
+        .unwrap();
+        let mut sorted = Gravenche::new(sorted_path.clone(), 100, Vec::new());
+        let mut shuffled = Gravenche::builder(shuffled_path.clone(), Vec::new())
+            .order_by_timestamp(true)
+            .build();
+
+        // Execute
+        sorted.start().await.unwrap();
+        shuffled.start().await.unwrap();
+
+        // Assert
+        let sorted_clients = sorted.clients.lock().await;
+        let shuffled_clients = shuffled.clients.lock().await;
+        assert_eq!(
+            sorted_clients[&1].snapshot(DEFAULT_DECIMALS),
+            shuffled_clients[&1].snapshot(DEFAULT_DECIMALS)
+        );
+        assert!(shuffled_clients[&1].locked);
+        std::fs::remove_file(&sorted_path).unwrap();
+        std::fs::remove_file(&shuffled_path).unwrap();
+    }
+
+    // Subscribers receive an event for every applied transaction.
+    #[tokio::test]
+    async fn test_subscribe() {
+        // Prepare
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 20.0),
+            Transaction::new(3, 1, TransactionType::Deposit, 5.0),
+            Transaction::new(1, 1, TransactionType::Dispute, 0.0),
+        ];
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let mut events = gravenche.subscribe();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        let deposit = events.recv().await.unwrap();
+        assert_eq!(deposit.event, "deposit");
+        assert_eq!(deposit.available, "10.0");
+        // The failed withdrawal has no event.
+        let deposit = events.recv().await.unwrap();
+        assert_eq!(deposit.tx, 3);
+        assert_eq!(deposit.available, "15.0");
+        let dispute = events.recv().await.unwrap();
+        assert_eq!(dispute.event, "dispute");
+        assert_eq!(dispute.tx, 1);
+        assert_eq!(dispute.amount, "10.0");
+        assert_eq!(dispute.available, "5.0");
+        assert_eq!(dispute.held, "10.0");
+        assert!(events.try_recv().is_err());
+    }
+
+    // A subscriber receives the events of a file in order, with the balances after each transaction.
+    #[tokio::test]
+    async fn test_subscribe_file() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+        let mut events = gravenche.subscribe();
+        let event = |event, tx, amount: &str, available: &str, held: &str, total: &str, locked| {
+            ClientEvent {
+                event,
+                client: 2,
+                tx,
+                amount: amount.to_string(),
+                available: available.to_string(),
+                held: held.to_string(),
+                total: total.to_string(),
+                locked,
+            }
+        };
+
+        // Execute
+        gravenche.start().await.unwrap();
+
+        // Assert
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.client == 2 {
+                received.push(event);
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                event("deposit", 3, "20.0", "20.0", "0.0", "20.0", false),
+                event("deposit", 4, "2.5", "22.5", "0.0", "22.5", false),
+                event("dispute", 3, "20.0", "2.5", "20.0", "22.5", false),
+                event("chargeback", 3, "20.0", "2.5", "0.0", "2.5", true),
+            ]
+        );
+    }
+
+    // A subscriber which falls too far behind is told how many events it missed and continues with the oldest kept.
+    #[tokio::test]
+    async fn test_subscribe_lagged() {
+        // Prepare
+        let count = EVENTS_CAPACITY as u32 + 10;
+        let transactions: Vec<Transaction> = (1..=count)
+            .map(|tx| Transaction::new(tx, 1, TransactionType::Deposit, 1.0))
+            .collect();
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let mut events = gravenche.subscribe();
+
+        // Execute
+        gravenche
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            events.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        );
+        assert_eq!(events.recv().await.unwrap().tx, 11);
+    }
+
+    // Reading stops at the row limit and the summary tells that the input was cut short.
+    #[tokio::test]
+    async fn test_max_rows() {
+        // Prepare
+        let path = std::env::temp_dir().join("gravenche_test_max_rows.csv");
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=100 {
+            csv.push_str(&format!("deposit, 1, {}, 1.0\n", tx));
+        }
+        std::fs::write(&path, csv).unwrap();
+        let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+            .max_rows(10)
+            .build();
+
+        // Execute
+        let summary = gravenche.start().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 10);
+        assert_eq!(summary.transactions_applied, 10);
+        assert!(summary.truncated);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].total, 10.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Malformed rows count towards the row limit, so a malformed tail longer than the limit isn't read past it, also
+    // when the input is sorted.
+    #[tokio::test]
+    async fn test_max_rows_malformed_tail() {
+        // Prepare
+        let path = std::env::temp_dir().join("gravenche_test_max_rows_malformed_tail.csv");
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=3 {
+            csv.push_str(&format!("deposit, 1, {}, 1.0\n", tx));
+        }
+        for tx in 4..=100 {
+            csv.push_str(&format!("bogus, 1, {}, 1.0\n", tx));
+        }
+        csv.push_str("deposit, 1, 101, 1.0\n");
+        std::fs::write(&path, csv).unwrap();
+
+        for order_by_timestamp in [false, true] {
+            let mut gravenche = Gravenche::builder(path.clone(), Vec::new())
+                .max_rows(10)
+                .order_by_timestamp(order_by_timestamp)
+                .build();
+
+            // Execute
+            let summary = gravenche.start().await.unwrap();
+
+            // Assert
+            assert_eq!(summary.rows_read, 10);
+            assert_eq!(summary.rows_skipped, 7);
+            assert_eq!(summary.transactions_applied, 3);
+            assert!(summary.truncated);
+            let clients = gravenche.clients.lock().await;
+            assert_eq!(clients[&1].total, 3.0);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Mapped input files give the same results as buffered ones, including gzip compressed and empty files.
+    #[cfg(feature = "mmap")]
+    #[tokio::test]
+    async fn test_mmap() {
+        // Prepare
+        let gzip_path = std::env::temp_dir().join("gravenche_test_mmap.csv.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gzip_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(&std::fs::read("tests/data/disputes.csv").unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+        let empty_path = std::env::temp_dir().join("gravenche_test_mmap_empty.csv");
+        File::create(&empty_path).unwrap();
+        let paths = [
+            PathBuf::from("tests/data/disputes.csv"),
+            PathBuf::from("tests/data/withdrawals.csv"),
+            gzip_path.clone(),
+            empty_path.clone(),
+        ];
+
+        for path in paths {
+            let mut buffered = Gravenche::builder(path.clone(), Vec::new()).build();
+            let mut mapped = Gravenche::builder(path.clone(), Vec::new())
+                .mmap(true)
+                .build();
+
+            // Execute
+            let buffered_summary = buffered.start().await.unwrap();
+            let mapped_summary = mapped.start().await.unwrap();
+
+            // Assert
+            assert_eq!(mapped_summary.rows_read, buffered_summary.rows_read);
+            assert_eq!(
+                snapshots(mapped.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+                snapshots(buffered.snapshot_clients().await.values(), DEFAULT_DECIMALS)
+            );
+            let mut buffered_output = Vec::new();
+            let mut mapped_output = Vec::new();
+            buffered
+                .export_transactions(&mut buffered_output)
+                .await
+                .unwrap();
+            mapped
+                .export_transactions(&mut mapped_output)
+                .await
+                .unwrap();
+            assert_eq!(mapped_output, buffered_output);
+        }
+        std::fs::remove_file(&gzip_path).unwrap();
+        std::fs::remove_file(&empty_path).unwrap();
+    }
+
+    // Applying a delta file on top of the snapshot of a base file gives the same state as processing both files
+    // together, including locked accounts and disputes raised in the base file.
+    #[tokio::test]
+    async fn test_snapshot_restore() {
+        // Prepare
+        let mut base = Gravenche::new(PathBuf::from("tests/data/state_base.csv"), 100, Vec::new());
+        base.start().await.unwrap();
+        let mut snapshot = Vec::new();
+        base.save_snapshot(&mut snapshot).await.unwrap();
+        let mut delta =
+            Gravenche::new(PathBuf::from("tests/data/state_delta.csv"), 100, Vec::new());
+        let mut single_run =
+            Gravenche::builder(PathBuf::from("tests/data/state_base.csv"), Vec::new())
+                .add_source(PathBuf::from("tests/data/state_delta.csv"))
+                .build();
+        single_run.start().await.unwrap();
+
+        // Execute
+        delta.load_snapshot(snapshot.as_slice()).await.unwrap();
+        delta.start().await.unwrap();
+
+        // Assert
+        let clients = delta.snapshot_clients().await;
+        assert_eq!(
+            snapshots(clients.values(), DEFAULT_DECIMALS),
+            snapshots(
+                single_run.snapshot_clients().await.values(),
+                DEFAULT_DECIMALS
+            )
+        );
+        assert!(clients[&2].locked);
+        assert_eq!(clients[&2].total, 2.5);
+        assert_eq!(clients[&1].held, 5.0);
+        let mut delta_output = Vec::new();
+        let mut single_run_output = Vec::new();
+        delta.export_transactions(&mut delta_output).await.unwrap();
+        single_run
+            .export_transactions(&mut single_run_output)
+            .await
+            .unwrap();
+        // Dispute history positions refer to the input of each run, so only the other columns match.
+        let without_history = |output: Vec<u8>| -> Vec<String> {
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(|line| line.rsplit_once(',').unwrap().0.to_string())
+                .collect()
+        };
+        assert_eq!(
+            without_history(delta_output),
+            without_history(single_run_output)
+        );
+        assert_eq!(
+            delta.transaction_history(1).await,
+            Some(vec![
+                DisputeEvent {
+                    kind: TransactionType::Dispute,
+                    sequence: 6,
+                    timestamp: None,
+                },
+                DisputeEvent {
+                    kind: TransactionType::Resolve,
+                    sequence: 1,
+                    timestamp: None,
+                },
+            ])
+        );
+    }
+
+    // Loading something which isn't a snapshot fails without changing the state.
+    #[tokio::test]
+    async fn test_load_snapshot_invalid() {
+        // Prepare
+        let mut gravenche =
+            Gravenche::new(PathBuf::from("tests/data/disputes.csv"), 100, Vec::new());
+        gravenche.start().await.unwrap();
+
+        // Execute
+        let result = gravenche
+            .load_snapshot(&b"type, client, tx, amount"[..])
+            .await;
+
+        // Assert
+        assert!(matches!(result, Err(GravencheError::Parse(_))));
+        assert_eq!(gravenche.snapshot_clients().await.len(), 2);
+    }
+
+    // A run interrupted after a checkpoint resumes from it and ends in the same state as an uninterrupted run.
+    #[tokio::test]
+    async fn test_checkpoint_resume() {
+        // Prepare
+        let path = PathBuf::from("tests/data/disputes.csv");
+        let checkpoint_path = std::env::temp_dir().join("gravenche_test_resume.ckpt");
+        let _ = std::fs::remove_file(&checkpoint_path);
+        // The run stops after 7 rows, as if it crashed, while the last checkpoint covers 4 transactions.
+        let mut interrupted = Gravenche::builder(path.clone(), Vec::new())
+            .checkpoint(checkpoint_path.clone(), 4)
+            .max_rows(7)
+            .build();
+        interrupted.start().await.unwrap();
+        let mut resumed = Gravenche::builder(path.clone(), Vec::new())
+            .checkpoint(checkpoint_path.clone(), 4)
+            .build();
+        let mut uninterrupted = Gravenche::new(path, 100, Vec::new());
+        uninterrupted.start().await.unwrap();
+
+        // Execute
+        let summary = resumed.resume().await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 9);
+        assert_eq!(summary.transactions_applied, 5);
+        assert_eq!(
+            snapshots(resumed.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+            snapshots(
+                uninterrupted.snapshot_clients().await.values(),
+                DEFAULT_DECIMALS
+            )
+        );
+        let mut resumed_output = Vec::new();
+        let mut uninterrupted_output = Vec::new();
+        resumed
+            .export_transactions(&mut resumed_output)
+            .await
+            .unwrap();
+        uninterrupted
+            .export_transactions(&mut uninterrupted_output)
+            .await
+            .unwrap();
+        assert_eq!(resumed_output, uninterrupted_output);
+        assert!(!std::env::temp_dir()
+            .join("gravenche_test_resume.ckpt.tmp")
+            .exists());
+        std::fs::remove_file(&checkpoint_path).unwrap();
+    }
+
+    // Withdrawal fees charged before a checkpoint are restored when resuming, and saved with a snapshot.
+    #[tokio::test]
+    async fn test_checkpoint_resume_fees() {
+        // Prepare
+        let path = temp_path("resume_fees.csv");
+        std::fs::write(
+            &path,
+            "type, client, tx, amount\ndeposit, 1, 1, 10.0\nwithdrawal, 1, 2, 1.0\nwithdrawal, 1, 3, 1.0\n\
+             deposit, 1, 4, 1.0\nwithdrawal, 1, 5, 1.0\nwithdrawal, 1, 6, 1.0\n",
+        )
+        .unwrap();
+        let checkpoint_path = temp_path("resume_fees.ckpt");
+        let _ = std::fs::remove_file(&checkpoint_path);
+        // The run stops after 4 rows, which the last checkpoint covers along with 2 fees.
+        let mut interrupted = Gravenche::builder(path.clone(), Vec::new())
+            .checkpoint(checkpoint_path.clone(), 2)
+            .fee_policy(FeePolicy::Flat(0.5))
+            .max_rows(4)
+            .build();
+        interrupted.start().await.unwrap();
+        let mut resumed = Gravenche::builder(path.clone(), Vec::new())
+            .checkpoint(checkpoint_path.clone(), 2)
+            .fee_policy(FeePolicy::Flat(0.5))
+            .build();
+
+        // Execute
+        let summary = resumed.resume().await.unwrap();
+        let mut snapshot = Vec::new();
+        resumed.save_snapshot(&mut snapshot).await.unwrap();
+        let mut loaded = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        loaded.load_snapshot(snapshot.as_slice()).await.unwrap();
+
+        // Assert
+        assert_eq!(interrupted.collected_fees(), 1.0);
+        assert_eq!(summary.fees_collected, 2.0);
+        assert_eq!(resumed.collected_fees(), 2.0);
+        assert_eq!(loaded.collected_fees(), 2.0);
+        assert_eq!(resumed.client(1).await.unwrap().available, 5.0);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&checkpoint_path).unwrap();
+    }
+
+    // Resuming without a checkpoint starts from the beginning, and a checkpoint past the end of the input is refused.
+    #[tokio::test]
+    async fn test_resume_checkpoint_past_end() {
+        // Prepare
+        let checkpoint_path = std::env::temp_dir().join("gravenche_test_resume_past_end.ckpt");
+        let _ = std::fs::remove_file(&checkpoint_path);
+        let mut gravenche =
+            Gravenche::builder(PathBuf::from("tests/data/disputes.csv"), Vec::new())
+                .checkpoint(checkpoint_path.clone(), 1)
+                .build();
+        let summary = gravenche.resume().await.unwrap();
+        assert_eq!(summary.transactions_applied, 9);
+        let mut shorter = Gravenche::builder(PathBuf::from("tests/data/three.csv"), Vec::new())
+            .checkpoint(checkpoint_path.clone(), 1)
+            .build();
+
+        // Execute
+        let result = shorter.resume().await;
+
+        // Assert
+        assert!(matches!(result, Err(GravencheError::Config(_))));
+        std::fs::remove_file(&checkpoint_path).unwrap();
+    }
+
+    /// A [TransactionSource] waiting for a signal between two batches of transactions.
+    struct PausedSource {
+        first: std::vec::IntoIter<Transaction>,
+        resume: Option<oneshot::Receiver<()>>,
+        second: std::vec::IntoIter<Transaction>,
+    }
+
+    impl TransactionSource for PausedSource {
+        async fn next(&mut self) -> Option<Transaction> {
+            if let Some(transaction) = self.first.next() {
+                return Some(transaction);
+            }
+            if let Some(resume) = self.resume.take() {
+                let _ = resume.await;
+            }
+            self.second.next()
+        }
+    }
+
+    // Snapshots can be taken from another task while transactions are processed.
+    #[tokio::test]
+    async fn test_snapshot_clients() {
+        // Prepare
+        let (resume, resumed) = oneshot::channel();
+        let source = PausedSource {
+            first: vec![
+                Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            ]
+            .into_iter(),
+            resume: Some(resumed),
+            second: vec![
+                Transaction::new(3, 1, TransactionType::Withdrawal, 4.0),
+                Transaction::new(4, 3, TransactionType::Deposit, 1.0),
+            ]
+            .into_iter(),
+        };
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let handle = gravenche.clients_handle();
+        let observer = async {
+            // Wait for the first batch to be applied while the source is paused.
+            let mut snapshot = handle.snapshot_clients().await;
+            while snapshot.len() < 2 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                snapshot = handle.snapshot_clients().await;
+            }
+            resume.send(()).unwrap();
+            snapshot
+        };
+
+        // Execute
+        let (summary, mid_run) = tokio::join!(gravenche.start_with_source(source), observer);
+        let last = gravenche.snapshot_clients().await;
+
+        // Assert
+        assert_eq!(summary.unwrap().transactions_applied, 4);
+        assert_eq!(mid_run.len(), 2);
+        assert_eq!(mid_run[&1].available, 10.0);
+        assert_eq!(last.len(), 3);
+        assert_eq!(last[&1].available, 6.0);
+        assert_eq!(last[&3].available, 1.0);
+    }
+
+    // The snapshot of the clients after a run holds every client once, ordered by client ID.
+    #[tokio::test]
+    async fn test_clients_snapshot() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::new(csv_path, 100, Vec::new());
+
+        // Execute
+        gravenche.start().await.unwrap();
+        let clients = gravenche.clients_snapshot().await;
+
+        // Assert
+        assert_eq!(
+            snapshots(&clients, DEFAULT_DECIMALS),
+            snapshots(
+                &[
+                    Client {
+                        id: 1,
+                        total: 14.0,
+                        available: 14.0,
+                        held: 0.0,
+                        locked: false,
+                        dispute_count: 1,
+                        chargeback_count: 0,
+                        was_negative: false,
+                        currencies: Vec::new(),
+                    },
+                    Client {
+                        id: 2,
+                        total: 2.5,
+                        available: 2.5,
+                        held: 0.0,
+                        locked: true,
+                        dispute_count: 1,
+                        chargeback_count: 1,
+                        was_negative: false,
+                        currencies: Vec::new(),
+                    },
+                ],
+                DEFAULT_DECIMALS
+            )
+        );
+        // Counters are not compared by equality.
+        let counts: Vec<(u32, u32)> = clients
+            .iter()
+            .map(|client| (client.dispute_count, client.chargeback_count))
+            .collect();
+        assert_eq!(counts, vec![(1, 0), (1, 1)]);
+    }
+
+    // A test case authored as data: transactions as in NDJSON input and the clients expected after them.
+    #[derive(serde::Deserialize)]
+    struct FixtureCase {
+        transactions: Vec<JsonTransaction>,
+        clients: Vec<ClientSnapshot>,
+    }
+
+    // Every case in tests/data/cases ends with the clients it expects, ordered by client ID.
+    #[tokio::test]
+    async fn test_fixture_cases() {
+        // Prepare
+        let mut paths: Vec<PathBuf> = std::fs::read_dir("tests/data/cases")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        assert!(paths.len() >= 3);
+
+        for path in paths {
+            let case: FixtureCase = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+            let transactions: Vec<Transaction> = case
+                .transactions
+                .into_iter()
+                .map(|record| Transaction::try_from(record).unwrap())
+                .collect();
+            let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+            // Execute
+            gravenche
+                .start_with_source(VecSource(transactions.into_iter()))
+                .await
+                .unwrap();
+
+            // Assert
+            let clients: Vec<ClientSnapshot> = gravenche
+                .clients_snapshot()
+                .await
+                .iter()
+                .map(|client| client.snapshot(DEFAULT_DECIMALS))
+                .collect();
+            assert_eq!(clients, case.clients, "{}", path.display());
+        }
+    }
+
+    // Processes CSV held in memory and returns the client table written to the output stream.
+    async fn process_in_memory(csv: &'static str) -> String {
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        gravenche
+            .start_with_source(CsvSource::new(csv.as_bytes()))
+            .await
+            .unwrap();
+        gravenche.show_output().await.unwrap();
+        String::from_utf8(gravenche.output_stream).unwrap()
+    }
+
+    // Deposits add to the available funds and withdrawals take from them, unless they exceed them.
+    #[tokio::test]
+    async fn test_in_memory_deposits_and_withdrawals() {
+        // Prepare
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 2, 2, 2.5
+withdrawal, 1, 3, 4.25
+withdrawal, 2, 4, 3.0
+deposit, 1, 5, 0.5
+";
+
+        // Execute
+        let output = process_in_memory(csv).await;
+
+        // Assert
+        assert_eq!(
+            output,
+            "client |  available |       held |      total | locked
+     1 |       6.25 |          0 |       6.25 |  false
+     2 |        2.5 |          0 |        2.5 |  false
+"
+        );
+    }
+
+    // A dispute holds the funds of a deposit and a resolve releases them again.
+    #[tokio::test]
+    async fn test_in_memory_dispute_and_resolve() {
+        // Prepare
+        let disputed = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 1, 2, 5.0
+dispute, 1, 1,
+";
+        let resolved = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 1, 2, 5.0
+dispute, 1, 1,
+resolve, 1, 1,
+";
+
+        // Execute
+        let disputed = process_in_memory(disputed).await;
+        let resolved = process_in_memory(resolved).await;
+
+        // Assert
+        assert_eq!(
+            disputed,
+            "client |  available |       held |      total | locked
+     1 |          5 |         10 |         15 |  false
+"
+        );
+        assert_eq!(
+            resolved,
+            "client |  available |       held |      total | locked
+     1 |         15 |          0 |         15 |  false
+"
+        );
+    }
+
+    // A chargeback removes the held funds of a disputed deposit and locks the account, which then rejects deposits.
+    #[tokio::test]
+    async fn test_in_memory_dispute_and_chargeback() {
+        // Prepare
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 1, 2, 5.0
+deposit, 2, 3, 1.0
+dispute, 1, 1,
+chargeback, 1, 1,
+deposit, 1, 4, 7.0
+";
+
+        // Execute
+        let output = process_in_memory(csv).await;
+
+        // Assert
+        assert_eq!(
+            output,
+            "client |  available |       held |      total | locked
+     1 |          5 |          0 |          5 |   true
+     2 |          1 |          0 |          1 |  false
+"
+        );
+    }
+
+    // Two runs over the same input give equal reports. With only_locked the report has only the locked clients, but its
+    // totals still cover all the clients.
+    #[tokio::test]
+    async fn test_report() {
+        // Prepare
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut first = Gravenche::new(csv_path.clone(), 100, Vec::new());
+        let mut second = Gravenche::new(csv_path.clone(), 100, Vec::new());
+        let mut locked = Gravenche::builder(csv_path, Vec::new())
+            .only_locked(true)
+            .build();
+
+        // Execute
+        for gravenche in [&mut first, &mut second, &mut locked] {
+            gravenche.start().await.unwrap();
+        }
+        let report = first.report().await;
+
+        // Assert
+        assert_eq!(report, second.report().await);
+        let ids: Vec<ClientId> = report.clients.iter().map(|client| client.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(report.locked_clients, 1);
+        assert_eq!(report.available, 16.5);
+        assert_eq!(report.held, 0.0);
+        assert_eq!(report.total, 16.5);
+        assert_eq!(report.disputes, 2);
+        assert_eq!(report.chargebacks, 1);
+
+        let report = locked.report().await;
+        assert_eq!(report.clients.len(), 1);
+        assert_eq!(report.clients[0].id, 2);
+        assert_eq!(report.all_clients, 2);
+        assert_eq!(report.locked_clients, 1);
+        assert_eq!(report.total, 16.5);
+        assert_eq!(report.disputes, 2);
+        assert_ne!(report, second.report().await);
+    }
+
+    // A formatter set on the builder writes the shown clients instead of the table.
+    #[tokio::test]
+    async fn test_formatter() {
+        // Prepare
+        struct SummaryFormatter;
+        impl OutputFormatter for SummaryFormatter {
+            fn write(&mut self, clients: &[Client], out: &mut dyn Write) -> anyhow::Result<()> {
+                let total: f32 = clients.iter().map(|client| client.total).sum();
+                writeln!(out, "{} clients, {} in total", clients.len(), total)?;
+                Ok(())
+            }
+        }
+        let csv_path = PathBuf::from("tests/data/disputes.csv");
+        let mut gravenche = Gravenche::builder(csv_path, Vec::new())
+            .formatter(Box::new(SummaryFormatter))
+            .build();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_output().await.unwrap();
+
+        // Assert
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        assert_eq!(output, "2 clients, 16.5 in total\n");
+    }
+
+    // The top clients are written with the formatter too, highest total first, and nothing else is written with it.
+    #[tokio::test]
+    async fn test_top_clients_formatter() {
+        // Prepare
+        struct IdFormatter;
+        impl OutputFormatter for IdFormatter {
+            fn write(&mut self, clients: &[Client], out: &mut dyn Write) -> anyhow::Result<()> {
+                let ids: Vec<String> = clients.iter().map(|client| client.id.to_string()).collect();
+                writeln!(out, "{}", ids.join(","))?;
+                Ok(())
+            }
+        }
+        let mut gravenche =
+            Gravenche::builder(PathBuf::from("tests/data/top_clients.csv"), Vec::new())
+                .formatter(Box::new(IdFormatter))
+                .build();
+        let mut locked =
+            Gravenche::builder(PathBuf::from("tests/data/locked_clients.csv"), Vec::new())
+                .formatter(Box::new(IdFormatter))
+                .only_locked(true)
+                .build();
+
+        // Execute
+        gravenche.start().await.unwrap();
+        gravenche.show_top_clients(3).await.unwrap();
+        locked.start().await.unwrap();
+        locked.show_top_clients(1).await.unwrap();
+
+        // Assert
+        let output = String::from_utf8(gravenche.output_stream).unwrap();
+        assert_eq!(output, "2,4,3\n");
+        let output = String::from_utf8(locked.output_stream).unwrap();
+        assert_eq!(output, "2\n");
+    }
+
+    // Single clients can be looked up from another task while transactions are processed, and after the run.
+    #[tokio::test]
+    async fn test_client() {
+        // Prepare
+        let (resume, resumed) = oneshot::channel();
+        let source = PausedSource {
+            first: vec![
+                Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                Transaction::new(2, 2, TransactionType::Deposit, 5.0),
+            ]
+            .into_iter(),
+            resume: Some(resumed),
+            second: vec![
+                Transaction::new(3, 1, TransactionType::Withdrawal, 4.0),
+                Transaction::new(4, 3, TransactionType::Deposit, 1.0),
+            ]
+            .into_iter(),
+        };
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let handle = gravenche.clients_handle();
+        let observer = async {
+            // Wait for the first batch to be applied while the source is paused.
+            let mut client = handle.client(2).await;
+            while client.is_none() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                client = handle.client(2).await;
+            }
+            let mid_run = (handle.client(1).await, client, handle.client(3).await);
+            resume.send(()).unwrap();
+            mid_run
+        };
+
+        // Execute
+        let (summary, (first, second, third)) =
+            tokio::join!(gravenche.start_with_source(source), observer);
+
+        // Assert
+        assert_eq!(summary.unwrap().transactions_applied, 4);
+        assert_eq!(first.unwrap().available, 10.0);
+        assert_eq!(second.unwrap().available, 5.0);
+        assert!(third.is_none());
+        assert_eq!(gravenche.client(1).await.unwrap().available, 6.0);
+        assert_eq!(gravenche.client(3).await.unwrap().available, 1.0);
+        assert!(gravenche.client(42).await.is_none());
+        assert!(handle.client(42).await.is_none());
+    }
+
+    // Processed transactions can be looked up while transactions are processed and after the run, showing their
+    // dispute state at the time.
+    #[tokio::test]
+    async fn test_transaction() {
+        // Prepare
+        let (resume, resumed) = oneshot::channel();
+        let source = PausedSource {
+            first: vec![
+                Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+                Transaction::new(2, 1, TransactionType::Deposit, 5.0),
+            ]
+            .into_iter(),
+            resume: Some(resumed),
+            second: vec![Transaction::new(2, 1, TransactionType::Dispute, 0.0)].into_iter(),
+        };
+        let mut gravenche = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        let handle = gravenche.clients_handle();
+        let observer = async {
+            // Wait for the deposits to be applied while the source is paused.
+            let mut transaction = handle.transaction(2).await;
+            while transaction.is_none() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                transaction = handle.transaction(2).await;
+            }
+            resume.send(()).unwrap();
+            transaction.unwrap()
+        };
+
+        // Execute
+        let (summary, before) = tokio::join!(gravenche.start_with_source(source), observer);
+        let after = gravenche.transaction(2).await.unwrap();
+
+        // Assert
+        assert_eq!(summary.unwrap().transactions_applied, 3);
+        assert_eq!(before.client_id, 1);
+        assert_eq!(before.amount, 5.0);
+        assert!(!before.is_disputed());
+        assert_eq!(before.dispute_state, DisputeState::Undisputed);
+        assert!(after.is_disputed());
+        assert_eq!(after.dispute_state, DisputeState::Disputed);
+        assert_eq!(after.disputed_amount, 5.0);
+        assert!(!gravenche.transaction(1).await.unwrap().is_disputed());
+        assert!(gravenche.transaction(3).await.is_none());
+    }
+
+    // An interrupted run applies exactly the rows read before it stopped, like a run over just those rows.
+    #[tokio::test]
+    async fn test_start_until() {
+        // Prepare
+        let csv_path = std::env::temp_dir().join("gravenche_test_start_until.csv");
+        let rows = READ_BATCH * 200;
+        let lines: Vec<String> = (0..rows)
+            .map(|row| format!("deposit, {}, {}, 1.0\n", row % 50, row + 1))
+            .collect();
+        let header = "type, client, tx, amount\n";
+        std::fs::write(&csv_path, format!("{}{}", header, lines.concat())).unwrap();
+        let mut gravenche = Gravenche::new(csv_path.clone(), 100, Vec::new());
+        let handle = gravenche.clients_handle();
+        let shutdown = async {
+            // Interrupt once the first transaction was applied.
+            while handle.client(0).await.is_none() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+
+        // Execute
+        let summary = gravenche.start_until(shutdown).await.unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+        let read = format!("{}{}", header, lines[..summary.rows_read].concat());
+        let mut expected = Gravenche::new(PathBuf::new(), 100, Vec::new());
+        expected
+            .start_with_source(CsvSource::new(read.as_bytes()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(summary.interrupted);
+        assert!(summary.files[0].interrupted);
+        assert!(summary.rows_read > 0 && summary.rows_read < rows);
+        assert_eq!(summary.transactions_applied, summary.rows_read);
+        assert_eq!(
+            snapshots(&gravenche.clients_snapshot().await, DEFAULT_DECIMALS),
+            snapshots(&expected.clients_snapshot().await, DEFAULT_DECIMALS)
+        );
+    }
+
+    // Parsing input files in a reader task of their own gives the same results as parsing them on the same task.
+    #[tokio::test]
+    async fn test_reader_task() {
+        // Prepare
+        let csv_path = temp_path("reader_task.csv");
+        let mut csv = String::from("type, client, tx, amount\n");
+        for row in 0..READ_BATCH * 5 {
+            let (client, tx) = (row % 50, row + 1);
+            match row % 8 {
+                0..=3 => csv.push_str(&format!("deposit, {}, {}, 10.0\n", client, tx)),
+                4 | 5 => csv.push_str(&format!("withdrawal, {}, {}, 1.5\n", client, tx)),
+                6 => csv.push_str(&format!("dispute, {}, {},\n", client, tx - 6)),
+                _ => csv.push_str(&format!("bogus, {}, {},\n", client, tx)),
+            }
+        }
+        std::fs::write(&csv_path, &csv).unwrap();
+        let mut threaded = Gravenche::new(csv_path.clone(), 100, Vec::new());
+        let mut inline = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let threaded_summary = threaded.start().await.unwrap();
+        let inline_summary = inline
+            .start_with_source(CsvSource::new(csv.as_bytes()))
+            .await
+            .unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+
+        // Assert
+        assert_eq!(threaded_summary.rows_read, READ_BATCH * 5);
+        assert_eq!(threaded_summary.rows_skipped, READ_BATCH * 5 / 8);
+        assert_eq!(threaded_summary.rows_skipped, inline_summary.rows_skipped);
+        assert_eq!(
+            threaded_summary.transactions_applied,
+            inline_summary.transactions_applied
+        );
+        assert_eq!(
+            snapshots(threaded.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+            snapshots(inline.snapshot_clients().await.values(), DEFAULT_DECIMALS)
+        );
+    }
+
+    // Replaying the write-ahead log of a run rebuilds the same clients and transactions.
+    #[tokio::test]
+    async fn test_replay_wal() {
+        // Prepare
+        let wal_path = temp_path("replay.wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let mut logged = Gravenche::builder(PathBuf::from("tests/data/disputes.csv"), Vec::new())
+            .wal(wal_path.clone())
+            .wal_sync_every(4)
+            .build();
+        logged.start().await.unwrap();
+        let mut replayed = Gravenche::new(PathBuf::new(), 100, Vec::new());
+
+        // Execute
+        let summary = replayed.replay_wal(&wal_path).await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 9);
+        assert_eq!(
+            snapshots(logged.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+            snapshots(replayed.snapshot_clients().await.values(), DEFAULT_DECIMALS)
+        );
+        let mut logged_output = Vec::new();
+        let mut replayed_output = Vec::new();
+        logged
+            .export_transactions(&mut logged_output)
+            .await
+            .unwrap();
+        replayed
+            .export_transactions(&mut replayed_output)
+            .await
+            .unwrap();
+        assert_eq!(logged_output, replayed_output);
+
+        let mut reader = WalReader::open(&wal_path).unwrap();
+        let mut applied = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            applied.push(record.applied);
+        }
+        assert_eq!(applied.len(), 9);
+        assert_eq!(applied.iter().filter(|applied| **applied).count(), 9);
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    // Rejected transactions are logged but not replayed, so a replay under a more permissive configuration rebuilds the
+    // clients of the logged run.
+    #[tokio::test]
+    async fn test_replay_wal_rejected() {
+        // Prepare
+        let wal_path = temp_path("replay_rejected.wal");
+        let _ = std::fs::remove_file(&wal_path);
+        let transactions = vec![
+            Transaction::new(1, 1, TransactionType::Deposit, 10.0),
+            Transaction::new(2, 1, TransactionType::Withdrawal, 15.0),
+            Transaction::new(3, 1, TransactionType::Withdrawal, 4.0),
+        ];
+        let mut logged = Gravenche::builder(PathBuf::new(), Vec::new())
+            .wal(wal_path.clone())
+            .build();
+        logged
+            .start_with_source(VecSource(transactions.into_iter()))
+            .await
+            .unwrap();
+        let mut replayed = Gravenche::builder(PathBuf::new(), Vec::new())
+            .overdraft_limit(10.0)
+            .build();
+
+        // Execute
+        let summary = replayed.replay_wal(&wal_path).await.unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 2);
+        assert_eq!(summary.transactions_applied, 2);
+        assert_eq!(replayed.snapshot_clients().await[&1].available, 6.0);
+        assert_eq!(
+            snapshots(logged.snapshot_clients().await.values(), DEFAULT_DECIMALS),
+            snapshots(replayed.snapshot_clients().await.values(), DEFAULT_DECIMALS)
+        );
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    // A missing input file is reported as an I/O error.
+    #[tokio::test]
+    async fn test_error_io() {
+        // Prepare
+        let path = std::env::temp_dir().join("gravenche_test_error_missing.csv");
+        let mut gravenche = Gravenche::new(path, 100, Vec::new());
+
+        // Execute
+        let result = gravenche.start().await;
+
+        // Assert
+        match result {
+            Err(GravencheError::Io(error)) => assert_eq!(error.kind(), io::ErrorKind::NotFound),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        }
+    }
+
+    // Input that isn't valid CSV is reported as a parse error.
+    #[tokio::test]
+    async fn test_error_parse() {
+        // Prepare
+        let path = std::env::temp_dir().join("gravenche_test_error_parse.csv");
+        std::fs::write(&path, b"type, client, tx, amount\ndeposit, 1, 1, \xff\n").unwrap();
+        let mut gravenche = Gravenche::new(path.clone(), 100, Vec::new());
+
+        // Execute
+        let result = gravenche.start().await;
+
+        // Assert
+        assert!(matches!(result, Err(GravencheError::Parse(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A [MessageConsumer](crate::kafka::MessageConsumer) returning queued messages and then waiting forever.
+    #[cfg(feature = "kafka")]
+    struct MockConsumer {
+        messages: std::collections::VecDeque<&'static str>,
+        consumed: usize,
+        /// Number of messages consumed at every commit.
+        commits: Vec<usize>,
+    }
+
+    #[cfg(feature = "kafka")]
+    impl crate::kafka::MessageConsumer for &mut MockConsumer {
+        async fn recv(&mut self) -> anyhow::Result<Vec<u8>> {
+            match self.messages.pop_front() {
+                Some(message) => {
+                    self.consumed += 1;
+                    Ok(message.as_bytes().to_vec())
+                }
+                None => std::future::pending().await,
+            }
+        }
+
+        fn commit(&mut self) -> anyhow::Result<()> {
+            self.commits.push(self.consumed);
+            Ok(())
+        }
+    }
+
+    // Consumed messages are applied, malformed ones skipped, and all of them committed once the consumer is idle.
+    #[cfg(feature = "kafka")]
+    #[tokio::test]
+    async fn test_consume() {
+        // Prepare
+        let mut consumer = MockConsumer {
+            messages: [
+                "deposit, 1, 1, 10.0",
+                "bogus, 1, 2",
+                "withdrawal, 1, 3, 4.0\n",
+            ]
+            .into(),
+            consumed: 0,
+            commits: Vec::new(),
+        };
+        let mut gravenche = Gravenche::builder(PathBuf::new(), Vec::new())
+            .poll_interval(Duration::from_millis(5))
+            .build();
+
+        // Execute
+        let summary = gravenche
+            .consume(
+                &mut consumer,
+                tokio::time::sleep(Duration::from_millis(100)),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(summary.rows_read, 3);
+        assert_eq!(summary.rows_skipped, 1);
+        assert_eq!(summary.transactions_applied, 2);
+        assert_eq!(consumer.commits, [3]);
+        let clients = gravenche.clients.lock().await;
+        assert_eq!(clients[&1].available, 6.0);
     }
 }
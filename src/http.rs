@@ -0,0 +1,164 @@
+//! This module contains the HTTP API started with [Gravenche::serve_http](crate::Gravenche::serve_http). It is
+//! available with the `http` feature.
+//!
+//! * `POST /transactions` applies a transaction sent as a JSON object with the same fields as NDJSON input.
+//! * `GET /clients` returns all the clients ordered by client ID.
+//! * `GET /clients/{id}` returns a single client or `404 Not Found`.
+//! * `GET /events` upgrades to a WebSocket streaming a JSON [ClientEvent] for every applied transaction.
+
+use crate::types::{
+    client::{ClientId, ClientSnapshot},
+    other::{ClientEvent, Command},
+    transaction::{JsonTransaction, Transaction},
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    http::StatusCode,
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, oneshot,
+};
+
+/// State shared by the request handlers.
+#[derive(Clone)]
+pub(crate) struct HttpState {
+    /// Sender of the transaction processor queue.
+    sender: mpsc::Sender<Command>,
+    /// Number of transactions received.
+    rows_read: Arc<AtomicUsize>,
+    /// Sender of the events of applied transactions.
+    events: broadcast::Sender<ClientEvent>,
+    /// Number of decimal places of the amounts of the listed clients.
+    decimals: u32,
+}
+
+impl HttpState {
+    pub(crate) fn new(
+        sender: mpsc::Sender<Command>,
+        events: broadcast::Sender<ClientEvent>,
+        decimals: u32,
+    ) -> Self {
+        HttpState {
+            sender,
+            rows_read: Arc::new(AtomicUsize::new(0)),
+            events,
+            decimals,
+        }
+    }
+
+    /// Returns the number of transactions received so far.
+    pub(crate) fn rows_read(&self) -> usize {
+        self.rows_read.load(Ordering::Relaxed)
+    }
+}
+
+/// Builds the router of the HTTP API.
+pub(crate) fn router(state: HttpState) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/clients", get(list_clients))
+        .route("/clients/{id}", get(get_client))
+        .route("/events", get(stream_events))
+        .with_state(state)
+}
+
+// Returns the clients reflecting all the transactions received so far, ordered by client ID.
+async fn snapshot(state: &HttpState) -> Result<Vec<ClientSnapshot>, StatusCode> {
+    let (reply, snapshot) = oneshot::channel();
+    state
+        .sender
+        .send(Command::Snapshot(reply))
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    let clients = snapshot
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(clients
+        .iter()
+        .map(|client| client.snapshot(state.decimals))
+        .collect())
+}
+
+// Queues a transaction for processing.
+async fn submit_transaction(
+    State(state): State<HttpState>,
+    Json(record): Json<JsonTransaction>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let transaction = Transaction::try_from(record)
+        .map_err(|error| (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()))?;
+    state.rows_read.fetch_add(1, Ordering::Relaxed);
+    state
+        .sender
+        .send(Command::Transaction(transaction))
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, String::new()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn list_clients(
+    State(state): State<HttpState>,
+) -> Result<Json<Vec<ClientSnapshot>>, StatusCode> {
+    Ok(Json(snapshot(&state).await?))
+}
+
+async fn get_client(
+    State(state): State<HttpState>,
+    Path(id): Path<ClientId>,
+) -> Result<Json<ClientSnapshot>, StatusCode> {
+    snapshot(&state)
+        .await?
+        .into_iter()
+        .find(|client| client.id == id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn stream_events(State(state): State<HttpState>, upgrade: WebSocketUpgrade) -> Response {
+    // Subscribe before the upgrade so no event is missed while the handshake completes.
+    let events = state.events.subscribe();
+    upgrade.on_upgrade(move |socket| send_events(socket, events))
+}
+
+// Sends events to a WebSocket until either side is closed.
+async fn send_events(mut socket: WebSocket, mut events: broadcast::Receiver<ClientEvent>) {
+    loop {
+        let received = tokio::select! {
+            received = events.recv() => received,
+            // Messages from the client are ignored, it only matters when it goes away.
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        };
+        let event = match received {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!(missed, "WebSocket subscriber fell behind, skipping events");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let text = match serde_json::to_string(&event) {
+            Ok(text) => text,
+            Err(error) => {
+                tracing::error!("Failed to serialize event: {}", error);
+                continue;
+            }
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! This module contains the Kafka input consumed with [Gravenche::consume](crate::Gravenche::consume). It is available
+//! with the `kafka` feature.
+
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    Message,
+};
+use std::future::Future;
+
+/// Number of messages after which their offsets are committed. Offsets are also committed when no message arrives
+/// for a poll interval.
+pub const COMMIT_BATCH_SIZE: usize = 1000;
+
+/// A consumer of messages carrying one CSV record without a header each.
+pub trait MessageConsumer {
+    /// Returns the payload of the next message, waiting until one arrives.
+    fn recv(&mut self) -> impl Future<Output = anyhow::Result<Vec<u8>>> + Send;
+
+    /// Commits all the messages returned so far, so they aren't consumed again after a restart.
+    fn commit(&mut self) -> anyhow::Result<()>;
+}
+
+/// A [MessageConsumer] reading a Kafka topic as a member of a consumer group.
+pub struct KafkaConsumer {
+    consumer: StreamConsumer,
+}
+
+impl KafkaConsumer {
+    /// Subscribes to `topic` on `brokers`, a comma separated list of `host:port`. Offsets are committed only through
+    /// [MessageConsumer::commit].
+    pub fn new(brokers: &str, topic: &str, group: &str) -> anyhow::Result<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(KafkaConsumer { consumer })
+    }
+}
+
+impl MessageConsumer for KafkaConsumer {
+    async fn recv(&mut self) -> anyhow::Result<Vec<u8>> {
+        let message = self.consumer.recv().await?;
+        Ok(message.payload().unwrap_or_default().to_vec())
+    }
+
+    fn commit(&mut self) -> anyhow::Result<()> {
+        self.consumer.commit_consumer_state(CommitMode::Sync)?;
+        Ok(())
+    }
+}
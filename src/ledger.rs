@@ -0,0 +1,606 @@
+//! This module contains the state machine applying transactions to clients. It is shared by the processor task of
+//! [Gravenche](crate::Gravenche) and the synchronous path of the `sync` feature, so both end in the same state.
+
+use crate::metrics::Rejection;
+use crate::observer::TransactionObserver;
+use crate::types::{
+    client::{amounts_equal, round_amount, Client, ClientId, ClientStore},
+    config::{Config, VelocityRule, WithdrawalDisputes},
+    currency::Currency,
+    transaction::{DisputeState, Transaction, TransactionType},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Outcome of a transaction applied by [RunRules::apply].
+pub(crate) struct Applied {
+    /// Set when a deposit or withdrawal is at or above the flag threshold. Only the processor task lists flagged
+    /// transactions, the synchronous path has no flag threshold.
+    #[cfg_attr(not(feature = "runtime"), allow(dead_code))]
+    pub(crate) flagged: bool,
+    /// Fee charged on a withdrawal, zero for other transactions. Only the processor task adds the fees up.
+    #[cfg_attr(not(feature = "runtime"), allow(dead_code))]
+    pub(crate) fee: f32,
+}
+
+/// State of the rules which span the transactions of a run: skipped replays and the velocity limit. Both count
+/// transactions by their position among those handled in the run, not by their input row.
+pub(crate) struct RunRules {
+    // Withdrawals counted against the velocity limit per client.
+    withdrawal_counts: HashMap<ClientId, (u64, u32)>,
+    // IDs of the transactions handled in the run, applied or rejected. Only kept when replays are skipped.
+    handled_ids: Option<HashSet<u32>>,
+}
+
+impl RunRules {
+    pub(crate) fn new(config: &Config) -> Self {
+        RunRules {
+            withdrawal_counts: HashMap::new(),
+            handled_ids: config.skip_replays.then(HashSet::new),
+        }
+    }
+
+    /// Applies a transaction like [apply_transaction], after rejecting replays and withdrawals over the velocity
+    /// limit, and tells `observer` the outcome. `sequence` is the position of the transaction among those handled in
+    /// the run, starting at 1. The transaction is left as it was read, the processed transactions get a copy.
+    pub(crate) fn apply<S: ClientStore>(
+        &mut self,
+        clients: &mut S,
+        processed_transactions: &mut HashMap<u32, Transaction>,
+        config: &Config,
+        transaction: &Transaction,
+        sequence: u64,
+        observer: Option<&mut dyn TransactionObserver>,
+    ) -> Result<Applied, Rejection> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.id;
+        let _type = transaction._type;
+        let amount = transaction.amount;
+        // Disputes and the like carry the ID of another transaction, so only their own checks apply.
+        let replayed = !_type.refers_to_transaction()
+            && self
+                .handled_ids
+                .as_ref()
+                .is_some_and(|ids| ids.contains(&transaction_id));
+        let velocity_exceeded = !replayed
+            && _type == TransactionType::Withdrawal
+            && config.velocity_limit.is_some_and(|rule| {
+                !count_withdrawal(&mut self.withdrawal_counts, rule, client_id, sequence)
+            });
+        let result = if replayed {
+            tracing::debug!(
+                client = client_id,
+                tx = transaction_id,
+                kind = %_type,
+                "Skipping replayed transaction"
+            );
+            Err(Rejection::Replayed)
+        } else if velocity_exceeded {
+            tracing::warn!(
+                client = client_id,
+                tx = transaction_id,
+                "Rejecting withdrawal over the velocity limit"
+            );
+            Err(Rejection::VelocityExceeded)
+        } else {
+            // Transactions read from the input have no dispute history and own no heap data unless they have a memo,
+            // so the copy doesn't allocate otherwise.
+            apply_transaction(
+                clients,
+                processed_transactions,
+                config,
+                transaction.clone(),
+                sequence,
+            )
+        };
+        // A rejected transaction counts as handled too, so its replay isn't retried against a later balance.
+        if let Some(handled_ids) = self.handled_ids.as_mut() {
+            if !_type.refers_to_transaction() {
+                handled_ids.insert(transaction_id);
+            }
+        }
+        if let Some(observer) = observer {
+            notify(observer, &*clients, transaction, result.map(|_| ()));
+        }
+        let fee = result?;
+
+        let flagged = matches!(
+            _type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && config
+            .flag_threshold
+            .is_some_and(|threshold| amount >= threshold);
+        if flagged {
+            tracing::warn!(
+                client = client_id,
+                tx = transaction_id,
+                kind = %_type,
+                amount,
+                "Flagging transaction at or above the threshold"
+            );
+        }
+        Ok(Applied { flagged, fee })
+    }
+}
+
+// Tells the observer the outcome of a transaction. A panic of the observer is logged, so it neither stops processing nor
+// changes the outcome.
+fn notify<S: ClientStore>(
+    observer: &mut dyn TransactionObserver,
+    clients: &S,
+    transaction: &Transaction,
+    result: Result<(), Rejection>,
+) {
+    let notified = panic::catch_unwind(AssertUnwindSafe(|| match result {
+        Ok(()) => match clients.get(transaction.client_id) {
+            Some(client) => observer.on_applied(transaction, client),
+            None => tracing::error!(
+                client = transaction.client_id,
+                tx = transaction.id,
+                "Applied transaction has no client to report to the observer"
+            ),
+        },
+        Err(rejection) => observer.on_rejected(transaction, rejection),
+    }));
+    if notified.is_err() {
+        tracing::error!("Transaction observer panicked");
+    }
+}
+
+// Counts a withdrawal of a client at `position` against the velocity rule. `counts` holds the window and the number
+// of withdrawals counted in it per client. Returns false, without counting, once the client made the maximum number
+// of withdrawals in the window.
+fn count_withdrawal(
+    counts: &mut HashMap<ClientId, (u64, u32)>,
+    rule: VelocityRule,
+    client_id: ClientId,
+    position: u64,
+) -> bool {
+    let window = rule.window_rows.map_or(0, |rows| (position - 1) / rows);
+    let (counted_window, count) = counts.entry(client_id).or_insert((window, 0));
+    if *counted_window != window {
+        *counted_window = window;
+        *count = 0;
+    }
+    if *count >= rule.max_withdrawals {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+// Returns the currency a transaction is applied in, `None` for the base currency.
+fn balance_currency(transaction: &Transaction, config: &Config) -> Option<Currency> {
+    transaction
+        .currency
+        .filter(|&currency| currency != config.base_currency)
+}
+
+// Returns the currency a dispute, resolve, chargeback or reversal is applied in, which is the currency of the
+// transaction it refers to. It may name that currency but no other.
+fn referenced_currency(
+    transaction: &Transaction,
+    referenced: &Transaction,
+    config: &Config,
+) -> Result<Option<Currency>, Rejection> {
+    let currency = balance_currency(referenced, config);
+    if transaction.currency.is_some() && balance_currency(transaction, config) != currency {
+        tracing::warn!(
+            client = transaction.client_id,
+            tx = transaction.id,
+            "Rejected: the currency differs from the referenced transaction"
+        );
+        return Err(Rejection::CurrencyMismatch);
+    }
+    Ok(currency)
+}
+
+// Returns why a withdrawal or dispute failed for a client that exists.
+fn funds_rejection(client: &Client) -> Rejection {
+    if client.locked {
+        Rejection::AccountLocked
+    } else {
+        Rejection::InsufficientFunds
+    }
+}
+
+/// Applies a transaction to the clients. Deposits and withdrawals are recorded in the processed transactions, so they
+/// can be disputed later. `sequence` is the position of the transaction among those handled by the processor, not its
+/// input row, and is recorded in the dispute history. Returns the fee charged, which is zero for all but withdrawals,
+/// or why the transaction was not applied.
+pub(crate) fn apply_transaction<S: ClientStore>(
+    clients: &mut S,
+    processed_transactions: &mut HashMap<u32, Transaction>,
+    config: &Config,
+    mut transaction: Transaction,
+    sequence: u64,
+) -> Result<f32, Rejection> {
+    // Amounts are stored with the configured precision, so every transaction sees the same rounding.
+    transaction.amount = round_amount(transaction.amount, config.decimals);
+    let client_id = transaction.client_id;
+    let transaction_id = transaction.id;
+
+    // Transactions of reserved clients are skipped.
+    let reserved = config
+        .reserved_client_ids
+        .iter()
+        .any(|ids| ids.contains(&client_id));
+    if reserved {
+        tracing::warn!(
+            client = client_id,
+            tx = transaction_id,
+            kind = %transaction._type,
+            "Skipping transaction of a reserved client ID"
+        );
+        return Err(Rejection::ReservedClient);
+    }
+
+    // Transactions of clients which aren't allowed are skipped, so such a client is never created.
+    if !config.is_allowed_client(client_id) {
+        tracing::warn!(
+            client = client_id,
+            tx = transaction_id,
+            kind = %transaction._type,
+            "Skipping transaction of a client ID which isn't allowed"
+        );
+        return Err(Rejection::NotAllowedClient);
+    }
+
+    // A dispute, resolve, chargeback or reversal is applied whatever the window, as long as the transaction it refers
+    // to was.
+    let outside_window = config
+        .tx_window
+        .as_ref()
+        .is_some_and(|window| !window.contains(&transaction_id));
+    if outside_window && !transaction._type.refers_to_transaction() {
+        tracing::debug!(
+            tx = transaction_id,
+            kind = %transaction._type,
+            "Skipping transaction outside the transaction ID window"
+        );
+        return Err(Rejection::OutsideTxWindow);
+    }
+
+    // Only withdrawals are charged a fee, which is deducted together with the amount.
+    let fee = match transaction._type {
+        TransactionType::Withdrawal => config
+            .fee_policy
+            .map_or(0.0, |fee_policy| fee_policy.fee(transaction.amount)),
+        _ => 0.0,
+    };
+
+    match transaction._type {
+        TransactionType::Deposit => {
+            let amount = transaction.amount;
+            let currency = balance_currency(&transaction, config);
+
+            // A deposit creates the client if it doesn't exist yet.
+            let current_client = clients.get_or_create(client_id);
+            current_client
+                .in_currency(currency, |current_client| current_client.deposit(amount))
+                .map_err(|_| Rejection::AccountLocked)?;
+
+            // Record the applied transaction. Required for dispute resolution, a rejected one can't be disputed.
+            processed_transactions.insert(transaction_id, transaction);
+            Ok(())
+        }
+        TransactionType::Withdrawal => {
+            let withdrawal_amount = transaction.amount;
+            let currency = balance_currency(&transaction, config);
+
+            // Unlike a deposit, a withdrawal creates a client only if configured. The client starts at zero, so the
+            // withdrawal itself fails but the client is shown.
+            if config.create_on_withdrawal {
+                clients.get_or_create(client_id);
+            }
+            let current_client = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+            // Modify client data only if Client is not locked.
+            current_client.in_currency(currency, |current_client| {
+                current_client
+                    .withdraw_with_overdraft(
+                        withdrawal_amount + fee,
+                        config.overdraft_limit_for(client_id),
+                    )
+                    .map_err(|_| funds_rejection(current_client))
+            })?;
+
+            // Record the applied transaction. Required for dispute resolution, a rejected one can't be disputed.
+            processed_transactions.insert(transaction_id, transaction);
+            Ok(())
+        }
+        TransactionType::Transfer => {
+            let amount = transaction.amount;
+            let currency = balance_currency(&transaction, config);
+            let to_client_id = transaction.counterparty.unwrap_or(client_id);
+            let reserved = config
+                .reserved_client_ids
+                .iter()
+                .any(|ids| ids.contains(&to_client_id));
+            if reserved {
+                tracing::warn!(
+                    client = to_client_id,
+                    tx = transaction_id,
+                    "Skipping transfer to a reserved client ID"
+                );
+                return Err(Rejection::ReservedClient);
+            }
+            if !config.is_allowed_client(to_client_id) {
+                tracing::warn!(
+                    client = to_client_id,
+                    tx = transaction_id,
+                    "Skipping transfer to a client ID which isn't allowed"
+                );
+                return Err(Rejection::NotAllowedClient);
+            }
+
+            // Check both clients before touching either, so a transfer is applied completely or not at all.
+            let source = clients.get(client_id).ok_or(Rejection::UnknownClient)?;
+            if source.locked || clients.get(to_client_id).is_some_and(|to| to.locked) {
+                return Err(Rejection::AccountLocked);
+            }
+            let source = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+            source.in_currency(currency, |source| {
+                source
+                    .withdraw_with_overdraft(amount, config.overdraft_limit_for(client_id))
+                    .map_err(|_| funds_rejection(source))
+            })?;
+
+            // Like a deposit, a transfer creates the receiving client if it doesn't exist yet.
+            clients
+                .get_or_create(to_client_id)
+                .in_currency(currency, |to| to.deposit(amount))
+                .map_err(|_| Rejection::AccountLocked)?;
+
+            // Record the applied transaction. A transfer is disputed against the client it was taken from.
+            processed_transactions.insert(transaction_id, transaction);
+            Ok(())
+        }
+        TransactionType::Dispute => {
+            let disputed_transaction = processed_transactions
+                .get_mut(&transaction_id)
+                .ok_or(Rejection::UnknownTransaction)?;
+            if disputed_transaction.is_reversed() {
+                return Err(Rejection::Reversed);
+            }
+            if disputed_transaction.is_admin() {
+                return Err(Rejection::AdminOperation);
+            }
+            let currency = referenced_currency(&transaction, disputed_transaction, config)?;
+            // Funds of a withdrawal or transfer already left the client, so only the policy decides about disputing it.
+            let is_withdrawal = disputed_transaction.is_withdrawal();
+            if is_withdrawal && config.withdrawal_disputes == WithdrawalDisputes::Rejected {
+                tracing::warn!(
+                    client = client_id,
+                    tx = transaction_id,
+                    "Dispute ignored: withdrawals can't be disputed"
+                );
+                return Err(Rejection::WithdrawalNotDisputable);
+            }
+            check_dispute_transition(
+                disputed_transaction.dispute_state,
+                DisputeState::Disputed,
+                config,
+            )?;
+            if transaction.amount < 0.0 {
+                return Err(Rejection::NegativeDisputeAmount);
+            }
+            // A dispute without an amount disputes all of the transaction which is not in dispute yet.
+            let undisputed_amount = disputed_transaction.undisputed_amount();
+            let disputed_amount = if transaction.amount > 0.0 {
+                transaction.amount
+            } else {
+                undisputed_amount
+            };
+            let exceeds_undisputed = disputed_amount > undisputed_amount
+                && !amounts_equal(disputed_amount, undisputed_amount, config.decimals);
+            if amounts_equal(undisputed_amount, 0.0, config.decimals) || exceeds_undisputed {
+                return Err(Rejection::DisputeExceedsAmount);
+            }
+            let disputed_amount = disputed_amount.min(undisputed_amount);
+            let current_client = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+
+            // The cap applies to the held funds of the currency of the dispute.
+            current_client.in_currency(currency, |current_client| {
+                if let Some(max_held) = config.max_held_per_client {
+                    if current_client.held + disputed_amount > max_held {
+                        tracing::warn!(
+                            client = client_id,
+                            tx = transaction_id,
+                            "Dispute rejected: held funds would exceed the cap of {}",
+                            max_held
+                        );
+                        return Err(Rejection::HeldCap);
+                    }
+                }
+
+                // Modify client data only if Client is not locked.
+                if is_withdrawal {
+                    current_client.raise_withdrawal_dispute(disputed_amount)
+                } else {
+                    current_client.raise_dispute(disputed_amount)
+                }
+                .map_err(|_| funds_rejection(current_client))
+            })?;
+            // Flag the transaction as disputed only if funds are held for it.
+            disputed_transaction.mark_disputed(disputed_amount);
+            disputed_transaction.record_dispute_event(
+                TransactionType::Dispute,
+                sequence,
+                transaction.timestamp,
+            );
+            Ok(())
+        }
+        TransactionType::Resolve => {
+            let disputed_transaction = processed_transactions
+                .get_mut(&transaction_id)
+                .ok_or(Rejection::UnknownTransaction)?;
+            check_dispute_transition(
+                disputed_transaction.dispute_state,
+                DisputeState::Resolved,
+                config,
+            )?;
+            let currency = referenced_currency(&transaction, disputed_transaction, config)?;
+            let disputed_amount = disputed_transaction.disputed_amount;
+            let credits_chargeback = credits_on_chargeback(disputed_transaction, config);
+
+            let current_client = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+            // Modify client data only if Client is not locked.
+            current_client
+                .in_currency(currency, |current_client| {
+                    if credits_chargeback {
+                        current_client.release_withdrawal_dispute(disputed_amount)
+                    } else {
+                        current_client.resolve_dispute(disputed_amount)
+                    }
+                })
+                .map_err(|_| Rejection::AccountLocked)?;
+            // Flag the transaction as resolved only once the funds are released, so a rejected resolve can be retried.
+            disputed_transaction.mark_resolved();
+            disputed_transaction.record_dispute_event(
+                TransactionType::Resolve,
+                sequence,
+                transaction.timestamp,
+            );
+            Ok(())
+        }
+        TransactionType::Reversal => {
+            let reversed_transaction = processed_transactions
+                .get_mut(&transaction_id)
+                .ok_or(Rejection::UnknownTransaction)?;
+            if reversed_transaction.is_reversed() {
+                return Err(Rejection::Reversed);
+            }
+            if reversed_transaction.dispute_state == DisputeState::ChargedBack {
+                return Err(Rejection::ChargedBack);
+            }
+            if reversed_transaction._type != TransactionType::Deposit
+                || reversed_transaction.client_id != client_id
+                || reversed_transaction.is_disputed()
+            {
+                return Err(Rejection::NotReversible);
+            }
+            let currency = referenced_currency(&transaction, reversed_transaction, config)?;
+            let current_client = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+            // Taking the deposit back needs available funds of at least its amount, whatever the overdraft limit.
+            current_client.in_currency(currency, |current_client| {
+                current_client
+                    .reverse_deposit(reversed_transaction.amount)
+                    .map_err(|_| funds_rejection(current_client))
+            })?;
+            reversed_transaction.mark_reversed();
+            Ok(())
+        }
+        TransactionType::AdminCredit | TransactionType::AdminDebit if !config.allow_admin_ops => {
+            tracing::warn!(
+                client = client_id,
+                tx = transaction_id,
+                "Admin adjustment rejected: admin operations are not allowed"
+            );
+            Err(Rejection::AdminOpsNotAllowed)
+        }
+        // Admin adjustments ignore the lock and are recorded so their IDs are known, but can't be disputed.
+        TransactionType::AdminCredit => {
+            let amount = transaction.amount;
+            let currency = balance_currency(&transaction, config);
+            processed_transactions.insert(transaction_id, transaction);
+            clients
+                .get_or_create(client_id)
+                .in_currency(currency, |client| client.admin_credit(amount));
+            Ok(())
+        }
+        TransactionType::AdminDebit => {
+            let amount = transaction.amount;
+            let currency = balance_currency(&transaction, config);
+            clients
+                .get_mut(client_id)
+                .ok_or(Rejection::UnknownClient)?
+                .in_currency(currency, |client| client.admin_debit(amount))
+                .map_err(|_| Rejection::InsufficientFunds)?;
+            processed_transactions.insert(transaction_id, transaction);
+            Ok(())
+        }
+        TransactionType::Unlock if !config.allow_unlock => {
+            tracing::warn!(
+                client = client_id,
+                tx = transaction_id,
+                "Unlock rejected: unlocking accounts is not allowed"
+            );
+            Err(Rejection::UnlockNotAllowed)
+        }
+        // An unlock never creates a client.
+        TransactionType::Unlock => {
+            clients
+                .get_mut(client_id)
+                .ok_or(Rejection::UnknownClient)?
+                .unlock();
+            Ok(())
+        }
+        TransactionType::Chargeback => {
+            let disputed_transaction = processed_transactions
+                .get_mut(&transaction_id)
+                .ok_or(Rejection::UnknownTransaction)?;
+            check_dispute_transition(
+                disputed_transaction.dispute_state,
+                DisputeState::ChargedBack,
+                config,
+            )?;
+            let currency = referenced_currency(&transaction, disputed_transaction, config)?;
+            let current_client = clients.get_mut(client_id).ok_or(Rejection::UnknownClient)?;
+            // An account is locked by its first chargeback. Later chargebacks leave the account and the dispute alone.
+            if current_client.locked {
+                tracing::warn!(
+                    client = client_id,
+                    tx = transaction_id,
+                    "Chargeback skipped: the account is already locked"
+                );
+                return Err(Rejection::AccountLocked);
+            }
+            let disputed_amount = disputed_transaction.disputed_amount;
+            let credits_chargeback = credits_on_chargeback(disputed_transaction, config);
+            current_client
+                .in_currency(currency, |current_client| {
+                    if credits_chargeback {
+                        current_client.chargeback_withdrawal(disputed_amount)
+                    } else {
+                        current_client.chargeback(disputed_amount)
+                    }
+                })
+                .map_err(|_| Rejection::AccountLocked)?;
+
+            // A charged back transaction is no longer in dispute.
+            disputed_transaction.mark_charged_back();
+            disputed_transaction.record_dispute_event(
+                TransactionType::Chargeback,
+                sequence,
+                transaction.timestamp,
+            );
+            Ok(())
+        }
+    }?;
+    Ok(fee)
+}
+
+// Tells if the settlement of a dispute of `disputed_transaction` credits the client on a chargeback rather than on a
+// resolve, which is the case for withdrawals and transfers under [WithdrawalDisputes::CreditOnChargeback].
+fn credits_on_chargeback(disputed_transaction: &Transaction, config: &Config) -> bool {
+    disputed_transaction.is_withdrawal()
+        && config.withdrawal_disputes == WithdrawalDisputes::CreditOnChargeback
+}
+
+// Checks that a dispute, resolve or chargeback may move a stored transaction from the dispute state `from` to `to`.
+// Disputes may add to a running dispute, which is how partial disputes grow.
+fn check_dispute_transition(
+    from: DisputeState,
+    to: DisputeState,
+    config: &Config,
+) -> Result<(), Rejection> {
+    match (from, to) {
+        (DisputeState::ChargedBack, _) => Err(Rejection::ChargedBack),
+        (DisputeState::Resolved, DisputeState::Disputed) if config.redispute_resolved => Ok(()),
+        (DisputeState::Resolved, _) => Err(Rejection::AlreadyResolved),
+        (_, DisputeState::Disputed) | (DisputeState::Disputed, _) => Ok(()),
+        (DisputeState::Undisputed, _) => Err(Rejection::NotDisputed),
+    }
+}
@@ -0,0 +1,31 @@
+//! Gravenche is a toy payment transaction processor. It reads transactions from a CSV file, applies
+//! them to client accounts and reports the final state of every client.
+
+#[cfg(feature = "runtime")]
+mod checkpoint;
+#[cfg(feature = "runtime")]
+pub mod gravenche;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(any(feature = "runtime", feature = "sync"))]
+mod ledger;
+pub mod metrics;
+pub mod observer;
+pub mod output;
+#[cfg(feature = "runtime")]
+pub mod server;
+pub mod source;
+#[cfg(feature = "sync")]
+pub mod sync;
+pub mod types;
+#[cfg(feature = "runtime")]
+pub mod wal;
+
+#[cfg(feature = "runtime")]
+pub use crate::gravenche::{
+    ClientsHandle, Gravenche, GravencheBuilder, DEFAULT_TRANSACTIONS_ALLOWED, EVENTS_CAPACITY,
+    STDIN_PATH,
+};
+pub use crate::types::error::GravencheError;
@@ -1,38 +1,1194 @@
-use std::{env, io::stdout, path::PathBuf, process};
+use std::{
+    collections::HashSet,
+    env,
+    fs::File,
+    io::{stdout, IsTerminal, Stdout},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
-use gravenche::Gravenche;
+use chrono::{DateTime, Utc};
+use gravenche::{
+    metrics::{serve_metrics, Rejection},
+    output::write_table,
+    types::{
+        client::ClientId,
+        config::{
+            Column, Compression, Config, FeePolicy, InputFormat, NumberLocale, OutputFormat,
+            VelocityRule, WithdrawalDisputes,
+        },
+        currency::Currency,
+        other::ProcessingSummary,
+        transaction::parse_timestamp,
+    },
+    Gravenche, GravencheBuilder, DEFAULT_TRANSACTIONS_ALLOWED, STDIN_PATH,
+};
+use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
 
-mod gravenche;
-mod types;
+/// Command line arguments accepted by the application.
+struct CliArgs {
+    /// Names of the csv files containing transactions, in processing order.
+    csv_filenames: Vec<String>,
+    /// Glob patterns matching csv files, processed after `csv_filenames`.
+    input_patterns: Vec<String>,
+    /// Allow glob patterns which match no files.
+    allow_empty: bool,
+    /// Show only clients whose account is locked.
+    only_locked: bool,
+    /// Show the dispute and chargeback counts of each client and whether its funds were ever negative.
+    extended: bool,
+    /// Columns of the client table in the order they are shown.
+    columns: Vec<Column>,
+    /// Show held funds as a negative amount included in the available funds.
+    signed_held: bool,
+    /// Number of clients with the highest total balance to show. Set by the `summary` subcommand.
+    top: Option<usize>,
+    /// Path of the file to export processed transactions to.
+    export_transactions: Option<PathBuf>,
+    /// Path of the file to export open disputes to.
+    export_disputes: Option<PathBuf>,
+    /// Path of the file to export flagged transactions to.
+    export_flagged: Option<PathBuf>,
+    /// Path of the file to save the final state to.
+    save_state: Option<PathBuf>,
+    /// Path of a state saved by an earlier run to continue from.
+    load_state: Option<PathBuf>,
+    /// Path of the SQLite database to export the final state to.
+    export_sqlite: Option<PathBuf>,
+    /// Replace existing export files which would be refused otherwise.
+    force: bool,
+    /// Number of transactions allowed to be pushed in queue.
+    buffer: i32,
+    /// Format of the input files.
+    input_format: InputFormat,
+    /// Format in which client data is shown.
+    output_format: OutputFormat,
+    /// Compression of the input files. Detected from each file when not set.
+    compression: Option<Compression>,
+    /// Field delimiter of CSV input.
+    delimiter: u8,
+    /// Character starting comment lines in CSV input.
+    comment: Option<u8>,
+    /// Number of decimal places of amounts.
+    decimals: u32,
+    /// Way amounts are written in the table.
+    locale: NumberLocale,
+    /// Maximum amount a client may have held in dispute.
+    max_held: Option<f32>,
+    /// Currency of transactions without a currency.
+    base_currency: Option<Currency>,
+    /// Amount every client may overdraw its available funds by.
+    overdraft_limit: Option<f32>,
+    /// Amount from which deposits and withdrawals are flagged.
+    flag_threshold: Option<f32>,
+    /// Keep reading transactions appended to the input file until interrupted.
+    follow: bool,
+    /// Seconds between client tables shown while following the input file.
+    snapshot_interval: Option<u64>,
+    /// Directory to run as a spool service on instead of processing csv files.
+    spool_dir: Option<PathBuf>,
+    /// Address to accept transactions on. Set by the `serve` subcommand.
+    listen: Option<String>,
+    /// Answer malformed lines sent over TCP with an error. When processing files, exit with [STRICT_EXIT_CODE] if a
+    /// row was skipped or a transaction referenced an unknown one.
+    strict: bool,
+    /// Stop at the first row which isn't a valid transaction.
+    fail_fast: bool,
+    /// Print the number of rows read to stderr every [PROGRESS_ROWS] rows.
+    progress: bool,
+    /// Check the invariants of every client after processing.
+    verify: bool,
+    /// Address to serve the HTTP API on.
+    http: Option<String>,
+    /// Address to serve Prometheus metrics on.
+    metrics_addr: Option<String>,
+    /// Kafka brokers to consume transactions from, as a comma separated list of `host:port`.
+    kafka_brokers: Option<String>,
+    /// Kafka topic to consume transactions from.
+    kafka_topic: Option<String>,
+    /// Kafka consumer group.
+    kafka_group: String,
+    /// Client IDs whose transactions are skipped.
+    reserved_ids: Vec<RangeInclusive<ClientId>>,
+    /// Client IDs allowed to transact. All clients are allowed when not set.
+    allowed_ids: Option<Vec<RangeInclusive<ClientId>>>,
+    /// Transaction IDs to process. Set by `--min-tx` and `--max-tx`.
+    tx_window: Option<RangeInclusive<u32>>,
+    /// Skip rows with an earlier timestamp.
+    since: Option<DateTime<Utc>>,
+    /// Skip rows with this or a later timestamp.
+    until: Option<DateTime<Utc>>,
+    /// Process the transactions of every file in timestamp order.
+    sort_by_timestamp: bool,
+    /// Apply `unlock` transactions.
+    allow_unlock: bool,
+    /// Apply `admin_credit` and `admin_debit` transactions.
+    allow_admin_ops: bool,
+    /// Skip transactions whose ID was applied before.
+    skip_replays: bool,
+    /// How disputes of withdrawals are handled.
+    withdrawal_disputes: WithdrawalDisputes,
+    /// Allow disputes of transactions whose dispute was resolved.
+    redispute_resolved: bool,
+    /// Show clients whose first transaction is a withdrawal.
+    create_on_withdrawal: bool,
+    /// Fee charged on every withdrawal.
+    withdrawal_fee: Option<FeePolicy>,
+    /// Limit on the number of withdrawals of a client.
+    velocity_limit: Option<VelocityRule>,
+    /// Maximum number of rows read from the csv files.
+    max_rows: Option<usize>,
+    /// Write-ahead log of the handled transactions.
+    wal: Option<PathBuf>,
+    /// Read the input files through a memory map.
+    mmap: bool,
+    /// Path of the checkpoint written while processing files.
+    checkpoint: Option<PathBuf>,
+    /// Number of applied transactions between two checkpoints.
+    checkpoint_every: u64,
+    /// Continue from the checkpoint instead of starting from the beginning.
+    resume: bool,
+}
+
+/// Exit code with `--strict` when rows were skipped or transactions referenced unknown ones.
+const STRICT_EXIT_CODE: i32 = 2;
+
+/// Exit code with `--verify` when a client violates an invariant.
+const VERIFY_EXIT_CODE: i32 = 3;
+
+/// Exit code when processing files was interrupted with Ctrl-C, after the partial output was written.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Number of applied transactions between two checkpoints when `--checkpoint-every` is not supplied.
+const DEFAULT_CHECKPOINT_EVERY: u64 = 100000;
+
+/// Number of rows read between two progress lines with `--progress`.
+const PROGRESS_ROWS: u64 = 100000;
+
+/// Number of decimal places of amounts when `--decimals` is not supplied.
+const DEFAULT_DECIMALS: u32 = 4;
+
+/// Largest number of decimal places accepted by `--decimals`. Amounts are `f32`, more decimals would only show noise.
+const MAX_DECIMALS: u32 = 8;
+
+/// Kafka consumer group used when `--kafka-group` is not supplied.
+const DEFAULT_KAFKA_GROUP: &str = "gravenche";
+
+/// Number of clients shown by the `summary` subcommand when `--top` is not supplied.
+const DEFAULT_TOP_CLIENTS: usize = 10;
+
+/// Returns the value following an option. Exits the process with code 1 if the value is missing.
+fn get_option_value(args: &mut impl Iterator<Item = String>, option: &str) -> String {
+    match args.next() {
+        Some(value) => value,
+        None => {
+            println!("ERROR: {} requires a value", option);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parses a comma separated list of client IDs and inclusive ranges of client IDs, e.g. `0,9000-9999`. Returns
+/// `None` if any of them is invalid.
+fn parse_id_ranges(value: &str) -> Option<Vec<RangeInclusive<ClientId>>> {
+    value
+        .split(',')
+        .map(|part| match part.trim().split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+                (start <= end).then_some(start..=end)
+            }
+            None => part.trim().parse().ok().map(|id| id..=id),
+        })
+        .collect()
+}
+
+/// Parses a withdrawal fee, either a flat amount such as `0.5` or a percentage of the amount such as `1.5%`. Returns
+/// `None` if it is invalid or negative.
+fn parse_fee_policy(value: &str) -> Option<FeePolicy> {
+    let fee_policy = match value.trim().strip_suffix('%') {
+        Some(rate) => FeePolicy::Percent(rate.trim().parse().ok()?),
+        None => FeePolicy::Flat(value.trim().parse().ok()?),
+    };
+    match fee_policy {
+        FeePolicy::Flat(fee) | FeePolicy::Percent(fee) if fee >= 0.0 => Some(fee_policy),
+        _ => None,
+    }
+}
+
+/// Parses a velocity limit, a number of withdrawals such as `5` optionally followed by a window of transactions such as
+/// `5/1000`. Returns `None` if either is invalid or zero.
+fn parse_velocity_rule(value: &str) -> Option<VelocityRule> {
+    let (max_withdrawals, window_rows) = match value.trim().split_once('/') {
+        Some((max_withdrawals, window_rows)) => {
+            (max_withdrawals, Some(window_rows.trim().parse().ok()?))
+        }
+        None => (value, None),
+    };
+    let rule = VelocityRule {
+        max_withdrawals: max_withdrawals.trim().parse().ok()?,
+        window_rows,
+    };
+    (rule.max_withdrawals > 0 && rule.window_rows != Some(0)).then_some(rule)
+}
+
+/// Parses column names separated by commas, e.g. `client,total`. Returns `None` if a name is unknown or there are
+/// none.
+fn parse_columns(value: &str) -> Option<Vec<Column>> {
+    let columns: Vec<Column> = value
+        .split(',')
+        .map(|name| name.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    (!columns.is_empty()).then_some(columns)
+}
+
+/// Processes command line arguments. Exits the process with code 1 if csv filename is missing or an option is unknown.
+fn get_command_line_args() -> CliArgs {
+    let mut csv_filenames = Vec::new();
+    let mut input_patterns = Vec::new();
+    let mut allow_empty = false;
+    let mut only_locked = false;
+    let mut extended = false;
+    let mut columns = Column::DEFAULT.to_vec();
+    let mut signed_held = false;
+    let mut is_summary = false;
+    let mut top = None;
+    let mut export_transactions = None;
+    let mut export_disputes = None;
+    let mut export_flagged = None;
+    let mut save_state = None;
+    let mut load_state = None;
+    let mut export_sqlite: Option<PathBuf> = None;
+    let mut force = false;
+    let mut buffer = DEFAULT_TRANSACTIONS_ALLOWED;
+    let mut input_format = InputFormat::Csv;
+    let mut output_sqlite = None;
+    let mut output_parquet = None;
+    let mut compression = None;
+    let mut delimiter = b',';
+    let mut comment = None;
+    let mut decimals = DEFAULT_DECIMALS;
+    let mut locale = NumberLocale::Plain;
+    let mut max_held = None;
+    let mut base_currency = None;
+    let mut overdraft_limit = None;
+    let mut flag_threshold = None;
+    let mut follow = false;
+    let mut snapshot_interval = None;
+    let mut spool_dir = None;
+    let mut is_serve = false;
+    let mut listen = None;
+    let mut strict = false;
+    let mut verify = false;
+    let mut fail_fast = false;
+    let mut progress = false;
+    let mut http = None;
+    let mut metrics_addr = None;
+    let mut kafka_brokers = None;
+    let mut kafka_topic = None;
+    let mut kafka_group = String::from(DEFAULT_KAFKA_GROUP);
+    let mut reserved_ids = Vec::new();
+    let mut allowed_ids: Option<Vec<RangeInclusive<ClientId>>> = None;
+    let mut min_tx = None;
+    let mut max_tx = None;
+    let mut since = None;
+    let mut until = None;
+    let mut sort_by_timestamp = false;
+    let mut allow_unlock = false;
+    let mut allow_admin_ops = false;
+    let mut skip_replays = false;
+    let mut withdrawal_disputes = WithdrawalDisputes::Rejected;
+    let mut redispute_resolved = false;
+    let mut create_on_withdrawal = false;
+    let mut withdrawal_fee = None;
+    let mut velocity_limit = None;
+    let mut max_rows = None;
+    let mut wal = None;
+    let mut mmap = false;
+    let mut checkpoint = None;
+    let mut checkpoint_every = DEFAULT_CHECKPOINT_EVERY;
+    let mut resume = false;
 
-/// Processes command line arguments. Exits the process with code 1 if argument count is less than 2.
-fn get_command_line_args() -> Vec<String> {
     // Process command line args
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let mut args = env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("summary") => is_summary = true,
+        Some("serve") => is_serve = true,
+        _ => {}
+    }
+    if is_summary || is_serve {
+        args.next();
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--only-locked" => only_locked = true,
+            "--extended" => extended = true,
+            "--signed-held" => signed_held = true,
+            "--columns" => match parse_columns(&get_option_value(&mut args, &arg)) {
+                Some(selected) => columns = selected,
+                None => {
+                    println!(
+                        "ERROR: --columns requires column names separated by commas, e.g. client,total"
+                    );
+                    process::exit(1);
+                }
+            },
+            "--input" => input_patterns.push(get_option_value(&mut args, &arg)),
+            "--allow-empty" => allow_empty = true,
+            "--top" if is_summary => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => top = Some(n),
+                None => {
+                    println!("ERROR: --top requires a number of clients");
+                    process::exit(1);
+                }
+            },
+            "--export-transactions" => {
+                export_transactions = Some(PathBuf::from(get_option_value(&mut args, &arg)))
+            }
+            "--export-disputes" => {
+                export_disputes = Some(PathBuf::from(get_option_value(&mut args, &arg)))
+            }
+            "--export-flagged" => {
+                export_flagged = Some(PathBuf::from(get_option_value(&mut args, &arg)))
+            }
+            "--save-state" => save_state = Some(PathBuf::from(get_option_value(&mut args, &arg))),
+            "--load-state" => load_state = Some(PathBuf::from(get_option_value(&mut args, &arg))),
+            "--buffer" => match get_option_value(&mut args, &arg).parse() {
+                Ok(n) if n > 0 => buffer = n,
+                _ => {
+                    println!("ERROR: --buffer requires a positive number of transactions");
+                    process::exit(1);
+                }
+            },
+            "--locale" => match get_option_value(&mut args, &arg).as_str() {
+                "en" => locale = NumberLocale::En,
+                "de" => locale = NumberLocale::De,
+                "fr" => locale = NumberLocale::Fr,
+                _ => {
+                    println!("ERROR: --locale must be en, de or fr");
+                    process::exit(1);
+                }
+            },
+            "--input-format" => match get_option_value(&mut args, &arg).as_str() {
+                "csv" => input_format = InputFormat::Csv,
+                "ndjson" => input_format = InputFormat::Ndjson,
+                _ => {
+                    println!("ERROR: --input-format must be csv or ndjson");
+                    process::exit(1);
+                }
+            },
+            "--export-sqlite" if cfg!(feature = "sqlite") => {
+                export_sqlite = Some(PathBuf::from(get_option_value(&mut args, &arg)))
+            }
+            "--force" => force = true,
+            "--output-parquet" if cfg!(feature = "parquet") => {
+                match get_option_value(&mut args, &arg) {
+                    path if path == STDIN_PATH => {
+                        println!(
+                            "ERROR: --output-parquet requires a file, not the standard output"
+                        );
+                        process::exit(1);
+                    }
+                    path => output_parquet = Some(PathBuf::from(path)),
+                }
+            }
+            "--output-sqlite" if cfg!(feature = "sqlite") => {
+                output_sqlite = Some(PathBuf::from(get_option_value(&mut args, &arg)))
+            }
+            "--compression" => match get_option_value(&mut args, &arg).as_str() {
+                "none" => compression = Some(Compression::None),
+                "gzip" => compression = Some(Compression::Gzip),
+                #[cfg(feature = "zstd")]
+                "zstd" => compression = Some(Compression::Zstd),
+                value => {
+                    println!("ERROR: Unsupported compression {}", value);
+                    process::exit(1);
+                }
+            },
+            "--delimiter" => match get_option_value(&mut args, &arg).as_str() {
+                "," => delimiter = b',',
+                ";" => delimiter = b';',
+                "\\t" | "\t" => delimiter = b'\t',
+                value => {
+                    println!("ERROR: Unsupported delimiter {}", value);
+                    process::exit(1);
+                }
+            },
+            "--comment" => match get_option_value(&mut args, &arg).as_bytes() {
+                &[byte] if byte.is_ascii() => comment = Some(byte),
+                _ => {
+                    println!("ERROR: The comment character must be a single ASCII character");
+                    process::exit(1);
+                }
+            },
+            "--decimals" => match get_option_value(&mut args, &arg).parse() {
+                Ok(value) if value <= MAX_DECIMALS => decimals = value,
+                _ => {
+                    println!(
+                        "ERROR: --decimals requires a number from 0 to {}",
+                        MAX_DECIMALS
+                    );
+                    process::exit(1);
+                }
+            },
+            "--max-held" => match get_option_value(&mut args, &arg).parse() {
+                Ok(amount) if amount >= 0.0 => max_held = Some(amount),
+                _ => {
+                    println!("ERROR: --max-held requires a non-negative amount");
+                    process::exit(1);
+                }
+            },
+            "--base-currency" => {
+                match get_option_value(&mut args, &arg).parse() {
+                    Ok(currency) => base_currency = Some(currency),
+                    Err(_) => {
+                        println!("ERROR: --base-currency requires a three letter currency code, e.g. EUR");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--overdraft-limit" => match get_option_value(&mut args, &arg).parse() {
+                Ok(amount) if amount >= 0.0 => overdraft_limit = Some(amount),
+                _ => {
+                    println!("ERROR: --overdraft-limit requires a non-negative amount");
+                    process::exit(1);
+                }
+            },
+            "--flag-threshold" => match get_option_value(&mut args, &arg).parse() {
+                Ok(amount) if amount > 0.0 => flag_threshold = Some(amount),
+                _ => {
+                    println!("ERROR: --flag-threshold requires a positive amount");
+                    process::exit(1);
+                }
+            },
+            "--listen" if is_serve => listen = Some(get_option_value(&mut args, &arg)),
+            "--strict" => strict = true,
+            "--verify" => verify = true,
+            "--fail-fast" => fail_fast = true,
+            "--progress" => progress = true,
+            "--reserved-ids" => match parse_id_ranges(&get_option_value(&mut args, &arg)) {
+                Some(ranges) => reserved_ids.extend(ranges),
+                None => {
+                    println!(
+                        "ERROR: --reserved-ids requires client IDs or ranges, e.g. 0,9000-9999"
+                    );
+                    process::exit(1);
+                }
+            },
+            "--allowed-ids" => match parse_id_ranges(&get_option_value(&mut args, &arg)) {
+                Some(ranges) => allowed_ids.get_or_insert_with(Vec::new).extend(ranges),
+                None => {
+                    println!("ERROR: --allowed-ids requires client IDs or ranges, e.g. 1-500,900");
+                    process::exit(1);
+                }
+            },
+            "--min-tx" => match get_option_value(&mut args, &arg).parse() {
+                Ok(id) => min_tx = Some(id),
+                Err(_) => {
+                    println!("ERROR: --min-tx requires a transaction ID");
+                    process::exit(1);
+                }
+            },
+            "--max-tx" => match get_option_value(&mut args, &arg).parse() {
+                Ok(id) => max_tx = Some(id),
+                Err(_) => {
+                    println!("ERROR: --max-tx requires a transaction ID");
+                    process::exit(1);
+                }
+            },
+            "--since" => match parse_timestamp(&get_option_value(&mut args, &arg)) {
+                Ok(timestamp) => since = Some(timestamp),
+                Err(_) => {
+                    println!("ERROR: --since requires an RFC 3339 timestamp or seconds since the Unix epoch");
+                    process::exit(1);
+                }
+            },
+            "--until" => match parse_timestamp(&get_option_value(&mut args, &arg)) {
+                Ok(timestamp) => until = Some(timestamp),
+                Err(_) => {
+                    println!("ERROR: --until requires an RFC 3339 timestamp or seconds since the Unix epoch");
+                    process::exit(1);
+                }
+            },
+            "--sort-by-timestamp" => sort_by_timestamp = true,
+            "--allow-unlock" => allow_unlock = true,
+            "--allow-admin-ops" => allow_admin_ops = true,
+            "--skip-replays" => skip_replays = true,
+            "--dispute-withdrawals" => withdrawal_disputes = WithdrawalDisputes::CreditOnResolve,
+            "--dispute-withdrawals-chargeback" => {
+                withdrawal_disputes = WithdrawalDisputes::CreditOnChargeback
+            }
+            "--redispute-resolved" => redispute_resolved = true,
+            "--withdrawal-fee" => match parse_fee_policy(&get_option_value(&mut args, &arg)) {
+                Some(fee_policy) => withdrawal_fee = Some(fee_policy),
+                None => {
+                    println!("ERROR: --withdrawal-fee requires a non-negative amount or percentage, e.g. 0.5 or 1.5%");
+                    process::exit(1);
+                }
+            },
+            "--velocity-limit" => match parse_velocity_rule(&get_option_value(&mut args, &arg)) {
+                Some(rule) => velocity_limit = Some(rule),
+                None => {
+                    println!("ERROR: --velocity-limit requires a number of withdrawals and optionally a window of transactions, e.g. 5 or 5/1000");
+                    process::exit(1);
+                }
+            },
+            "--create-on-withdrawal" => create_on_withdrawal = true,
+            "--max-rows" => match get_option_value(&mut args, &arg).parse() {
+                Ok(rows) if rows > 0 => max_rows = Some(rows),
+                _ => {
+                    println!("ERROR: --max-rows requires a positive number of rows");
+                    process::exit(1);
+                }
+            },
+            "--wal" => wal = Some(PathBuf::from(get_option_value(&mut args, &arg))),
+            "--mmap" if cfg!(feature = "mmap") => mmap = true,
+            "--checkpoint" => checkpoint = Some(PathBuf::from(get_option_value(&mut args, &arg))),
+            "--checkpoint-every" => match get_option_value(&mut args, &arg).parse() {
+                Ok(n) if n > 0 => checkpoint_every = n,
+                _ => {
+                    println!(
+                        "ERROR: --checkpoint-every requires a positive number of transactions"
+                    );
+                    process::exit(1);
+                }
+            },
+            "--resume" => resume = true,
+            "--metrics-addr" => metrics_addr = Some(get_option_value(&mut args, &arg)),
+            "--http" if cfg!(feature = "http") => http = Some(get_option_value(&mut args, &arg)),
+            "--kafka-brokers" if cfg!(feature = "kafka") => {
+                kafka_brokers = Some(get_option_value(&mut args, &arg))
+            }
+            "--kafka-topic" if cfg!(feature = "kafka") => {
+                kafka_topic = Some(get_option_value(&mut args, &arg))
+            }
+            "--kafka-group" if cfg!(feature = "kafka") => {
+                kafka_group = get_option_value(&mut args, &arg)
+            }
+            "--follow" => follow = true,
+            "--spool" => spool_dir = Some(PathBuf::from(get_option_value(&mut args, &arg))),
+            "--snapshot-interval" => match get_option_value(&mut args, &arg).parse() {
+                Ok(seconds) if seconds > 0 => snapshot_interval = Some(seconds),
+                _ => {
+                    println!("ERROR: --snapshot-interval requires a positive number of seconds");
+                    process::exit(1);
+                }
+            },
+            option if option.starts_with("--") => {
+                println!("ERROR: Unknown option {}", option);
+                process::exit(1);
+            }
+            _ => csv_filenames.push(arg),
+        }
+    }
+
+    if is_serve {
+        if listen.is_none() {
+            println!("ERROR: serve requires --listen <address>");
+            process::exit(1);
+        }
+        if spool_dir.is_some() || follow || !csv_filenames.is_empty() || !input_patterns.is_empty()
+        {
+            println!("ERROR: serve can't be combined with csv files, --spool or --follow");
+            process::exit(1);
+        }
+    }
+
+    if http.is_some()
+        && (is_serve
+            || spool_dir.is_some()
+            || follow
+            || !csv_filenames.is_empty()
+            || !input_patterns.is_empty())
+    {
+        println!("ERROR: --http can't be combined with csv files, serve, --spool or --follow");
+        process::exit(1);
+    }
+
+    if kafka_brokers.is_some() != kafka_topic.is_some() {
+        println!("ERROR: --kafka-brokers and --kafka-topic must be supplied together");
+        process::exit(1);
+    }
+
+    if kafka_brokers.is_some()
+        && (is_serve
+            || http.is_some()
+            || spool_dir.is_some()
+            || follow
+            || !csv_filenames.is_empty()
+            || !input_patterns.is_empty())
+    {
+        println!("ERROR: --kafka-brokers can't be combined with csv files, serve, --http, --spool or --follow");
+        process::exit(1);
+    }
+
+    if spool_dir.is_some() && (follow || !csv_filenames.is_empty() || !input_patterns.is_empty()) {
+        println!("ERROR: --spool can't be combined with csv files or --follow");
+        process::exit(1);
+    }
+
+    if csv_filenames.is_empty()
+        && input_patterns.is_empty()
+        && spool_dir.is_none()
+        && !is_serve
+        && http.is_none()
+        && kafka_brokers.is_none()
+    {
         println!("ERROR: Please provide csv filename");
         process::exit(1);
     }
-    args
+
+    let tx_window = match (min_tx, max_tx) {
+        (None, None) => None,
+        (min_tx, max_tx) => Some(min_tx.unwrap_or(0)..=max_tx.unwrap_or(u32::MAX)),
+    };
+    if tx_window.as_ref().is_some_and(|window| window.is_empty()) {
+        println!("ERROR: --min-tx must not be greater than --max-tx");
+        process::exit(1);
+    }
+    if since.is_some_and(|since| until.is_some_and(|until| since >= until)) {
+        println!("ERROR: --since must be before --until");
+        process::exit(1);
+    }
+
+    if resume && checkpoint.is_none() {
+        println!("ERROR: --resume requires --checkpoint <path>");
+        process::exit(1);
+    }
+
+    if resume
+        && (is_serve || http.is_some() || kafka_brokers.is_some() || spool_dir.is_some() || follow)
+    {
+        println!("ERROR: --resume can't be combined with serve, --http, --kafka-brokers, --spool or --follow");
+        process::exit(1);
+    }
+
+    if follow && csv_filenames.len() + input_patterns.len() != 1 {
+        println!("ERROR: --follow requires exactly one csv file");
+        process::exit(1);
+    }
+
+    if follow && compression.is_some_and(|compression| compression != Compression::None) {
+        println!("ERROR: --follow can't be combined with compressed input");
+        process::exit(1);
+    }
+
+    // An existing database is refused before processing rather than after.
+    if let Some(path) = &export_sqlite {
+        if path.exists() && !force {
+            println!(
+                "ERROR: {} already exists, use --force to replace it",
+                path.display()
+            );
+            process::exit(1);
+        }
+    }
+
+    if is_summary && top.is_none() {
+        top = Some(DEFAULT_TOP_CLIENTS);
+    }
+
+    if output_sqlite.is_some() && output_parquet.is_some() {
+        println!("ERROR: --output-sqlite can't be combined with --output-parquet");
+        process::exit(1);
+    }
+    // The options are only accepted with their feature, so the paths are unset without it.
+    let output_format = match (output_sqlite, output_parquet) {
+        #[cfg(feature = "sqlite")]
+        (Some(path), _) => OutputFormat::Sqlite(path),
+        #[cfg(feature = "parquet")]
+        (_, Some(path)) => OutputFormat::Parquet(path),
+        _ => OutputFormat::Table,
+    };
+
+    CliArgs {
+        csv_filenames,
+        input_patterns,
+        allow_empty,
+        only_locked,
+        extended,
+        columns,
+        signed_held,
+        top,
+        export_transactions,
+        export_disputes,
+        export_flagged,
+        save_state,
+        load_state,
+        export_sqlite,
+        force,
+        buffer,
+        input_format,
+        output_format,
+        compression,
+        delimiter,
+        comment,
+        decimals,
+        locale,
+        max_held,
+        base_currency,
+        overdraft_limit,
+        flag_threshold,
+        follow,
+        snapshot_interval,
+        spool_dir,
+        listen,
+        strict,
+        verify,
+        fail_fast,
+        progress,
+        http,
+        metrics_addr,
+        kafka_brokers,
+        kafka_topic,
+        kafka_group,
+        reserved_ids,
+        allowed_ids,
+        tx_window,
+        since,
+        until,
+        sort_by_timestamp,
+        allow_unlock,
+        allow_admin_ops,
+        skip_replays,
+        withdrawal_disputes,
+        redispute_resolved,
+        create_on_withdrawal,
+        withdrawal_fee,
+        velocity_limit,
+        max_rows,
+        wal,
+        mmap,
+        checkpoint,
+        checkpoint_every,
+        resume,
+    }
 }
 
 /// Generates absolute path for supplied csv filename. Also, it verifies if filename exists.
+/// [STDIN_PATH] is returned as is because it refers to the standard input.
 fn get_csv_path(filename: &str) -> anyhow::Result<PathBuf> {
+    if filename == STDIN_PATH {
+        return Ok(PathBuf::from(filename));
+    }
     Ok(std::path::Path::new(filename).canonicalize()?)
 }
 
+/// Expands glob patterns into matching filenames sorted lexicographically. Exits the process with code 1 if a
+/// pattern is invalid or matches no files unless `allow_empty` is set.
+fn expand_input_patterns(patterns: &[String], allow_empty: bool) -> Vec<String> {
+    let mut filenames = Vec::new();
+    for pattern in patterns {
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths,
+            Err(error) => {
+                println!("ERROR: Invalid pattern {}: {}", pattern, error);
+                process::exit(1);
+            }
+        };
+
+        let matched: Vec<String> = paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .map(|path| path.display().to_string())
+            .collect();
+        if matched.is_empty() && !allow_empty {
+            println!("ERROR: Pattern {} matched no files", pattern);
+            process::exit(1);
+        }
+        filenames.extend(matched);
+    }
+
+    // Sorting keeps chronologically named files in order.
+    filenames.sort();
+    filenames
+}
+
+/// Returns absolute paths of all the csv files to process in order. A file matched more than once is processed only
+/// the first time.
+fn get_csv_paths(args: &CliArgs) -> Vec<PathBuf> {
+    let expanded = expand_input_patterns(&args.input_patterns, args.allow_empty);
+
+    let mut seen = HashSet::new();
+    args.csv_filenames
+        .iter()
+        .chain(expanded.iter())
+        .map(|filename| get_csv_path(filename).unwrap())
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// Serves the HTTP API on `address` until `shutdown` completes.
+#[cfg(feature = "http")]
+async fn serve_http<F: std::future::Future<Output = ()> + Send + 'static>(
+    gravenche: &mut Gravenche<Stdout>,
+    address: &str,
+    shutdown: F,
+) -> anyhow::Result<ProcessingSummary> {
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!("Serving HTTP on {}", listener.local_addr()?);
+    Ok(gravenche.serve_http(listener, shutdown).await?)
+}
+
+/// The HTTP API requires the `http` feature. `--http` is rejected without it, so this is never called.
+#[cfg(not(feature = "http"))]
+async fn serve_http<F>(
+    _gravenche: &mut Gravenche<Stdout>,
+    _address: &str,
+    _shutdown: F,
+) -> anyhow::Result<ProcessingSummary> {
+    anyhow::bail!("The HTTP API requires the http feature")
+}
+
+/// Consumes transactions from a Kafka topic until `shutdown` completes.
+#[cfg(feature = "kafka")]
+async fn consume_kafka<F: std::future::Future<Output = ()>>(
+    gravenche: &mut Gravenche<Stdout>,
+    brokers: &str,
+    topic: &str,
+    group: &str,
+    shutdown: F,
+) -> anyhow::Result<ProcessingSummary> {
+    let consumer = gravenche::kafka::KafkaConsumer::new(brokers, topic, group)?;
+    tracing::info!("Consuming {} from {} as {}", topic, brokers, group);
+    Ok(gravenche.consume(consumer, shutdown).await?)
+}
+
+/// The Kafka input requires the `kafka` feature. Its options are rejected without it, so this is never called.
+#[cfg(not(feature = "kafka"))]
+async fn consume_kafka<F>(
+    _gravenche: &mut Gravenche<Stdout>,
+    _brokers: &str,
+    _topic: &str,
+    _group: &str,
+    _shutdown: F,
+) -> anyhow::Result<ProcessingSummary> {
+    anyhow::bail!("The Kafka input requires the kafka feature")
+}
+
+/// Exports the processed transactions to the Parquet file at `path`.
+#[cfg(feature = "parquet")]
+async fn export_transactions_parquet(
+    gravenche: &Gravenche<Stdout>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    Ok(gravenche.export_transactions_parquet(path).await?)
+}
+
+/// Parquet requires the `parquet` feature.
+#[cfg(not(feature = "parquet"))]
+async fn export_transactions_parquet(
+    _gravenche: &Gravenche<Stdout>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "Exporting transactions to {} requires the parquet feature",
+        path.display()
+    )
+}
+
+/// Exports the final state to the SQLite database at `path`.
+#[cfg(feature = "sqlite")]
+async fn export_sqlite(
+    gravenche: &Gravenche<Stdout>,
+    path: &Path,
+    replace: bool,
+) -> anyhow::Result<()> {
+    Ok(gravenche.export_sqlite(path, replace).await?)
+}
+
+/// The SQLite export requires the `sqlite` feature. `--export-sqlite` is rejected without it, so this is never called.
+#[cfg(not(feature = "sqlite"))]
+async fn export_sqlite(
+    _gravenche: &Gravenche<Stdout>,
+    _path: &Path,
+    _replace: bool,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The SQLite export requires the sqlite feature")
+}
+
+/// Sets whether the input files are read through a memory map.
+#[cfg(feature = "mmap")]
+fn mmap(builder: GravencheBuilder<Stdout>, mmap: bool) -> GravencheBuilder<Stdout> {
+    builder.mmap(mmap)
+}
+
+/// Memory mapped input requires the `mmap` feature. `--mmap` is rejected without it, so the builder is unchanged.
+#[cfg(not(feature = "mmap"))]
+fn mmap(builder: GravencheBuilder<Stdout>, _mmap: bool) -> GravencheBuilder<Stdout> {
+    builder
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Process command line args
     let args = get_command_line_args();
 
-    // Get absolute path of CSV filename.
-    let csv_filepath = get_csv_path(&args[1]).unwrap();
+    // Services read their input from elsewhere instead of csv files.
+    let is_service = args.spool_dir.is_some()
+        || args.listen.is_some()
+        || args.http.is_some()
+        || args.kafka_brokers.is_some();
+    let is_long_running = is_service || args.follow;
+
+    // Log to stderr so the output table on stdout stays clean. RUST_LOG overrides the default level. Long running
+    // modes also log what they process.
+    let default_level = if is_long_running { "info" } else { "warn" };
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+        )
+        .init();
+
+    // Get absolute paths of CSV filenames.
+    let mut csv_filepaths = get_csv_paths(&args).into_iter();
 
     let mut _stdout = stdout();
-    let mut gravenche = Gravenche::new(csv_filepath, 100000, _stdout);
 
-    gravenche.start().await?;
-    gravenche.show_output().await?;
+    // Patterns allowed to be empty may leave nothing to process.
+    if csv_filepaths.len() == 0 && !is_service {
+        let config = Config {
+            extended_output: args.extended,
+            columns: args.columns,
+            signed_held: args.signed_held,
+            ..Default::default()
+        };
+        write_table(&mut _stdout, [], &config)?;
+        return Ok(());
+    }
+
+    // Services don't have a csv file.
+    let first_path = csv_filepaths.next().unwrap_or_default();
+    let mut builder = Gravenche::builder(first_path, _stdout)
+        .transactions_allowed(args.buffer)
+        .only_locked(args.only_locked)
+        .extended_output(args.extended)
+        .columns(args.columns)
+        .signed_held(args.signed_held)
+        .input_format(args.input_format)
+        .output_format(args.output_format)
+        .delimiter(args.delimiter)
+        .decimals(args.decimals)
+        .locale(args.locale)
+        .strict(args.strict)
+        .fail_fast(args.fail_fast)
+        .reserved_client_ids(args.reserved_ids)
+        .order_by_timestamp(args.sort_by_timestamp)
+        .allow_unlock(args.allow_unlock)
+        .allow_admin_ops(args.allow_admin_ops)
+        .skip_replays(args.skip_replays)
+        .withdrawal_disputes(args.withdrawal_disputes)
+        .redispute_resolved(args.redispute_resolved)
+        .create_on_withdrawal(args.create_on_withdrawal);
+    if args.progress {
+        builder = builder.progress(PROGRESS_ROWS, |rows| eprintln!("{} rows read", rows));
+    }
+    if let Some(seconds) = args.snapshot_interval {
+        builder = builder.snapshot_interval(Duration::from_secs(seconds));
+    }
+    if let Some(max_rows) = args.max_rows {
+        builder = builder.max_rows(max_rows);
+    }
+    if let Some(wal) = args.wal {
+        builder = builder.wal(wal);
+    }
+    builder = mmap(builder, args.mmap);
+    if let Some(checkpoint) = args.checkpoint {
+        builder = builder.checkpoint(checkpoint, args.checkpoint_every);
+    }
+    if let Some(max_held) = args.max_held {
+        builder = builder.max_held_per_client(max_held);
+    }
+    if let Some(base_currency) = args.base_currency {
+        builder = builder.base_currency(base_currency);
+    }
+    if let Some(overdraft_limit) = args.overdraft_limit {
+        builder = builder.overdraft_limit(overdraft_limit);
+    }
+    if let Some(flag_threshold) = args.flag_threshold {
+        builder = builder.flag_threshold(flag_threshold);
+    }
+    if let Some(allowed_ids) = args.allowed_ids {
+        builder = builder.allowed_client_ids(allowed_ids);
+    }
+    if let Some(tx_window) = args.tx_window {
+        builder = builder.tx_window(tx_window);
+    }
+    if let Some(since) = args.since {
+        builder = builder.since(since);
+    }
+    if let Some(until) = args.until {
+        builder = builder.until(until);
+    }
+    if let Some(fee_policy) = args.withdrawal_fee {
+        builder = builder.fee_policy(fee_policy);
+    }
+    if let Some(velocity_limit) = args.velocity_limit {
+        builder = builder.velocity_limit(velocity_limit);
+    }
+    if let Some(comment) = args.comment {
+        builder = builder.comment(comment);
+    }
+    if let Some(compression) = args.compression {
+        builder = builder.compression(compression);
+    }
+    for csv_filepath in csv_filepaths {
+        builder = builder.add_source(csv_filepath);
+    }
+    let mut gravenche = builder.build();
+    if let Some(path) = &args.load_state {
+        gravenche.load_snapshot(File::open(path)?).await?;
+    }
+
+    if let Some(address) = &args.metrics_addr {
+        let listener = TcpListener::bind(address).await?;
+        tracing::info!("Serving metrics on {}", listener.local_addr()?);
+        tokio::spawn(serve_metrics(listener, gravenche.metrics()));
+    }
+
+    // Services run until interrupted, then report the final state as usual.
+    let interrupted = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let summary = if let Some(address) = &args.http {
+        serve_http(&mut gravenche, address, interrupted).await?
+    } else if let (Some(brokers), Some(topic)) = (&args.kafka_brokers, &args.kafka_topic) {
+        consume_kafka(
+            &mut gravenche,
+            brokers,
+            topic,
+            &args.kafka_group,
+            interrupted,
+        )
+        .await?
+    } else if let Some(address) = &args.listen {
+        let listener = TcpListener::bind(address).await?;
+        tracing::info!("Listening on {}", listener.local_addr()?);
+        gravenche.serve(listener, interrupted).await?
+    } else if let Some(spool_dir) = &args.spool_dir {
+        gravenche.spool(spool_dir, interrupted).await?
+    } else if args.follow {
+        gravenche.follow(interrupted).await?
+    } else if args.resume {
+        gravenche.resume_until(interrupted).await?
+    } else {
+        gravenche.start_until(interrupted).await?
+    };
+
+    // Report per file statistics when more than one file is processed.
+    if summary.files.len() > 1 {
+        for file in summary.files.iter() {
+            eprintln!(
+                "{}: {} rows read, {} rows skipped",
+                file.path.display(),
+                file.rows_read,
+                file.rows_skipped
+            );
+        }
+    }
+
+    if summary.rows_filtered > 0 {
+        eprintln!(
+            "{} rows outside the time window were skipped",
+            summary.rows_filtered
+        );
+    }
+
+    if summary.transactions_flagged > 0 {
+        eprintln!(
+            "{} transactions flagged at or above the threshold",
+            summary.transactions_flagged
+        );
+    }
+
+    if args.withdrawal_fee.is_some() {
+        eprintln!("Collected withdrawal fees: {}", summary.fees_collected);
+    }
+
+    if summary.truncated {
+        tracing::warn!(
+            "Stopped reading after {} rows, the rest of the input was ignored",
+            summary.rows_read
+        );
+    }
+
+    if summary.interrupted {
+        eprintln!(
+            "Interrupted after {} rows, the output covers only the transactions read so far",
+            summary.rows_read
+        );
+    }
+
+    // The label goes to the standard error like the other summary lines, so the output holds only the clients.
+    if args.top.is_some() && args.only_locked {
+        let report = gravenche.report().await;
+        eprintln!(
+            "Filtered to locked clients, {} of {} clients. Totals of all clients: available {}, held {}, total {}",
+            report.clients.len(),
+            report.all_clients,
+            report.available,
+            report.held,
+            report.total
+        );
+    }
+
+    match args.top {
+        Some(n) => gravenche.show_top_clients(n).await?,
+        None => gravenche.show_output().await?,
+    }
+
+    if let Some(path) = args.export_transactions {
+        if path.extension().is_some_and(|ext| ext == "parquet") {
+            export_transactions_parquet(&gravenche, &path).await?;
+        } else {
+            gravenche.export_transactions(File::create(path)?).await?;
+        }
+    }
+
+    if let Some(path) = args.export_disputes {
+        gravenche.export_disputes(File::create(path)?).await?;
+    }
+
+    if let Some(path) = args.export_flagged {
+        gravenche.export_flagged(File::create(path)?).await?;
+    }
+
+    if let Some(path) = args.save_state {
+        gravenche.save_snapshot(File::create(path)?).await?;
+    }
+
+    if let Some(path) = args.export_sqlite {
+        export_sqlite(&gravenche, &path, args.force).await?;
+    }
+
+    // The output and the exports are complete either way, strict mode and verification only change the exit code.
+    // Services already answer malformed lines with an error instead.
+    let unknown_transactions = gravenche.metrics().rejected(Rejection::UnknownTransaction);
+    if args.strict && !is_service && (summary.rows_skipped > 0 || unknown_transactions > 0) {
+        eprintln!(
+            "ERROR: {} rows skipped, {} transactions referenced unknown transactions",
+            summary.rows_skipped, unknown_transactions
+        );
+        process::exit(STRICT_EXIT_CODE);
+    }
+
+    if args.verify {
+        if let Err(violations) = gravenche.verify().await {
+            for violation in violations.iter() {
+                eprintln!("ERROR: {}", violation);
+            }
+            process::exit(VERIFY_EXIT_CODE);
+        }
+    }
+
+    if summary.interrupted {
+        process::exit(INTERRUPTED_EXIT_CODE);
+    }
     Ok(())
 }
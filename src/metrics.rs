@@ -0,0 +1,364 @@
+//! This module contains operational metrics of the transaction processor in the Prometheus text format. The
+//! processor updates them with atomic operations only, so recording a transaction takes no locks. They can be
+//! scraped over HTTP with [serve_metrics].
+
+use crate::types::transaction::TransactionType;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "runtime")]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "runtime")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "runtime")]
+use tokio::net::TcpListener;
+
+/// Returns the position of a transaction type in [TransactionType::ALL], which is the order of their counters.
+fn type_index(_type: &TransactionType) -> usize {
+    match _type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Unlock => 5,
+        TransactionType::Transfer => 6,
+        TransactionType::Reversal => 7,
+        TransactionType::AdminCredit => 8,
+        TransactionType::AdminDebit => 9,
+    }
+}
+
+/// Upper bounds of the processing latency histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 6] = [0.000_001, 0.000_01, 0.000_1, 0.001, 0.01, 0.1];
+
+/// What became of a row of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The transaction modified client data.
+    Applied,
+    /// The transaction was handled by the processor but not applied, see [Rejection].
+    Rejected,
+    /// The row couldn't be parsed into a transaction and never reached the processor.
+    Skipped,
+}
+
+/// Reason a transaction was not applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The client doesn't exist.
+    UnknownClient,
+    /// The referenced transaction doesn't exist.
+    UnknownTransaction,
+    /// The referenced transaction is not in dispute.
+    NotDisputed,
+    /// The account of the client is locked.
+    AccountLocked,
+    /// The client doesn't have enough available funds.
+    InsufficientFunds,
+    /// The dispute would exceed the cap on held funds.
+    HeldCap,
+    /// The client ID is reserved.
+    ReservedClient,
+    /// The client ID is not among the allowed ones.
+    NotAllowedClient,
+    /// Unlocking accounts is not allowed.
+    UnlockNotAllowed,
+    /// The transaction ID is outside the window of IDs being processed.
+    OutsideTxWindow,
+    /// The referenced transaction was reversed.
+    Reversed,
+    /// The referenced transaction is not a deposit of the client or is in dispute.
+    NotReversible,
+    /// Admin adjustments are not allowed.
+    AdminOpsNotAllowed,
+    /// The referenced transaction is an admin adjustment, which can't be disputed.
+    AdminOperation,
+    /// The dispute exceeds the part of the transaction which is not in dispute yet.
+    DisputeExceedsAmount,
+    /// The dispute refers to a withdrawal or transfer, and those can't be disputed.
+    WithdrawalNotDisputable,
+    /// The referenced transaction's dispute was resolved already.
+    AlreadyResolved,
+    /// The referenced transaction was charged back, which settles it for good.
+    ChargedBack,
+    /// A transaction with the same ID was applied before, e.g. because an input was fed twice.
+    Replayed,
+    /// The client made the maximum number of withdrawals allowed by the velocity limit.
+    VelocityExceeded,
+    /// The currency differs from the currency of the referenced transaction.
+    CurrencyMismatch,
+    /// The dispute carries a negative amount.
+    NegativeDisputeAmount,
+}
+
+impl Rejection {
+    /// All the reasons in the order of their counters.
+    const ALL: [Rejection; 22] = [
+        Rejection::UnknownClient,
+        Rejection::UnknownTransaction,
+        Rejection::NotDisputed,
+        Rejection::AccountLocked,
+        Rejection::InsufficientFunds,
+        Rejection::HeldCap,
+        Rejection::ReservedClient,
+        Rejection::NotAllowedClient,
+        Rejection::UnlockNotAllowed,
+        Rejection::OutsideTxWindow,
+        Rejection::Reversed,
+        Rejection::NotReversible,
+        Rejection::AdminOpsNotAllowed,
+        Rejection::AdminOperation,
+        Rejection::DisputeExceedsAmount,
+        Rejection::WithdrawalNotDisputable,
+        Rejection::AlreadyResolved,
+        Rejection::ChargedBack,
+        Rejection::Replayed,
+        Rejection::VelocityExceeded,
+        Rejection::CurrencyMismatch,
+        Rejection::NegativeDisputeAmount,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnknownClient => "unknown_client",
+            Self::UnknownTransaction => "unknown_transaction",
+            Self::NotDisputed => "not_disputed",
+            Self::AccountLocked => "account_locked",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::HeldCap => "held_cap",
+            Self::ReservedClient => "reserved_client",
+            Self::NotAllowedClient => "not_allowed_client",
+            Self::UnlockNotAllowed => "unlock_not_allowed",
+            Self::OutsideTxWindow => "outside_tx_window",
+            Self::Reversed => "reversed",
+            Self::NotReversible => "not_reversible",
+            Self::AdminOpsNotAllowed => "admin_ops_not_allowed",
+            Self::AdminOperation => "admin_operation",
+            Self::DisputeExceedsAmount => "dispute_exceeds_amount",
+            Self::WithdrawalNotDisputable => "withdrawal_not_disputable",
+            Self::AlreadyResolved => "already_resolved",
+            Self::ChargedBack => "charged_back",
+            Self::Replayed => "replayed",
+            Self::VelocityExceeded => "velocity_exceeded",
+            Self::CurrencyMismatch => "currency_mismatch",
+            Self::NegativeDisputeAmount => "negative_dispute_amount",
+        }
+    }
+}
+
+/// Counters, gauges and histograms describing the work of the transaction processor.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Transactions handled by the processor, by [TransactionType::ALL].
+    processed: [AtomicU64; 10],
+    /// Transactions which modified client data, by [TransactionType::ALL].
+    applied: [AtomicU64; 10],
+    /// Transactions not applied, by [Rejection::ALL].
+    rejected: [AtomicU64; 22],
+    /// Input rows which couldn't be parsed into a transaction.
+    skipped: AtomicU64,
+    /// Accounts locked by a chargeback.
+    accounts_locked: AtomicU64,
+    /// Commands waiting in the processor queue.
+    queue_depth: AtomicU64,
+    /// Processing latency observations, by [LATENCY_BUCKETS] plus one for larger values.
+    latency_buckets: [AtomicU64; 7],
+    /// Sum of the processing latency observations in nanoseconds.
+    latency_sum_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Records a transaction handled by the processor, whether it was applied and how long applying it took.
+    pub fn record_processed(&self, _type: &TransactionType, applied: bool, latency: Duration) {
+        let index = type_index(_type);
+        self.processed[index].fetch_add(1, Ordering::Relaxed);
+        if applied {
+            self.applied[index].fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records a transaction which was not applied.
+    pub fn record_rejection(&self, rejection: Rejection) {
+        let index = Rejection::ALL
+            .iter()
+            .position(|&known| known == rejection)
+            .unwrap_or_default();
+        self.rejected[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records input rows which couldn't be parsed into a transaction.
+    pub fn record_skipped(&self, rows: usize) {
+        self.skipped.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Records an account locked by a chargeback.
+    pub fn record_account_locked(&self) {
+        self.accounts_locked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets the number of commands waiting in the processor queue.
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of processed transactions of a type.
+    pub fn processed(&self, _type: &TransactionType) -> u64 {
+        self.processed[type_index(_type)].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of applied transactions of a type.
+    pub fn applied(&self, _type: &TransactionType) -> u64 {
+        self.applied[type_index(_type)].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of input rows with an outcome, over all transaction types.
+    pub fn outcome(&self, outcome: Outcome) -> u64 {
+        let sum = |counters: &[AtomicU64]| -> u64 {
+            counters
+                .iter()
+                .map(|counter| counter.load(Ordering::Relaxed))
+                .sum()
+        };
+        match outcome {
+            Outcome::Applied => sum(&self.applied),
+            Outcome::Rejected => sum(&self.rejected),
+            Outcome::Skipped => self.skipped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of transactions rejected for a reason.
+    pub fn rejected(&self, rejection: Rejection) -> u64 {
+        Rejection::ALL
+            .iter()
+            .position(|&known| known == rejection)
+            .map_or(0, |index| self.rejected[index].load(Ordering::Relaxed))
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("# HELP gravenche_transactions_processed_total Transactions handled by the processor.\n");
+        text.push_str("# TYPE gravenche_transactions_processed_total counter\n");
+        for (_type, counter) in TransactionType::ALL.iter().zip(self.processed.iter()) {
+            let _ = writeln!(
+                text,
+                "gravenche_transactions_processed_total{{type=\"{}\"}} {}",
+                _type.as_str(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        text.push_str(
+            "# HELP gravenche_transactions_applied_total Transactions which modified client data.\n",
+        );
+        text.push_str("# TYPE gravenche_transactions_applied_total counter\n");
+        for (_type, counter) in TransactionType::ALL.iter().zip(self.applied.iter()) {
+            let _ = writeln!(
+                text,
+                "gravenche_transactions_applied_total{{type=\"{}\"}} {}",
+                _type.as_str(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        text.push_str("# HELP gravenche_transactions_rejected_total Transactions not applied.\n");
+        text.push_str("# TYPE gravenche_transactions_rejected_total counter\n");
+        for (rejection, counter) in Rejection::ALL.iter().zip(self.rejected.iter()) {
+            let _ = writeln!(
+                text,
+                "gravenche_transactions_rejected_total{{reason=\"{}\"}} {}",
+                rejection.as_str(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        text.push_str("# HELP gravenche_rows_skipped_total Input rows which couldn't be parsed.\n");
+        text.push_str("# TYPE gravenche_rows_skipped_total counter\n");
+        let _ = writeln!(
+            text,
+            "gravenche_rows_skipped_total {}",
+            self.skipped.load(Ordering::Relaxed)
+        );
+
+        text.push_str("# HELP gravenche_accounts_locked_total Accounts locked by a chargeback.\n");
+        text.push_str("# TYPE gravenche_accounts_locked_total counter\n");
+        let _ = writeln!(
+            text,
+            "gravenche_accounts_locked_total {}",
+            self.accounts_locked.load(Ordering::Relaxed)
+        );
+
+        text.push_str("# HELP gravenche_queue_depth Commands waiting in the processor queue.\n");
+        text.push_str("# TYPE gravenche_queue_depth gauge\n");
+        let _ = writeln!(
+            text,
+            "gravenche_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+
+        text.push_str(
+            "# HELP gravenche_processing_latency_seconds Time taken to apply a transaction.\n",
+        );
+        text.push_str("# TYPE gravenche_processing_latency_seconds histogram\n");
+        let mut count = 0;
+        for (index, counter) in self.latency_buckets.iter().enumerate() {
+            count += counter.load(Ordering::Relaxed);
+            let bound = match LATENCY_BUCKETS.get(index) {
+                Some(bound) => bound.to_string(),
+                None => String::from("+Inf"),
+            };
+            let _ = writeln!(
+                text,
+                "gravenche_processing_latency_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let sum = self.latency_sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(text, "gravenche_processing_latency_seconds_sum {}", sum);
+        let _ = writeln!(text, "gravenche_processing_latency_seconds_count {}", count);
+
+        text
+    }
+}
+
+/// Answers `GET /metrics` requests on `listener` with the rendered metrics and any other request with `404 Not
+/// Found`. Runs until accepting connections fails.
+#[cfg(feature = "runtime")]
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // Only the request line matters, which fits in the first read.
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).await?;
+            let request = String::from_utf8_lossy(&request[..read]);
+
+            let response = if request.starts_with("GET /metrics ") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                String::from(
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+            };
+            stream.write_all(response.as_bytes()).await?;
+            stream.shutdown().await
+        });
+    }
+}
@@ -0,0 +1,41 @@
+//! This module contains the [TransactionObserver] trait used to react to the outcome of every transaction, e.g. to open
+//! a case for each chargeback, without changing the processor.
+
+use crate::metrics::Rejection;
+use crate::types::{client::Client, transaction::Transaction};
+#[cfg(feature = "runtime")]
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Told the outcome of every transaction handled by the processor, or by the synchronous path of the `sync` feature,
+/// in the order they are handled. Both methods do nothing unless implemented.
+///
+/// The methods are called on the processor task, so a slow observer slows processing down. Work taking longer, like
+/// sending an email, should be handed over to another task. A panicking observer is logged and the transaction stays
+/// applied or rejected as it was.
+pub trait TransactionObserver {
+    /// Called after `transaction`, as it was read, modified `client`, which has the balances after the change. For
+    /// transfers `client` is the sending client, the receiving client is not reported.
+    fn on_applied(&mut self, _transaction: &Transaction, _client: &Client) {}
+
+    /// Called after `transaction` was rejected for `rejection` without modifying any client.
+    fn on_rejected(&mut self, _transaction: &Transaction, _rejection: Rejection) {}
+}
+
+/// A [TransactionObserver] shared between the runs of a [Gravenche](crate::Gravenche).
+#[cfg(feature = "runtime")]
+#[derive(Clone)]
+pub(crate) struct SharedObserver(Arc<Mutex<Box<dyn TransactionObserver + Send>>>);
+
+#[cfg(feature = "runtime")]
+impl SharedObserver {
+    pub(crate) fn new(observer: Box<dyn TransactionObserver + Send>) -> Self {
+        SharedObserver(Arc::new(Mutex::new(observer)))
+    }
+
+    // Locks the observer. Panics of the observer are caught while it is called, so the lock isn't poisoned by them.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Box<dyn TransactionObserver + Send>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
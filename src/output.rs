@@ -0,0 +1,294 @@
+//! This module contains helpers to render client data in tabular format and other output sinks.
+
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[cfg(feature = "runtime")]
+use crate::types::config::OutputFormat;
+use crate::types::{
+    client::{round_amount, Client, CurrencyBalance},
+    config::{Column, Config, NumberLocale},
+    currency::Currency,
+};
+use std::io::Write;
+
+/// Width of the currency column.
+const CURRENCY_WIDTH: usize = 8;
+
+/// Writes the clients shown at the end of a run, e.g. in a custom format. Set one with
+/// [formatter](crate::GravencheBuilder::formatter) to replace the configured [OutputFormat].
+pub trait OutputFormatter {
+    /// Writes `clients` to `out` in the given order, by ID for all the clients and by total for the top clients.
+    fn write(&mut self, clients: &[Client], out: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+/// The [OutputFormatter] used unless another one is set, writing the clients in the [output
+/// format](Config::output_format) of the config. SQLite and Parquet output goes to its file instead of `out`.
+#[cfg(feature = "runtime")]
+pub(crate) struct ConfiguredFormatter<'a>(pub(crate) &'a Config);
+
+#[cfg(feature = "runtime")]
+impl OutputFormatter for ConfiguredFormatter<'_> {
+    fn write(&mut self, clients: &[Client], mut out: &mut dyn Write) -> anyhow::Result<()> {
+        let config = self.0;
+        match &config.output_format {
+            OutputFormat::Table => write_table(&mut out, clients, config),
+            #[cfg(feature = "sqlite")]
+            OutputFormat::Sqlite(path) => sqlite::write_clients(path, clients),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet(path) => parquet::write_clients(
+                path,
+                clients,
+                config.base_currency,
+                config.decimals,
+                config.extended_output,
+            ),
+        }
+    }
+}
+
+/// Writes the header of the client table with the default columns. The `extended` table has the dispute and
+/// chargeback counts too, and whether the available funds were ever negative.
+pub fn write_table_header<W: Write>(output_stream: &mut W, extended: bool) -> anyhow::Result<()> {
+    write_header(
+        output_stream,
+        &default_widths(&default_columns(extended)),
+        false,
+    )
+}
+
+/// Writes a single client as a row of the client table with the default columns, with amounts rounded to `decimals`
+/// decimal places. Only the funds in the base currency are shown.
+pub fn write_table_row<W: Write>(
+    output_stream: &mut W,
+    client: &Client,
+    decimals: u32,
+    extended: bool,
+) -> anyhow::Result<()> {
+    write_row(
+        output_stream,
+        client,
+        &base_balance(client),
+        &default_widths(&default_columns(extended)),
+        false,
+        decimals,
+        NumberLocale::Plain,
+    )
+}
+
+/// Writes the client table, the header followed by a row per client, with the [table
+/// columns](Config::table_columns) of `config` and amounts rounded to its decimals. If any client has funds in a
+/// currency other than the base currency, the table gets a `currency` column after the client ID and a row per client
+/// per currency, the base currency first. With [signed_held](Config::signed_held) held funds are shown as a negative
+/// amount taken from the available funds.
+pub fn write_table<'a, W, I>(
+    output_stream: &mut W,
+    clients: I,
+    config: &Config,
+) -> anyhow::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Client>,
+{
+    let clients: Vec<&Client> = clients.into_iter().collect();
+    let currencies = clients.iter().any(|client| !client.currencies.is_empty());
+    let mut rows: Vec<(&Client, CurrencyBalance)> = Vec::new();
+    for client in clients {
+        let balances: Vec<CurrencyBalance> = if currencies {
+            client.balances(config.base_currency).collect()
+        } else {
+            vec![base_balance(client)]
+        };
+        for mut balance in balances {
+            if config.signed_held {
+                balance = signed_held(balance);
+            }
+            rows.push((client, balance));
+        }
+    }
+    let columns = table_widths(&config.table_columns(), &rows, config);
+
+    write_header(output_stream, &columns, currencies)?;
+    for (client, balance) in rows {
+        write_row(
+            output_stream,
+            client,
+            &balance,
+            &columns,
+            currencies,
+            config.decimals,
+            config.locale,
+        )?;
+    }
+    Ok(())
+}
+
+// Returns the columns of the client table with their widths. With a locale the amount columns are as wide as their
+// longest cell, since grouped amounts with all the decimal places often don't fit the default width.
+fn table_widths(
+    columns: &[Column],
+    rows: &[(&Client, CurrencyBalance)],
+    config: &Config,
+) -> Vec<(Column, usize)> {
+    let mut columns = default_widths(columns);
+    if config.locale == NumberLocale::Plain {
+        return columns;
+    }
+    for (column, width) in &mut columns {
+        let amount = |balance: &CurrencyBalance| match column {
+            Column::Available => Some(balance.available),
+            Column::Held => Some(balance.held),
+            Column::Total => Some(balance.total),
+            _ => None,
+        };
+        for (_, balance) in rows {
+            if let Some(amount) = amount(balance) {
+                let cell = format_amount(amount, config.decimals, config.locale);
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+    }
+    columns
+}
+
+// Returns the balance with the held funds as a negative amount and the available funds before they were taken out,
+// so the available funds after them are the sum of both.
+fn signed_held(balance: CurrencyBalance) -> CurrencyBalance {
+    CurrencyBalance {
+        available: balance.available + balance.held,
+        // Subtracting from zero avoids showing -0 for no held funds.
+        held: 0.0 - balance.held,
+        ..balance
+    }
+}
+
+/// Formats an amount rounded to `decimals` decimal places the way `locale` writes numbers. Unless the locale is
+/// [NumberLocale::Plain], thousands are grouped and all the decimal places are shown, e.g. `-1,234.5000`.
+pub fn format_amount(amount: f32, decimals: u32, locale: NumberLocale) -> String {
+    // The shortest form of the rounded amount, so no digits beyond the precision of f32 show up.
+    let plain = round_amount(amount, decimals).to_string();
+    let Some(group_separator) = locale.group_separator() else {
+        return plain;
+    };
+    let (sign, digits) = match plain.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", plain.as_str()),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    let mut formatted = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            formatted.push(group_separator);
+        }
+        formatted.push(digit);
+    }
+    if decimals > 0 {
+        formatted.push(locale.decimal_separator());
+        formatted.push_str(fraction);
+        formatted.extend(std::iter::repeat_n('0', decimals as usize - fraction.len()));
+    }
+    formatted
+}
+
+// Returns the default columns, followed by the counters if `extended` is set.
+fn default_columns(extended: bool) -> Vec<Column> {
+    let mut columns = Column::DEFAULT.to_vec();
+    if extended {
+        columns.extend(Column::COUNTERS);
+    }
+    columns
+}
+
+// Returns the funds of a client in the base currency.
+fn base_balance(client: &Client) -> CurrencyBalance {
+    CurrencyBalance {
+        currency: Currency::default(),
+        total: client.total,
+        available: client.available,
+        held: client.held,
+    }
+}
+
+// Returns the width of a column, at least the width of its name.
+fn width(column: Column) -> usize {
+    match column {
+        Column::Available | Column::Held | Column::Total => 10,
+        _ => column.name().len().max(6),
+    }
+}
+
+// Returns the columns with their default widths.
+fn default_widths(columns: &[Column]) -> Vec<(Column, usize)> {
+    columns
+        .iter()
+        .map(|&column| (column, width(column)))
+        .collect()
+}
+
+// Writes the cells of a row, right aligned to the width of their column and separated by bars. The currency cell
+// follows the client ID wherever it is, or comes first if the client ID isn't shown.
+fn write_cells<W: Write>(
+    output_stream: &mut W,
+    columns: &[(Column, usize)],
+    currency: Option<String>,
+    cell: impl Fn(Column) -> String,
+) -> anyhow::Result<()> {
+    let mut cells: Vec<(String, usize)> = columns
+        .iter()
+        .map(|&(column, width)| (cell(column), width))
+        .collect();
+    if let Some(currency) = currency {
+        let position = columns
+            .iter()
+            .position(|&(column, _)| column == Column::Client)
+            .map_or(0, |client| client + 1);
+        cells.insert(position, (currency, CURRENCY_WIDTH));
+    }
+    for (i, (cell, width)) in cells.iter().enumerate() {
+        if i > 0 {
+            write!(output_stream, " | ")?;
+        }
+        write!(output_stream, "{0: >1$}", cell, width)?;
+    }
+    writeln!(output_stream)?;
+    Ok(())
+}
+
+// Writes the header of the client table, with the currency column if `currency` is set.
+fn write_header<W: Write>(
+    output_stream: &mut W,
+    columns: &[(Column, usize)],
+    currency: bool,
+) -> anyhow::Result<()> {
+    let currency = currency.then(|| String::from("currency"));
+    write_cells(output_stream, columns, currency, |column| {
+        column.name().to_string()
+    })
+}
+
+// Writes the funds of a client in a single currency as a row of the client table, with the currency column if
+// `currency` is set.
+fn write_row<W: Write>(
+    output_stream: &mut W,
+    client: &Client,
+    balance: &CurrencyBalance,
+    columns: &[(Column, usize)],
+    currency: bool,
+    decimals: u32,
+    locale: NumberLocale,
+) -> anyhow::Result<()> {
+    let currency = currency.then(|| balance.currency.to_string());
+    write_cells(output_stream, columns, currency, |column| match column {
+        Column::Client => client.id.to_string(),
+        Column::Available => format_amount(balance.available, decimals, locale),
+        Column::Held => format_amount(balance.held, decimals, locale),
+        Column::Total => format_amount(balance.total, decimals, locale),
+        Column::Locked => client.locked.to_string(),
+        Column::Disputes => client.dispute_count.to_string(),
+        Column::Chargebacks => client.chargeback_count.to_string(),
+        Column::WasNegative => client.was_negative.to_string(),
+    })
+}
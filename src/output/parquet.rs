@@ -0,0 +1,188 @@
+//! This module contains sinks writing client data and processed transactions to Parquet files.
+
+use crate::types::{
+    client::{Client, CurrencyBalance},
+    currency::Currency,
+    transaction::{format_dispute_events, Transaction},
+};
+use arrow_array::{
+    ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+    UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use std::{fs::File, path::Path, sync::Arc};
+
+/// Precision of amount columns.
+const AMOUNT_PRECISION: u8 = 38;
+
+// Converts amounts to a decimal column with `decimals` decimal places, the precision gravenche is configured with.
+fn amount_column(amounts: impl Iterator<Item = f32>, decimals: u32) -> anyhow::Result<ArrayRef> {
+    let scale = 10f64.powi(decimals as i32);
+    let amounts = Decimal128Array::from_iter_values(
+        amounts.map(|amount| (f64::from(amount) * scale).round() as i128),
+    )
+    .with_precision_and_scale(AMOUNT_PRECISION, decimals as i8)?;
+    Ok(Arc::new(amounts))
+}
+
+fn amount_field(name: &str, decimals: u32) -> Field {
+    Field::new(
+        name,
+        DataType::Decimal128(AMOUNT_PRECISION, decimals as i8),
+        false,
+    )
+}
+
+// Writes a single record batch to a new Parquet file at `path`.
+fn write_batch(path: &Path, batch: RecordBatch) -> anyhow::Result<()> {
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes clients to a Parquet file at `path` with the columns `client`, `available`, `held`, `total` and `locked`,
+/// followed by `disputes`, `chargebacks` and `was_negative` if `extended`. Clients are ordered by client ID and
+/// amounts have `decimals` decimal places. If any client has funds in a currency other than `base_currency`, a
+/// `currency` column follows `client` and there is a row per client per currency.
+pub fn write_clients<'a, I>(
+    path: &Path,
+    clients: I,
+    base_currency: Currency,
+    decimals: u32,
+    extended: bool,
+) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = &'a Client>,
+{
+    let mut clients: Vec<&Client> = clients.into_iter().collect();
+    clients.sort_by_key(|client| client.id);
+    let currencies = clients.iter().any(|client| !client.currencies.is_empty());
+    let rows: Vec<(&Client, CurrencyBalance)> = clients
+        .iter()
+        .flat_map(|&client| {
+            client
+                .balances(base_currency)
+                .take(if currencies { usize::MAX } else { 1 })
+                .map(move |balance| (client, balance))
+        })
+        .collect();
+
+    let mut fields = vec![Field::new("client", DataType::UInt32, false)];
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(UInt32Array::from_iter_values(
+        rows.iter().map(|(client, _)| client.id),
+    ))];
+    if currencies {
+        fields.push(Field::new("currency", DataType::Utf8, false));
+        columns.push(Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|(_, balance)| balance.currency.as_str()),
+        )));
+    }
+    fields.extend([
+        amount_field("available", decimals),
+        amount_field("held", decimals),
+        amount_field("total", decimals),
+        Field::new("locked", DataType::Boolean, false),
+    ]);
+    columns.extend([
+        amount_column(rows.iter().map(|(_, balance)| balance.available), decimals)?,
+        amount_column(rows.iter().map(|(_, balance)| balance.held), decimals)?,
+        amount_column(rows.iter().map(|(_, balance)| balance.total), decimals)?,
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|(client, _)| Some(client.locked)),
+        )) as ArrayRef,
+    ]);
+    if extended {
+        fields.push(Field::new("disputes", DataType::UInt32, false));
+        fields.push(Field::new("chargebacks", DataType::UInt32, false));
+        fields.push(Field::new("was_negative", DataType::Boolean, false));
+        columns.push(Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|(client, _)| client.dispute_count),
+        )));
+        columns.push(Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|(client, _)| client.chargeback_count),
+        )));
+        columns.push(Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|(client, _)| Some(client.was_negative)),
+        )));
+    }
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+    write_batch(path, batch)
+}
+
+/// Writes processed transactions to a Parquet file at `path` with the columns `tx`, `client`, `type`, `amount`,
+/// `disputed` and `dispute_history`, and `timestamp` and `memo` if any transaction has one. Transactions are ordered by
+/// transaction ID and amounts have `decimals` decimal places.
+pub fn write_transactions<'a, I>(path: &Path, transactions: I, decimals: u32) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    let mut transactions: Vec<&Transaction> = transactions.into_iter().collect();
+    transactions.sort_by_key(|transaction| transaction.id);
+
+    let mut fields = vec![
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("client", DataType::UInt32, false),
+        Field::new("type", DataType::Utf8, false),
+        amount_field("amount", decimals),
+        Field::new("disputed", DataType::Boolean, false),
+        Field::new("dispute_history", DataType::Utf8, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            transactions.iter().map(|transaction| transaction.id),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            transactions.iter().map(|transaction| transaction.client_id),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            transactions
+                .iter()
+                .map(|transaction| transaction._type.as_str()),
+        )),
+        amount_column(
+            transactions.iter().map(|transaction| transaction.amount),
+            decimals,
+        )?,
+        Arc::new(BooleanArray::from_iter(
+            transactions
+                .iter()
+                .map(|transaction| Some(transaction.is_disputed())),
+        )),
+        Arc::new(StringArray::from_iter_values(transactions.iter().map(
+            |transaction| format_dispute_events(transaction.dispute_events()),
+        ))),
+    ];
+    if transactions
+        .iter()
+        .any(|transaction| transaction.timestamp.is_some())
+    {
+        fields.push(Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ));
+        columns.push(Arc::new(
+            TimestampMicrosecondArray::from_iter(transactions.iter().map(|transaction| {
+                transaction
+                    .timestamp
+                    .map(|timestamp| timestamp.timestamp_micros())
+            }))
+            .with_timezone("UTC"),
+        ));
+    }
+    if transactions
+        .iter()
+        .any(|transaction| transaction.memo.is_some())
+    {
+        fields.push(Field::new("memo", DataType::Utf8, true));
+        columns.push(Arc::new(StringArray::from_iter(
+            transactions
+                .iter()
+                .map(|transaction| transaction.memo.as_deref()),
+        )));
+    }
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+    write_batch(path, batch)
+}
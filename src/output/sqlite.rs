@@ -0,0 +1,107 @@
+//! This module contains a sink writing client data to a SQLite database.
+
+use crate::types::{client::Client, transaction::Transaction};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+/// Writes clients into the `clients` table of the SQLite database at `path`. The table is created if absent and
+/// existing rows are updated by client ID.
+pub fn write_clients<'a, I>(path: &Path, clients: I) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = &'a Client>,
+{
+    let mut connection = Connection::open(path)?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS clients (
+            id INTEGER PRIMARY KEY,
+            available REAL NOT NULL,
+            held REAL NOT NULL,
+            total REAL NOT NULL,
+            locked INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // A single transaction makes inserting many rows fast.
+    let transaction = connection.transaction()?;
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO clients (id, available, held, total, locked) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(id) DO UPDATE SET
+                available = excluded.available,
+                held = excluded.held,
+                total = excluded.total,
+                locked = excluded.locked",
+        )?;
+        for client in clients {
+            statement.execute(params![
+                client.id,
+                client.available,
+                client.held,
+                client.total,
+                client.locked
+            ])?;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Writes the final state of a run into a new SQLite database at `path`: clients into the `clients` table and processed
+/// transactions into the `transactions` table.
+pub fn write_state<'a, C, T>(path: &Path, clients: C, transactions: T) -> anyhow::Result<()>
+where
+    C: IntoIterator<Item = &'a Client>,
+    T: IntoIterator<Item = &'a Transaction>,
+{
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE clients (
+            id INTEGER PRIMARY KEY,
+            available REAL NOT NULL,
+            held REAL NOT NULL,
+            total REAL NOT NULL,
+            locked INTEGER NOT NULL
+        );
+        CREATE TABLE transactions (
+            id INTEGER PRIMARY KEY,
+            client_id INTEGER NOT NULL,
+            type TEXT NOT NULL,
+            amount REAL NOT NULL,
+            disputed INTEGER NOT NULL
+        );",
+    )?;
+
+    let transaction = connection.transaction()?;
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO clients (id, available, held, total, locked) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for client in clients {
+            statement.execute(params![
+                client.id,
+                client.available,
+                client.held,
+                client.total,
+                client.locked
+            ])?;
+        }
+
+        let mut statement = transaction.prepare(
+            "INSERT INTO transactions (id, client_id, type, amount, disputed) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for processed in transactions {
+            statement.execute(params![
+                processed.id,
+                processed.client_id,
+                processed._type.as_str(),
+                processed.amount,
+                processed.is_disputed()
+            ])?;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
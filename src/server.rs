@@ -0,0 +1,72 @@
+//! This module contains the connection handler of the TCP listener mode started with
+//! [Gravenche::serve](crate::Gravenche::serve). Every line a client sends is a transaction in CSV format without a
+//! header, e.g. `deposit, 1, 1, 10.0`. The line `SNAPSHOT` is answered with the client table followed by an empty
+//! line.
+
+use crate::output::{write_table_header, write_table_row};
+use crate::source::parse_csv_line;
+use crate::types::other::{Command, FileSummary};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// A line requesting the current client table.
+pub const SNAPSHOT_COMMAND: &str = "SNAPSHOT";
+
+/// Forwards the transactions sent over a connection to the processor until the client closes it. Malformed lines
+/// are answered with an `ERROR` line in strict mode and only logged otherwise.
+/// Returns statistics of the connection, named after the address of the client.
+pub(crate) async fn handle_connection(
+    stream: TcpStream,
+    sender: mpsc::Sender<Command>,
+    delimiter: u8,
+    strict: bool,
+    decimals: u32,
+) -> anyhow::Result<FileSummary> {
+    let mut summary = FileSummary {
+        path: PathBuf::from(stream.peer_addr()?.to_string()),
+        ..Default::default()
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        // Blank lines carry no record.
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == SNAPSHOT_COMMAND {
+            let (reply, snapshot) = oneshot::channel();
+            sender.send(Command::Snapshot(reply)).await?;
+
+            let mut table = Vec::new();
+            write_table_header(&mut table, false)?;
+            for client in snapshot.await?.iter() {
+                write_table_row(&mut table, client, decimals, false)?;
+            }
+            table.push(b'\n');
+            writer.write_all(&table).await?;
+            continue;
+        }
+
+        summary.rows_read += 1;
+        match parse_csv_line(line, delimiter) {
+            Ok(transaction) => sender.send(Command::Transaction(transaction)).await?,
+            Err(error) => {
+                summary.rows_skipped += 1;
+                if strict {
+                    writer
+                        .write_all(format!("ERROR {}\n", error).as_bytes())
+                        .await?;
+                } else {
+                    tracing::warn!("{}: Skipping record: {}", summary.path.display(), error);
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
@@ -0,0 +1,913 @@
+//! This module contains the [TransactionSource] trait used to feed transactions to the transaction processor and its
+//! implementations for CSV and NDJSON input. Other sources such as message queues or test generators can be plugged
+//! in by implementing the trait.
+
+#[cfg(feature = "runtime")]
+use crate::types::config::InputFormat;
+#[cfg(feature = "serde")]
+use crate::types::transaction::JsonTransaction;
+use crate::types::{
+    error::GravencheError,
+    transaction::{parse_timestamp, trim_field, Transaction, TIMESTAMP_INDEX},
+};
+#[cfg(feature = "serde")]
+use std::io::{BufRead, Lines};
+#[cfg(feature = "runtime")]
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+    vec,
+};
+use std::{
+    future::Future,
+    io::{BufReader, Read},
+};
+#[cfg(feature = "runtime")]
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Largest number of transactions a [BlockingCsvSource] passes from its reading task at once.
+pub const READ_BATCH: usize = 1024;
+
+/// Returns the size of the batches a [BlockingCsvSource] reading `read_ahead` transactions ahead passes on, and the
+/// number of full batches it may queue. Together with the batch being filled they hold at most `read_ahead`
+/// transactions, and never less than two.
+#[cfg(feature = "runtime")]
+fn batch_sizes(read_ahead: usize) -> (usize, usize) {
+    let batch_size = (read_ahead / 2).clamp(1, READ_BATCH);
+    (
+        batch_size,
+        (read_ahead / batch_size).saturating_sub(1).max(1),
+    )
+}
+
+/// A source of transactions to process.
+pub trait TransactionSource {
+    /// Returns the next transaction or `None` once the source is exhausted.
+    fn next(&mut self) -> impl Future<Output = Option<Transaction>> + Send;
+
+    /// Returns the number of records skipped because they could not be parsed.
+    fn rows_skipped(&self) -> usize {
+        0
+    }
+
+    /// Returns the error which stopped the source before it was exhausted, if any.
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        None
+    }
+
+    /// Returns if the source stopped at its limit of records while there were more to read.
+    fn truncated(&self) -> bool {
+        false
+    }
+}
+
+/// Converts a line of NDJSON to a [Transaction]. Returns `None` if the line can't be converted.
+#[cfg(feature = "serde")]
+fn parse_json_line(line: &str) -> Option<Transaction> {
+    let record: JsonTransaction = serde_json::from_str(line).ok()?;
+    Transaction::try_from(record).ok()
+}
+
+/// Converts a single line of CSV without a header to a [Transaction].
+pub fn parse_csv_line(line: &str, delimiter: u8) -> anyhow::Result<Transaction> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+    match csv_reader.records().next() {
+        Some(record) => Transaction::from_record(&record?),
+        None => anyhow::bail!("Empty record."),
+    }
+}
+
+/// Positions of the columns of a CSV header which are found by their name.
+#[derive(Clone, Copy, Debug, Default)]
+struct NamedColumns {
+    /// Position of the `currency` column.
+    currency: Option<usize>,
+    /// Position of the `timestamp` column, unless the timestamp is read from its usual position.
+    timestamp: Option<usize>,
+    /// Position of the `memo` or `description` column.
+    memo: Option<usize>,
+}
+
+impl NamedColumns {
+    fn from_header(header: &csv::StringRecord) -> Self {
+        let position = |name: &str| {
+            header
+                .iter()
+                .position(|field| trim_field(field).eq_ignore_ascii_case(name))
+        };
+        let currency = position("currency");
+        // A timestamp in the fifth column once the currency is taken out is read by position, which leaves its place
+        // to the destination client on transfers.
+        let timestamp = position("timestamp").filter(|&index| {
+            index - currency.map_or(0, |currency| usize::from(currency < index)) != TIMESTAMP_INDEX
+        });
+        NamedColumns {
+            currency,
+            timestamp,
+            memo: position("memo").or_else(|| position("description")),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.currency.is_none() && self.timestamp.is_none() && self.memo.is_none()
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        [self.currency, self.timestamp, self.memo].contains(&Some(index))
+    }
+}
+
+/// Converts a CSV record with named `columns` to a [Transaction]. The named columns are taken out into `stripped`
+/// first, so the other columns are read at their usual positions.
+fn parse_named_record(
+    record: &csv::StringRecord,
+    stripped: &mut csv::StringRecord,
+    columns: NamedColumns,
+) -> anyhow::Result<Transaction> {
+    stripped.clear();
+    for (_, field) in record
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !columns.contains(*i))
+    {
+        stripped.push_field(field);
+    }
+    let mut transaction = Transaction::from_record(stripped)?;
+    let named = |index: Option<usize>| {
+        index
+            .and_then(|index| record.get(index))
+            .map(trim_field)
+            .filter(|field| !field.is_empty())
+    };
+    if let Some(currency) = named(columns.currency) {
+        transaction = transaction.with_currency(currency.parse()?);
+    }
+    if let Some(timestamp) = named(columns.timestamp) {
+        transaction = transaction.with_timestamp(parse_timestamp(timestamp)?);
+    }
+    if let Some(memo) = named(columns.memo) {
+        transaction = transaction.with_memo(memo);
+    }
+    Ok(transaction)
+}
+
+/// A [TransactionSource] reading transactions in CSV format with a header row. Columns named `currency`, `timestamp`
+/// and `memo` or `description` are read as the currency, time and memo of the transaction wherever they are in the
+/// header.
+pub struct CsvSource<R: Read> {
+    /// Reader of CSV records.
+    csv_reader: csv::Reader<BufReader<R>>,
+    /// We re-use this to store record. It prevents memory allocation every time.
+    record: csv::StringRecord,
+    /// Number of records skipped because they could not be parsed.
+    rows_skipped: usize,
+    /// Stop at the first record which can't be parsed instead of skipping it.
+    fail_fast: bool,
+    /// Columns found by name in the header. `None` until the header was read.
+    named_columns: Option<NamedColumns>,
+    /// Record without the named columns, re-used like `record`. Only used if there are named columns.
+    stripped: csv::StringRecord,
+    /// Maximum number of records to read, parsed or skipped.
+    max_rows: Option<usize>,
+    /// Number of records read so far, parsed or skipped.
+    rows_read: usize,
+    /// Set once reading stopped at `max_rows` with records left.
+    truncated: bool,
+    /// Error which stopped reading.
+    error: Option<anyhow::Error>,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_delimiter(reader, b',')
+    }
+
+    /// Builds a CsvSource reading fields separated by `delimiter`, e.g. `b';'` or `b'\t'`.
+    pub fn with_delimiter(reader: R, delimiter: u8) -> Self {
+        Self::with_format(reader, delimiter, None)
+    }
+
+    /// Builds a CsvSource reading fields separated by `delimiter` and skipping lines which start with `comment`, e.g.
+    /// `Some(b'#')`.
+    pub fn with_format(reader: R, delimiter: u8, comment: Option<u8>) -> Self {
+        // Use of BufReader makes reading efficient by reading large chuk, infrequent reads.
+        let buf_reader = BufReader::new(reader);
+
+        // csv-core strips a UTF-8 BOM at the start of the input, so files exported from Excel parse as well.
+        // Records are flexible so a short record is skipped rather than failing the whole input.
+        let csv_reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .comment(comment)
+            .flexible(true)
+            .from_reader(buf_reader);
+
+        CsvSource {
+            csv_reader,
+            record: csv::StringRecord::new(),
+            rows_skipped: 0,
+            fail_fast: false,
+            named_columns: None,
+            stripped: csv::StringRecord::new(),
+            max_rows: None,
+            rows_read: 0,
+            truncated: false,
+            error: None,
+        }
+    }
+
+    /// Makes reading stop with [GravencheError::BadRow] at the first record which can't be parsed.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Stops reading after `max_rows` records, counting the skipped ones too. No limit by default.
+    pub fn max_rows(mut self, max_rows: Option<usize>) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    /// Returns the next transaction like [TransactionSource::next], but without an async runtime and with the error
+    /// which stopped reading returned instead of kept.
+    pub fn next_transaction(&mut self) -> anyhow::Result<Option<Transaction>> {
+        let named_columns = match self.named_columns {
+            Some(named_columns) => named_columns,
+            None => {
+                let named_columns = NamedColumns::from_header(self.csv_reader.headers()?);
+                self.named_columns = Some(named_columns);
+                named_columns
+            }
+        };
+        loop {
+            // A record past the limit shows that there was more to read. It is dropped.
+            if self.max_rows.is_some_and(|max| self.rows_read >= max) {
+                self.truncated = self.csv_reader.read_record(&mut self.record)?;
+                return Ok(None);
+            }
+            if !self.csv_reader.read_record(&mut self.record)? {
+                return Ok(None);
+            }
+            self.rows_read += 1;
+
+            let line = self.record.position().map_or(0, |position| position.line());
+            let transaction = if named_columns.is_empty() {
+                Transaction::from_record(&self.record)
+            } else {
+                parse_named_record(&self.record, &mut self.stripped, named_columns)
+            };
+            match transaction {
+                Ok(transaction) => return Ok(Some(transaction.with_source_line(line))),
+                Err(error) => {
+                    if self.fail_fast {
+                        return Err(GravencheError::BadRow {
+                            line,
+                            reason: error.to_string(),
+                        }
+                        .into());
+                    }
+                    // A bad record is logged and skipped so the rest of the input is still processed.
+                    tracing::error!(line, "Skipping record: {}", error);
+                    self.rows_skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read + Send> TransactionSource for CsvSource<R> {
+    async fn next(&mut self) -> Option<Transaction> {
+        match self.next_transaction() {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    fn rows_skipped(&self) -> usize {
+        self.rows_skipped
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+
+    fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Result of the reading task of a [BlockingCsvSource]: the number of skipped records, whether reading stopped at the
+/// limit of records and the error which stopped it.
+#[cfg(feature = "runtime")]
+type ReaderResult = (usize, bool, Option<anyhow::Error>);
+
+/// A [TransactionSource] reading and parsing CSV in a blocking task of its own, so a large input is parsed in parallel
+/// with applying its transactions. The task reads at most `read_ahead` transactions ahead, in batches of up to
+/// [READ_BATCH], and then waits until they are taken, so memory stays bounded. It stops once the source is dropped.
+#[cfg(feature = "runtime")]
+pub struct BlockingCsvSource {
+    /// Batches of transactions read by the task, each with the number of records skipped before it.
+    receiver: mpsc::Receiver<Vec<(Transaction, usize)>>,
+    /// Rest of the batch being returned.
+    batch: vec::IntoIter<(Transaction, usize)>,
+    /// Number of records skipped before the last returned transaction, or in total once the task finished.
+    rows_skipped: usize,
+    /// Reading task. Taken once it finished.
+    reader: Option<JoinHandle<ReaderResult>>,
+    /// Set once the task stopped at the limit of records with records left.
+    truncated: bool,
+    /// Error which stopped reading.
+    error: Option<anyhow::Error>,
+}
+
+#[cfg(feature = "runtime")]
+impl BlockingCsvSource {
+    /// Starts reading `source` in a blocking task, at most `read_ahead` transactions ahead. Must be called within a
+    /// Tokio runtime.
+    pub fn spawn<R: Read + Send + 'static>(mut source: CsvSource<R>, read_ahead: usize) -> Self {
+        let (batch_size, batches) = batch_sizes(read_ahead);
+        let (sender, receiver) = mpsc::channel(batches);
+        let reader = tokio::task::spawn_blocking(move || {
+            let mut batch = Vec::with_capacity(batch_size);
+            let error = loop {
+                match source.next_transaction() {
+                    Ok(Some(transaction)) => {
+                        batch.push((transaction, source.rows_skipped));
+                        if batch.len() < batch_size {
+                            continue;
+                        }
+                    }
+                    Ok(None) => break None,
+                    Err(error) => break Some(error),
+                }
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                // Nobody is left to read the rest once the source was dropped.
+                if sender.blocking_send(full).is_err() {
+                    return (source.rows_skipped, source.truncated, None);
+                }
+            };
+            if !batch.is_empty() {
+                let _ = sender.blocking_send(batch);
+            }
+            (source.rows_skipped, source.truncated, error)
+        });
+
+        BlockingCsvSource {
+            receiver,
+            batch: Vec::new().into_iter(),
+            rows_skipped: 0,
+            reader: Some(reader),
+            truncated: false,
+            error: None,
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl TransactionSource for BlockingCsvSource {
+    async fn next(&mut self) -> Option<Transaction> {
+        loop {
+            if let Some((transaction, rows_skipped)) = self.batch.next() {
+                self.rows_skipped = rows_skipped;
+                return Some(transaction);
+            }
+            match self.receiver.recv().await {
+                Some(batch) => self.batch = batch.into_iter(),
+                None => {
+                    // The task finished, so it only has to be joined for the rows skipped after the last transaction.
+                    if let Some(reader) = self.reader.take() {
+                        match reader.await {
+                            Ok((rows_skipped, truncated, error)) => {
+                                self.rows_skipped = rows_skipped;
+                                self.truncated = truncated;
+                                self.error = error;
+                            }
+                            Err(error) => self.error = Some(error.into()),
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn rows_skipped(&self) -> usize {
+        self.rows_skipped
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+
+    fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// A [TransactionSource] reading one JSON transaction per line. Requires the `serde` feature.
+#[cfg(feature = "serde")]
+pub struct NdjsonSource<R: Read> {
+    /// Lines of the input.
+    lines: Lines<BufReader<R>>,
+    /// Number of the last line read, starting at 1.
+    line: u64,
+    /// Number of lines skipped because they could not be parsed.
+    rows_skipped: usize,
+    /// Stop at the first line which can't be parsed instead of skipping it.
+    fail_fast: bool,
+    /// Maximum number of records to read, parsed or skipped.
+    max_rows: Option<usize>,
+    /// Number of records read so far, parsed or skipped.
+    rows_read: usize,
+    /// Set once reading stopped at `max_rows` with records left.
+    truncated: bool,
+    /// Error which stopped reading.
+    error: Option<anyhow::Error>,
+}
+
+#[cfg(feature = "serde")]
+impl<R: Read> NdjsonSource<R> {
+    pub fn new(reader: R) -> Self {
+        NdjsonSource {
+            lines: BufReader::new(reader).lines(),
+            line: 0,
+            rows_skipped: 0,
+            fail_fast: false,
+            max_rows: None,
+            rows_read: 0,
+            truncated: false,
+            error: None,
+        }
+    }
+
+    /// Makes reading stop with [GravencheError::BadRow] at the first line which can't be parsed.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Stops reading after `max_rows` records, counting the skipped ones too. No limit by default.
+    pub fn max_rows(mut self, max_rows: Option<usize>) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<R: Read + Send> TransactionSource for NdjsonSource<R> {
+    async fn next(&mut self) -> Option<Transaction> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => {
+                    self.error = Some(error.into());
+                    return None;
+                }
+            };
+            self.line += 1;
+
+            // Blank lines carry no record.
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A record past the limit shows that there was more to read. It is dropped.
+            if self.max_rows.is_some_and(|max| self.rows_read >= max) {
+                self.truncated = true;
+                return None;
+            }
+            self.rows_read += 1;
+
+            match parse_json_line(&line) {
+                Some(transaction) => return Some(transaction.with_source_line(self.line)),
+                None if self.fail_fast => {
+                    self.error = Some(
+                        GravencheError::BadRow {
+                            line: self.line,
+                            reason: String::from("Not a valid transaction"),
+                        }
+                        .into(),
+                    );
+                    return None;
+                }
+                None => self.rows_skipped += 1,
+            }
+        }
+    }
+
+    fn rows_skipped(&self) -> usize {
+        self.rows_skipped
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+
+    fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// A [TransactionSource] which follows a file like `tail -f`. It reads the file to the end and then waits for new
+/// lines to be appended. It is never exhausted unless reading fails, so it is meant to be stopped from outside.
+#[cfg(feature = "runtime")]
+pub struct FollowSource {
+    /// Path of the followed file.
+    path: PathBuf,
+    /// The followed file.
+    file: File,
+    /// Number of bytes of the file read so far.
+    offset: u64,
+    /// Bytes read but not yet consumed. Ends with a partial line until its newline arrives.
+    buffer: Vec<u8>,
+    /// Format of the lines.
+    input_format: InputFormat,
+    /// Field delimiter of CSV lines.
+    delimiter: u8,
+    /// Lines of CSV starting with this byte are comments.
+    comment: Option<u8>,
+    /// Set while the CSV header row is still to be skipped.
+    header_pending: bool,
+    /// How long to wait before checking the file for new data again.
+    poll_interval: Duration,
+    /// Number of lines skipped because they could not be parsed.
+    rows_skipped: usize,
+    /// Error which stopped reading.
+    error: Option<anyhow::Error>,
+}
+
+#[cfg(feature = "runtime")]
+impl FollowSource {
+    pub fn new(
+        path: &Path,
+        input_format: InputFormat,
+        delimiter: u8,
+        comment: Option<u8>,
+        poll_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        Ok(FollowSource {
+            path: path.to_path_buf(),
+            file: File::open(path)?,
+            offset: 0,
+            buffer: Vec::new(),
+            input_format,
+            delimiter,
+            comment,
+            header_pending: input_format == InputFormat::Csv,
+            poll_interval,
+            rows_skipped: 0,
+            error: None,
+        })
+    }
+
+    // Removes the next complete line from the buffer.
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.buffer.iter().position(|&byte| byte == b'\n')?;
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    // Converts a complete line to a Transaction.
+    fn parse_line(&self, line: &str) -> anyhow::Result<Transaction> {
+        match self.input_format {
+            InputFormat::Csv => parse_csv_line(line, self.delimiter),
+            InputFormat::Ndjson => match parse_json_line(line) {
+                Some(transaction) => Ok(transaction),
+                None => anyhow::bail!("Invalid JSON transaction."),
+            },
+        }
+    }
+
+    // Reads the bytes appended since the last read. Returns false if there are none. A file that got shorter than
+    // what was already read is assumed to be truncated and is read again from the start.
+    fn read_appended(&mut self) -> anyhow::Result<bool> {
+        let read = self.file.read_to_end(&mut self.buffer)?;
+        self.offset += read as u64;
+        if read > 0 {
+            return Ok(true);
+        }
+
+        if self.file.metadata()?.len() < self.offset {
+            tracing::warn!(
+                "{} was truncated, reading it from the start",
+                self.path.display()
+            );
+            self.file.seek(SeekFrom::Start(0))?;
+            self.offset = 0;
+            self.buffer.clear();
+            self.header_pending = self.input_format == InputFormat::Csv;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl TransactionSource for FollowSource {
+    async fn next(&mut self) -> Option<Transaction> {
+        loop {
+            while let Some(line) = self.next_line() {
+                // Blank lines carry no record.
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // Comments may come before the header too.
+                let is_comment = self.input_format == InputFormat::Csv
+                    && self
+                        .comment
+                        .is_some_and(|comment| line.as_bytes().first() == Some(&comment));
+                if is_comment {
+                    continue;
+                }
+                if self.header_pending {
+                    self.header_pending = false;
+                    continue;
+                }
+
+                match self.parse_line(&line) {
+                    Ok(transaction) => return Some(transaction),
+                    Err(error) => {
+                        tracing::error!("Skipping record {}: {}", line.trim(), error);
+                        self.rows_skipped += 1;
+                    }
+                }
+            }
+
+            match self.read_appended() {
+                Ok(true) => {}
+                Ok(false) => tokio::time::sleep(self.poll_interval).await,
+                Err(error) => {
+                    self.error = Some(error);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn rows_skipped(&self) -> usize {
+        self.rows_skipped
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+}
+
+/// A [TransactionSource] yielding the transactions of another source ordered by timestamp. Transactions without a
+/// timestamp come first and transactions with equal timestamps keep their order. All the transactions of the inner
+/// source are read into memory before the first one is returned.
+pub struct SortedSource<S: TransactionSource> {
+    /// Source to read the transactions from. Taken once all of its transactions are buffered.
+    inner: S,
+    /// Buffered transactions in reverse order, so the next one is popped from the end.
+    buffered: Option<Vec<Transaction>>,
+}
+
+impl<S: TransactionSource> SortedSource<S> {
+    pub fn new(inner: S) -> Self {
+        SortedSource {
+            inner,
+            buffered: None,
+        }
+    }
+}
+
+impl<S: TransactionSource + Send> TransactionSource for SortedSource<S> {
+    async fn next(&mut self) -> Option<Transaction> {
+        if self.buffered.is_none() {
+            let mut transactions = Vec::new();
+            while let Some(transaction) = self.inner.next().await {
+                transactions.push(transaction);
+            }
+            // A stable sort keeps the file order of transactions with equal timestamps.
+            transactions.sort_by_key(|transaction| transaction.timestamp);
+            transactions.reverse();
+            self.buffered = Some(transactions);
+        }
+
+        self.buffered.as_mut()?.pop()
+    }
+
+    fn rows_skipped(&self) -> usize {
+        self.inner.rows_skipped()
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.inner.take_error()
+    }
+
+    fn truncated(&self) -> bool {
+        self.inner.truncated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::DateTime;
+    #[cfg(feature = "runtime")]
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // Unparseable records are skipped and counted.
+    #[tokio::test]
+    async fn test_csv_source() {
+        // Prepare
+        let csv = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nbogus, 1, 2, 1.0\nwithdrawal, 1, 3, 0.5\n";
+        let mut source = CsvSource::new(csv.as_bytes());
+
+        // Execute
+        let first = source.next().await.unwrap();
+        let second = source.next().await.unwrap();
+
+        // Assert
+        assert_eq!(first.id, 1);
+        assert_eq!(second.id, 3);
+        assert!(source.next().await.is_none());
+        assert_eq!(source.rows_skipped(), 1);
+        assert!(source.take_error().is_none());
+    }
+
+    // A currency column is found by its name and the columns after it are read at their usual positions.
+    #[tokio::test]
+    async fn test_csv_source_currency_column() {
+        // Prepare
+        let csv = "type, client, tx, Currency, amount, timestamp\ndeposit, 1, 1, eur, 1.0, 7\ndeposit, 1, 2, , 2.0\n";
+        let mut source = CsvSource::new(csv.as_bytes());
+
+        // Execute
+        let first = source.next().await.unwrap();
+        let second = source.next().await.unwrap();
+
+        // Assert
+        assert_eq!(first.currency.unwrap().as_str(), "EUR");
+        assert_eq!(first.amount, 1.0);
+        assert_eq!(first.timestamp, DateTime::from_timestamp(7, 0));
+        assert_eq!(second.currency, None);
+        assert_eq!(second.amount, 2.0);
+        assert_eq!(source.rows_skipped(), 0);
+    }
+
+    // A timestamp column is found by its name and the columns after it are read at their usual positions.
+    #[tokio::test]
+    async fn test_csv_source_timestamp_column() {
+        // Prepare
+        let csv = "type, timestamp, client, tx, amount
+\
+                   deposit, 2024-03-01T12:00:00Z, 1, 1, 1.0
+\
+                   transfer, , 1, 2, 0.5, 3
+";
+        let mut source = CsvSource::new(csv.as_bytes());
+
+        // Execute
+        let first = source.next().await.unwrap();
+        let second = source.next().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            first.timestamp,
+            Some("2024-03-01T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(first.amount, 1.0);
+        assert_eq!(second.timestamp, None);
+        assert_eq!(second.counterparty, Some(3));
+        assert_eq!(source.rows_skipped(), 0);
+    }
+
+    // A truncated record is skipped and the records after it are still read.
+    #[tokio::test]
+    async fn test_csv_source_truncated_record() {
+        // Prepare
+        let csv = "type, client, tx, amount\ndeposit, 1\ndeposit, 1, 2, 1.0\ndeposit, 2, 3, 1.0\n";
+        let mut source = CsvSource::new(csv.as_bytes());
+
+        // Execute
+        let first = source.next().await.unwrap();
+        let second = source.next().await.unwrap();
+
+        // Assert
+        assert_eq!(first.id, 2);
+        assert_eq!(second.id, 3);
+        assert!(source.next().await.is_none());
+        assert_eq!(source.rows_skipped(), 1);
+        assert!(source.take_error().is_none());
+    }
+
+    // Counts the bytes read from a reader.
+    #[cfg(feature = "runtime")]
+    struct CountingReader<R: Read> {
+        inner: R,
+        bytes_read: Arc<AtomicUsize>,
+    }
+
+    #[cfg(feature = "runtime")]
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.bytes_read.fetch_add(read, Ordering::Relaxed);
+            Ok(read)
+        }
+    }
+
+    // Parsing in a blocking task yields the same transactions and skipped records, and reads only a few batches ahead.
+    #[cfg(feature = "runtime")]
+    #[tokio::test]
+    async fn test_blocking_csv_source() {
+        // Prepare
+        let rows = READ_BATCH * 100;
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=rows {
+            if tx % 1000 == 0 {
+                csv.push_str("bogus, 1, 1, 1.0\n");
+            }
+            csv.push_str(&format!("deposit, {}, {}, 1.0\n", tx % 100, tx));
+        }
+        let bytes_read = Arc::new(AtomicUsize::new(0));
+        let reader = CountingReader {
+            inner: std::io::Cursor::new(csv.clone().into_bytes()),
+            bytes_read: bytes_read.clone(),
+        };
+        let mut source = BlockingCsvSource::spawn(CsvSource::new(reader), READ_BATCH);
+        let mut expected = CsvSource::new(csv.as_bytes());
+
+        // Execute
+        let first = source.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let read_ahead = bytes_read.load(Ordering::Relaxed);
+        let mut transactions = vec![first];
+        while let Some(transaction) = source.next().await {
+            transactions.push(transaction);
+        }
+
+        // Assert
+        assert!(
+            read_ahead < csv.len() / 10,
+            "read {} bytes ahead",
+            read_ahead
+        );
+        assert_eq!(transactions.len(), rows);
+        for transaction in transactions {
+            let expected = expected.next().await.unwrap();
+            assert_eq!(transaction.id, expected.id);
+            assert_eq!(transaction.client_id, expected.client_id);
+            assert_eq!(transaction.source_line, expected.source_line);
+        }
+        assert!(expected.next().await.is_none());
+        assert_eq!(source.rows_skipped(), rows / 1000);
+        assert!(source.take_error().is_none());
+    }
+
+    // The batches in flight never hold more transactions than asked for, down to a read-ahead of two.
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn test_batch_sizes() {
+        for read_ahead in [2, 3, 10, 100, 1000, READ_BATCH, READ_BATCH * 2, 100_000] {
+            // Execute
+            let (batch_size, batches) = batch_sizes(read_ahead);
+
+            // Assert
+            assert!(batch_size <= READ_BATCH);
+            assert!(batches >= 1);
+            assert!(
+                (batches + 1) * batch_size <= read_ahead,
+                "read-ahead of {} holds {} batches of {}",
+                read_ahead,
+                batches + 1,
+                batch_size
+            );
+        }
+        assert_eq!(batch_sizes(100_000), (READ_BATCH, 96));
+        assert_eq!(batch_sizes(0), (1, 1));
+    }
+
+    // An error stopping the reading task is returned after the transactions read before it.
+    #[cfg(feature = "runtime")]
+    #[tokio::test]
+    async fn test_blocking_csv_source_fail_fast() {
+        // Prepare
+        let csv =
+            "type, client, tx, amount\ndeposit, 1, 1, 1.0\nbogus, 1, 2, 1.0\ndeposit, 1, 3, 1.0\n";
+        let source = CsvSource::new(csv.as_bytes()).fail_fast(true);
+        let mut source = BlockingCsvSource::spawn(source, READ_BATCH);
+
+        // Execute
+        let first = source.next().await.unwrap();
+        let second = source.next().await;
+
+        // Assert
+        assert_eq!(first.id, 1);
+        assert!(second.is_none());
+        assert!(source.take_error().is_some());
+    }
+}
@@ -0,0 +1,178 @@
+//! This module contains a synchronous processing path which uses neither tokio nor channels, for environments
+//! without an async runtime or a file system such as WebAssembly. It is available with the `sync` feature and builds
+//! without the default `runtime` feature, e.g. `--no-default-features --features sync`.
+
+use crate::ledger::RunRules;
+use crate::observer::TransactionObserver;
+use crate::source::CsvSource;
+use crate::types::{
+    client::{Client, ClientId, ClientStore},
+    config::Config,
+};
+use std::{collections::HashMap, io::Read};
+
+/// Processes CSV transactions from `reader` with the default options, applying them the same way as
+/// [Gravenche::start](crate::Gravenche::start), and returns the clients. Records which can't be parsed are skipped.
+/// Reading stops at the first I/O error and the clients reflect the transactions read until then.
+pub fn process_sync(reader: impl Read) -> HashMap<ClientId, Client> {
+    let mut clients = HashMap::new();
+    process_sync_into(reader, &mut clients);
+    clients
+}
+
+/// Processes CSV transactions from `reader` like [process_sync], but applies them to the clients of `clients`, which
+/// can be any [ClientStore].
+pub fn process_sync_into<S: ClientStore>(reader: impl Read, clients: &mut S) {
+    process(reader, clients, None);
+}
+
+/// Processes CSV transactions from `reader` into `clients` like [process_sync_into], and tells `observer` the outcome of
+/// every transaction.
+pub fn process_sync_observed<S: ClientStore>(
+    reader: impl Read,
+    clients: &mut S,
+    observer: &mut dyn TransactionObserver,
+) {
+    process(reader, clients, Some(observer));
+}
+
+// Processes the transactions of `reader`, telling `observer` the outcome of each if there is one.
+fn process<S: ClientStore>(
+    reader: impl Read,
+    clients: &mut S,
+    mut observer: Option<&mut dyn TransactionObserver>,
+) {
+    let config = Config::default();
+    let mut source = CsvSource::with_format(reader, config.delimiter, config.comment);
+    let mut processed_transactions = HashMap::new();
+    let mut rules = RunRules::new(&config);
+    let mut position = 0;
+
+    loop {
+        match source.next_transaction() {
+            Ok(Some(transaction)) => {
+                position += 1;
+                // Rejections are only counted by the metrics of the processor task.
+                let _ = rules.apply(
+                    clients,
+                    &mut processed_transactions,
+                    &config,
+                    &transaction,
+                    position,
+                    observer
+                        .as_mut()
+                        .map(|observer| &mut **observer as &mut dyn TransactionObserver),
+                );
+            }
+            Ok(None) => return,
+            Err(error) => {
+                tracing::error!("Reading input failed: {:#}", error);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::metrics::Rejection;
+    use crate::types::transaction::Transaction;
+    #[cfg(feature = "runtime")]
+    use crate::types::{client::snapshots, config::DEFAULT_DECIMALS};
+    #[cfg(feature = "runtime")]
+    use crate::Gravenche;
+    #[cfg(feature = "runtime")]
+    use std::{fs::File, path::PathBuf};
+
+    // Disputes, resolves and chargebacks are applied without a runtime, and malformed records are skipped.
+    #[test]
+    fn test_process_sync() {
+        // Prepare
+        let csv = "type, client, tx, amount\ndeposit, 1, 1, 10.0\ndeposit, 1, 2, 2.0\ndeposit, 2, 3, 5.0\n\
+                   deposit, 2, 4, 1.0\nbogus, 1, 5, 1.0\ndispute, 1, 1, \nresolve, 1, 1, \ndispute, 2, 3, \n\
+                   chargeback, 2, 3, \n";
+
+        // Execute
+        let clients = process_sync(csv.as_bytes());
+
+        // Assert
+        assert_eq!(clients[&1].available, 12.0);
+        assert_eq!(clients[&1].held, 0.0);
+        assert!(!clients[&1].locked);
+        assert_eq!(clients[&2].total, 1.0);
+        assert!(clients[&2].locked);
+    }
+
+    // Records the ID of every transaction it is told about with the total of the client, or why it was rejected.
+    #[derive(Default)]
+    struct RecordingObserver(Vec<(u32, Result<f32, Rejection>)>);
+
+    impl TransactionObserver for RecordingObserver {
+        fn on_applied(&mut self, transaction: &Transaction, client: &Client) {
+            self.0.push((transaction.id, Ok(client.total)));
+        }
+
+        fn on_rejected(&mut self, transaction: &Transaction, rejection: Rejection) {
+            self.0.push((transaction.id, Err(rejection)));
+        }
+    }
+
+    // The observer is told the outcome of every transaction processed without a runtime, in order.
+    #[test]
+    fn test_process_sync_observed() {
+        // Prepare
+        let csv = "type, client, tx, amount\ndeposit, 1, 1, 10.0\nwithdrawal, 1, 2, 20.0\ndeposit, 1, 3, 5.0\n\
+                   dispute, 1, 1, \nchargeback, 1, 1, \n";
+        let mut observer = RecordingObserver::default();
+        let mut clients = HashMap::new();
+
+        // Execute
+        process_sync_observed(csv.as_bytes(), &mut clients, &mut observer);
+
+        // Assert
+        assert_eq!(
+            observer.0,
+            vec![
+                (1, Ok(10.0)),
+                (2, Err(Rejection::InsufficientFunds)),
+                (3, Ok(15.0)),
+                (1, Ok(15.0)),
+                (1, Ok(5.0)),
+            ]
+        );
+    }
+
+    // The synchronous path ends in the same state as the processor task for every fixture.
+    #[cfg(feature = "runtime")]
+    #[tokio::test]
+    async fn test_process_sync_matches_async() {
+        for path in [
+            "tests/data/disputes.csv",
+            "tests/data/locked_clients.csv",
+            "tests/data/withdrawals.csv",
+            "tests/data/open_disputes.csv",
+            "tests/data/malformed.csv",
+            "test_data.csv",
+        ] {
+            // Prepare
+            let mut gravenche = Gravenche::new(PathBuf::from(path), 100, Vec::new());
+            gravenche.start().await.unwrap();
+
+            // Execute
+            let clients = process_sync(File::open(path).unwrap());
+
+            // Assert
+            assert_eq!(
+                snapshots(clients.values(), DEFAULT_DECIMALS),
+                snapshots(
+                    gravenche.snapshot_clients().await.values(),
+                    DEFAULT_DECIMALS
+                ),
+                "{}",
+                path
+            );
+        }
+    }
+}
@@ -1,3 +1,6 @@
 pub mod client;
+pub mod config;
+pub mod currency;
+pub mod error;
 pub mod other;
 pub mod transaction;
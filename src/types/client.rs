@@ -1,13 +1,41 @@
 //! This module contains a Client struct used to store client data.
 
-use std::{collections::HashMap, sync::Arc};
+use super::{config::DEFAULT_DECIMALS, currency::Currency};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "runtime")]
+use std::sync::Arc;
+use std::{collections::HashMap, fmt, mem};
+#[cfg(feature = "runtime")]
 use tokio::sync::Mutex;
 
+/// Client ID. Changing it changes the width of client IDs everywhere, including the saved state.
+pub type ClientId = u32;
+
+/// Largest difference between two amounts with `decimals` decimal places still considered equal, half a unit in the
+/// last decimal place. Smaller differences are floating point rounding errors.
+pub fn amount_tolerance(decimals: u32) -> f32 {
+    (0.5 / 10f64.powi(decimals as i32)) as f32
+}
+
+/// Compares two amounts with `decimals` decimal places ignoring rounding errors within [amount_tolerance].
+pub fn amounts_equal(a: f32, b: f32, decimals: u32) -> bool {
+    (a - b).abs() < amount_tolerance(decimals)
+}
+
+/// Rounds an amount to `decimals` decimal places. Amounts are `f32`, so digits beyond about seven significant ones are
+/// lost whatever the number of decimals.
+pub fn round_amount(amount: f32, decimals: u32) -> f32 {
+    let scale = 10f64.powi(decimals as i32);
+    ((f64::from(amount) * scale).round() / scale) as f32
+}
+
 /// A struct to store client data.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Client {
     /// Client ID.
-    pub id: u16,
+    pub id: ClientId,
     /// The total funds that are available or held. This should be equal to available + held.
     pub total: f32,
     /// The total funds that are available for trading, staking, withdrawal, etc. This should be equal to the total - held amounts.
@@ -16,17 +44,210 @@ pub struct Client {
     pub held: f32,
     /// A flag indicating if the account is locked. An account is locked if a charge back occurs.
     pub locked: bool,
+    /// Number of disputes raised against transactions of the client.
+    pub dispute_count: u32,
+    /// Number of chargebacks of the client.
+    pub chargeback_count: u32,
+    /// Set once the available funds dropped below zero, even if they recovered since.
+    pub was_negative: bool,
+    /// Balances in currencies other than the base currency, which is kept in `total`, `available` and `held`. Empty,
+    /// and not allocated, unless the client has transactions in another currency.
+    pub currencies: Vec<CurrencyBalance>,
+}
+
+/// The funds of a [Client] in a single currency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CurrencyBalance {
+    pub currency: Currency,
+    pub total: f32,
+    pub available: f32,
+    pub held: f32,
+}
+
+impl CurrencyBalance {
+    /// Builds a zero balance in `currency`.
+    pub fn new(currency: Currency) -> Self {
+        CurrencyBalance {
+            currency,
+            total: 0.0,
+            available: 0.0,
+            held: 0.0,
+        }
+    }
+}
+
+/// A plain copy of the state of a [Client]. Amounts are compared using [amounts_equal] with the finer of the decimals
+/// of both snapshots. It is serialized with the client ID named `client` like in the input, and can be deserialized
+/// the same way, e.g. as the expected clients of a test case, which are then compared with [DEFAULT_DECIMALS].
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClientSnapshot {
+    #[cfg_attr(feature = "serde", serde(rename = "client"))]
+    pub id: ClientId,
+    pub total: f32,
+    pub available: f32,
+    pub held: f32,
+    pub locked: bool,
+    /// Number of decimal places of the amounts, which decides how close they must be to compare equal.
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_decimals"))]
+    pub decimals: u32,
+}
+
+#[cfg(feature = "serde")]
+fn default_decimals() -> u32 {
+    DEFAULT_DECIMALS
+}
+
+/// Returns the [snapshots](Client::snapshot) of `clients` ordered by client ID, with amounts of `decimals` decimal
+/// places. Two sets of clients hold the same funds if their snapshots are equal.
+pub fn snapshots<'a>(
+    clients: impl IntoIterator<Item = &'a Client>,
+    decimals: u32,
+) -> Vec<ClientSnapshot> {
+    let mut snapshots: Vec<ClientSnapshot> = clients
+        .into_iter()
+        .map(|client| client.snapshot(decimals))
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.id);
+    snapshots
+}
+
+impl PartialEq for ClientSnapshot {
+    fn eq(&self, other: &Self) -> bool {
+        let decimals = self.decimals.max(other.decimals);
+        self.id == other.id
+            && amounts_equal(self.total, other.total, decimals)
+            && amounts_equal(self.available, other.available, decimals)
+            && amounts_equal(self.held, other.held, decimals)
+            && self.locked == other.locked
+    }
+}
+
+/// A client whose total differs from the sum of its available and held funds by more than [amount_tolerance].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvariantViolation {
+    pub client_id: ClientId,
+    pub total: f32,
+    pub available: f32,
+    pub held: f32,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Client {}: total {} is not available {} + held {}",
+            self.client_id, self.total, self.available, self.held
+        )
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Clients are equal if their [snapshots](Client::snapshot) are, with amounts compared at [DEFAULT_DECIMALS]. Compare
+/// the snapshots directly to use other decimals.
+impl PartialEq for Client {
+    fn eq(&self, other: &Self) -> bool {
+        self.snapshot(DEFAULT_DECIMALS) == other.snapshot(DEFAULT_DECIMALS)
+    }
 }
 
 impl Client {
-    /// Builds a new Client
-    pub fn new(id: u16, available: f32) -> Self {
+    /// Builds a new Client with zero balance. Funds are added using [Client::deposit].
+    pub fn new(id: ClientId) -> Self {
         Client {
             id,
-            total: available, // Initially total is same as available because held is 0.
-            available,
+            total: 0.0,
+            available: 0.0,
             held: 0.0,
             locked: false,
+            dispute_count: 0,
+            chargeback_count: 0,
+            was_negative: false,
+            currencies: Vec::new(),
+        }
+    }
+
+    /// Returns the balances of the client, the one in `base_currency` first and the others in the order they were
+    /// first used.
+    pub fn balances(&self, base_currency: Currency) -> impl Iterator<Item = CurrencyBalance> + '_ {
+        let base = CurrencyBalance {
+            currency: base_currency,
+            total: self.total,
+            available: self.available,
+            held: self.held,
+        };
+        std::iter::once(base).chain(self.currencies.iter().copied())
+    }
+
+    /// Runs `operation` on the balance in `currency` instead of the base currency, e.g.
+    /// `client.in_currency(Some(eur), |client| client.deposit(10.0))`. The balance is swapped into `total`,
+    /// `available` and `held` for the operation, so every method of the client works on any currency. `None` is the
+    /// base currency. A balance created for the operation is dropped again if it is still zero.
+    pub fn in_currency<R>(
+        &mut self,
+        currency: Option<Currency>,
+        operation: impl FnOnce(&mut Client) -> R,
+    ) -> R {
+        let Some(currency) = currency else {
+            return operation(self);
+        };
+        let (index, created) = match self
+            .currencies
+            .iter()
+            .position(|balance| balance.currency == currency)
+        {
+            Some(index) => (index, false),
+            None => {
+                self.currencies.push(CurrencyBalance::new(currency));
+                (self.currencies.len() - 1, true)
+            }
+        };
+
+        self.swap_balance(index);
+        let result = operation(self);
+        self.swap_balance(index);
+
+        if created && self.currencies[index] == CurrencyBalance::new(currency) {
+            self.currencies.pop();
+        }
+        result
+    }
+
+    // Swaps the base currency funds with the balance at `index` of the other currencies.
+    fn swap_balance(&mut self, index: usize) {
+        let balance = &mut self.currencies[index];
+        mem::swap(&mut self.total, &mut balance.total);
+        mem::swap(&mut self.available, &mut balance.available);
+        mem::swap(&mut self.held, &mut balance.held);
+    }
+
+    /// Returns a copy of the current state of the client, with amounts of `decimals` decimal places.
+    pub fn snapshot(&self, decimals: u32) -> ClientSnapshot {
+        ClientSnapshot {
+            id: self.id,
+            total: self.total,
+            available: self.available,
+            held: self.held,
+            locked: self.locked,
+            decimals,
+        }
+    }
+
+    /// Checks that the total equals the available plus the held funds in every currency, ignoring rounding errors
+    /// within the [amount_tolerance] of `decimals` decimal places.
+    pub fn check_invariants(&self, decimals: u32) -> Result<(), InvariantViolation> {
+        match self.balances(Currency::default()).find(|balance| {
+            !amounts_equal(balance.total, balance.available + balance.held, decimals)
+        }) {
+            Some(balance) => Err(InvariantViolation {
+                client_id: self.id,
+                total: balance.total,
+                available: balance.available,
+                held: balance.held,
+            }),
+            None => Ok(()),
         }
     }
 
@@ -35,6 +256,7 @@ impl Client {
         if !self.locked {
             self.total += amount;
             self.available += amount;
+            self.track_negative();
         } else {
             anyhow::bail!("Account is locked. Unable to deposit.")
         }
@@ -43,12 +265,24 @@ impl Client {
 
     /// Withddraws the amount.
     pub fn withdraw(&mut self, amount: f32) -> anyhow::Result<()> {
+        self.withdraw_with_overdraft(amount, 0.0)
+    }
+
+    /// Withdraws funds, letting available funds go down to minus `overdraft_limit`. Without an overdraft the funds
+    /// may go down to zero. Withdrawing zero is a no-op which always succeeds on an unlocked account.
+    pub fn withdraw_with_overdraft(
+        &mut self,
+        amount: f32,
+        overdraft_limit: f32,
+    ) -> anyhow::Result<()> {
         if !self.locked {
-            // Allow withdrawl only if account has sufficient balance.
+            // Allow withdrawal only if account has sufficient balance, or stays within its overdraft.
             let available_fund = self.available;
-            if available_fund - amount > 0.0 {
+            let sufficient = amount == 0.0 || available_fund - amount >= -overdraft_limit;
+            if sufficient {
                 self.total -= amount;
                 self.available -= amount;
+                self.track_negative();
             } else {
                 anyhow::bail!("Account balance is not sufficient. Unable to withdraw.")
             }
@@ -58,6 +292,20 @@ impl Client {
         Ok(())
     }
 
+    /// Takes a deposit back. The available funds must cover the amount, so the whole balance can be taken back, but
+    /// the overdraft limit doesn't apply.
+    pub fn reverse_deposit(&mut self, amount: f32) -> anyhow::Result<()> {
+        if self.locked {
+            anyhow::bail!("Account is locked. Unable to reverse a deposit.")
+        }
+        if self.available < amount {
+            anyhow::bail!("Account balance is not sufficient. Unable to reverse a deposit.")
+        }
+        self.total -= amount;
+        self.available -= amount;
+        Ok(())
+    }
+
     /// Raises a dispute.
     pub fn raise_dispute(&mut self, amount: f32) -> anyhow::Result<()> {
         if !self.locked {
@@ -66,6 +314,8 @@ impl Client {
             if available_fund - amount > 0.0 {
                 self.available -= amount;
                 self.held += amount;
+                self.dispute_count += 1;
+                self.track_negative();
             } else {
                 anyhow::bail!("Account balance is not sufficient. Unable to raise dispute.")
             }
@@ -75,11 +325,35 @@ impl Client {
         Ok(())
     }
 
+    /// Raises a dispute of a withdrawal. The disputed amount is held on top of the available funds, which are left
+    /// alone since the client already paid it out.
+    pub fn raise_withdrawal_dispute(&mut self, amount: f32) -> anyhow::Result<()> {
+        if self.locked {
+            anyhow::bail!("Account is locked. Unable to raise dispute.")
+        }
+        self.held += amount;
+        self.total += amount;
+        self.dispute_count += 1;
+        Ok(())
+    }
+
+    /// Releases the dispute of a withdrawal without crediting the client: the held amount leaves the total again, since
+    /// the withdrawal stands.
+    pub fn release_withdrawal_dispute(&mut self, amount: f32) -> anyhow::Result<()> {
+        if self.locked {
+            anyhow::bail!("Account is locked. Unable to resolve a dispute.")
+        }
+        self.held -= amount;
+        self.total -= amount;
+        Ok(())
+    }
+
     /// Resolves existing dispute.
     pub fn resolve_dispute(&mut self, amount: f32) -> anyhow::Result<()> {
         if !self.locked {
             self.available += amount;
             self.held -= amount;
+            self.track_negative();
         } else {
             anyhow::bail!("Account is locked. Unable to resolve a dispute.")
         }
@@ -94,36 +368,150 @@ impl Client {
 
             // Chargeback occured so account must be locked.
             self.locked = true;
+            self.chargeback_count += 1;
         } else {
             anyhow::bail!("Account is already locked. Unable to perform chargeback twice.")
         }
         Ok(())
     }
+
+    /// Performs a chargeback of a withdrawal, which credits the held amount to the available funds and locks the account.
+    pub fn chargeback_withdrawal(&mut self, amount: f32) -> anyhow::Result<()> {
+        if self.locked {
+            anyhow::bail!("Account is already locked. Unable to perform chargeback twice.")
+        }
+        self.held -= amount;
+        self.available += amount;
+        self.locked = true;
+        self.chargeback_count += 1;
+        Ok(())
+    }
+
+    /// Unlocks the account, e.g. after a chargeback was investigated. Balances are unchanged.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+    }
+
+    /// Credits the amount like a deposit, even if the account is locked.
+    pub fn admin_credit(&mut self, amount: f32) {
+        self.total += amount;
+        self.available += amount;
+        self.track_negative();
+    }
+
+    /// Debits the amount from available funds, even if the account is locked. The available funds may drop to zero but
+    /// not below.
+    pub fn admin_debit(&mut self, amount: f32) -> anyhow::Result<()> {
+        if self.available - amount < 0.0 {
+            anyhow::bail!("Account balance is not sufficient. Unable to debit.")
+        }
+        self.total -= amount;
+        self.available -= amount;
+        self.track_negative();
+        Ok(())
+    }
+
+    // Flags the client once its available funds are below zero after an operation. Amounts aren't checked for their
+    // sign, so e.g. a negative deposit takes the funds below zero.
+    fn track_negative(&mut self) {
+        if self.available < 0.0 {
+            self.was_negative = true;
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    // Formats the client as its row of the client table with the default columns and precision, without the line
+    // break, e.g. `     1 |        1.5 | ...`. Only the funds in the base currency are shown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut row = Vec::new();
+        crate::output::write_table_row(&mut row, self, DEFAULT_DECIMALS, false)
+            .map_err(|_| fmt::Error)?;
+        f.write_str(String::from_utf8_lossy(&row).trim_end_matches('\n'))
+    }
 }
 
 /// A HashMap to store data of all the clients.
-pub type Clients = Arc<Mutex<HashMap<u16, Client>>>;
+#[cfg(feature = "runtime")]
+pub type Clients = Arc<Mutex<HashMap<ClientId, Client>>>;
+
+/// Storage of the clients transactions are applied to. The processing state machine, the processor task and
+/// checkpoints only reach clients through this trait, so clients can be kept somewhere other than the default
+/// in-memory [HashMap], e.g. on disk or in shards. [Gravenche](crate::Gravenche) itself still holds a [HashMap], which
+/// its exports, saved states and the HTTP API read directly.
+pub trait ClientStore {
+    /// Returns the client with the ID, if it exists.
+    fn get(&self, id: ClientId) -> Option<&Client>;
+
+    /// Returns the client with the ID for modification, if it exists.
+    fn get_mut(&mut self, id: ClientId) -> Option<&mut Client>;
+
+    /// Returns the client with the ID for modification, creating it with a zero balance if it doesn't exist yet.
+    fn get_or_create(&mut self, id: ClientId) -> &mut Client;
+
+    /// Returns all the clients in no particular order.
+    fn iter(&self) -> impl Iterator<Item = &Client>;
+}
+
+impl ClientStore for HashMap<ClientId, Client> {
+    fn get(&self, id: ClientId) -> Option<&Client> {
+        HashMap::get(self, &id)
+    }
+
+    fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        HashMap::get_mut(self, &id)
+    }
+
+    fn get_or_create(&mut self, id: ClientId) -> &mut Client {
+        self.entry(id).or_insert_with(|| Client::new(id))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.values()
+    }
+}
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
+    // A new client starts with zero balance.
+    #[test]
+    fn test_new() {
+        // Execute
+        let client = Client::new(1);
+
+        // Assert
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 0.0);
+        assert!(!client.locked);
+    }
+
     // Tests deposit method happy path.
     #[test]
     fn test_deposit() {
         // Prepare
         let test_available_balance = 1000_f32;
         let balance_after_deposit = test_available_balance + 1000_f32;
-        let mut client = Client::new(1, test_available_balance);
+        let mut client = Client::new(1);
+        client.deposit(test_available_balance).unwrap();
+
+        let expected_client = ClientSnapshot {
+            id: 1,
+            total: balance_after_deposit,
+            available: balance_after_deposit,
+            held: 0.0,
+            locked: false,
+            decimals: DEFAULT_DECIMALS,
+        };
 
         // Execute
         client.deposit(1000_f32).unwrap();
 
         // Assert
-        assert_eq!(client.available, balance_after_deposit);
-        assert_eq!(client.total, balance_after_deposit);
-        assert_eq!(client.held, 0.0);
+        assert_eq!(client.snapshot(DEFAULT_DECIMALS), expected_client);
     }
 
     // Tests deposit method when client is locked.
@@ -132,7 +520,8 @@ mod tests {
     fn test_deposit_when_locked() {
         // Prepare
         let test_available_balance = 1000_f32;
-        let mut client = Client::new(1, test_available_balance);
+        let mut client = Client::new(1);
+        client.deposit(test_available_balance).unwrap();
 
         // Execute
         client.chargeback(1000_f32).unwrap();
@@ -145,15 +534,41 @@ mod tests {
         // Prepare
         let test_available_balance = 1000_f32;
         let balance_after_withdraw = test_available_balance - 500_f32;
-        let mut client = Client::new(1, test_available_balance);
+        let mut client = Client::new(1);
+        client.deposit(test_available_balance).unwrap();
+
+        let expected_client = ClientSnapshot {
+            id: 1,
+            total: balance_after_withdraw,
+            available: balance_after_withdraw,
+            held: 0.0,
+            locked: false,
+            decimals: DEFAULT_DECIMALS,
+        };
 
         // Execute
         client.withdraw(500_f32).unwrap();
 
         // Assert
-        assert_eq!(client.available, balance_after_withdraw);
-        assert_eq!(client.total, balance_after_withdraw);
-        assert_eq!(client.held, 0.0);
+        assert_eq!(client.snapshot(DEFAULT_DECIMALS), expected_client);
+    }
+
+    // All of the available funds can be withdrawn without an overdraft, and no more.
+    #[test]
+    fn test_withdraw_all() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10.0).unwrap();
+
+        // Execute
+        let all = client.withdraw(10.0);
+        let more = client.withdraw(0.5);
+
+        // Assert
+        assert!(all.is_ok());
+        assert!(more.is_err());
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.total, 0.0);
     }
 
     // Tests withdraw method when client is locked.
@@ -162,7 +577,8 @@ mod tests {
     fn test_withdraw_when_locked() {
         // Prepare
         let test_available_balance = 1000_f32;
-        let mut client = Client::new(1, test_available_balance);
+        let mut client = Client::new(1);
+        client.deposit(test_available_balance).unwrap();
 
         // Execute
         client.chargeback(1000_f32).unwrap();
@@ -175,18 +591,86 @@ mod tests {
     fn test_withdraw_insufficient_balance() {
         // Prepare
         let test_available_balance = 1000_f32;
-        let mut client = Client::new(1, test_available_balance);
+        let mut client = Client::new(1);
+        client.deposit(test_available_balance).unwrap();
 
         // Execute
         client.chargeback(1000_f32).unwrap();
         client.withdraw(500_f32).unwrap();
     }
 
+    // deposit 100, withdraw 100, dispute 100, withdraw 10: the dispute of funds which are already gone can't take the
+    // available funds below zero, and the withdrawal is rejected although the client is not locked.
+    #[test]
+    fn test_withdraw_after_disputing_withdrawn_funds() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(100.0).unwrap();
+        client.withdraw(100.0).unwrap();
+        let dispute = client.raise_dispute(100.0);
+
+        // Execute
+        let result = client.withdraw(10.0);
+
+        // Assert
+        assert!(dispute.is_err());
+        assert!(result.is_err());
+        assert!(!client.locked);
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 0.0);
+    }
+
+    // withdraw_with_overdraft allows available funds down to minus the limit and no further.
+    #[test]
+    fn test_withdraw_with_overdraft() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10.0).unwrap();
+
+        // Execute
+        client.withdraw_with_overdraft(20.0, 10.0).unwrap();
+        let beyond = client.withdraw_with_overdraft(0.5, 10.0);
+
+        // Assert
+        assert!(beyond.is_err());
+        assert_eq!(client.available, -10.0);
+        assert_eq!(client.total, -10.0);
+        assert!(client.was_negative);
+        assert!(client.raise_dispute(1.0).is_err());
+    }
+
+    // Operations in another currency leave the base currency alone, and a balance left at zero is dropped.
+    #[test]
+    fn test_in_currency() {
+        // Prepare
+        let mut client = Client::new(1);
+        let eur: Currency = "EUR".parse().unwrap();
+        client.deposit(10.0).unwrap();
+
+        // Execute
+        client
+            .in_currency(Some(eur), |client| client.deposit(5.0))
+            .unwrap();
+        let failed =
+            client.in_currency(Some("GBP".parse().unwrap()), |client| client.withdraw(1.0));
+
+        // Assert
+        assert!(failed.is_err());
+        assert_eq!(client.available, 10.0);
+        assert_eq!(client.total, 10.0);
+        let balances: Vec<CurrencyBalance> = client.balances(Currency::USD).collect();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[1].currency, eur);
+        assert_eq!(balances[1].available, 5.0);
+        assert!(client.check_invariants(DEFAULT_DECIMALS).is_ok());
+    }
+
     // raise_dispute happy path.
     #[test]
     fn test_dispute() {
         // Prepare
-        let mut client = Client::new(1, 0.0);
+        let mut client = Client::new(1);
         client.deposit(1000.0).unwrap();
 
         // Execute
@@ -203,7 +687,7 @@ mod tests {
     #[should_panic]
     fn test_dispute_when_insufficient_balance() {
         // Prepare
-        let mut client = Client::new(1, 0.0);
+        let mut client = Client::new(1);
 
         // Execute
         client.raise_dispute(430.0).unwrap();
@@ -214,7 +698,8 @@ mod tests {
     #[should_panic]
     fn test_dispute_when_locked() {
         // Prepare
-        let mut client = Client::new(1, 10000.0);
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
         client.chargeback(10000.0).unwrap();
 
         // Execute
@@ -225,7 +710,8 @@ mod tests {
     #[test]
     fn test_resolve_dispute() {
         // Prepare
-        let mut client = Client::new(1, 10000.0);
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
         client.raise_dispute(5000.0).unwrap();
 
         // Execute
@@ -242,7 +728,8 @@ mod tests {
     #[should_panic]
     fn test_resolve_dispute_when_locked() {
         // Prepare
-        let mut client = Client::new(1, 10000.0);
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
         client.raise_dispute(5000.0).unwrap();
         client.chargeback(5000.0).unwrap();
 
@@ -254,14 +741,16 @@ mod tests {
     #[test]
     fn test_chargeback() {
         // Prepare
-        let mut client = Client::new(1, 10000.0);
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
         client.raise_dispute(5000.0).unwrap();
 
         // Execute
         client.chargeback(5000.0).unwrap();
 
         // Assert
-        assert_eq!(client.locked, true);
+        assert!(client.locked);
+        assert_eq!((client.dispute_count, client.chargeback_count), (1, 1));
     }
 
     // chargeback in case of already chargedback client.
@@ -269,10 +758,212 @@ mod tests {
     #[should_panic]
     fn test_chargeback_already_chargeback() {
         // Prepare
-        let mut client = Client::new(1, 10000.0);
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
         client.chargeback(5000.0).unwrap();
 
         // Execute
         client.chargeback(5000.0).unwrap();
     }
+
+    // unlock clears the lock and leaves the balances alone.
+    #[test]
+    fn test_unlock() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
+        client.raise_dispute(4000.0).unwrap();
+        client.chargeback(4000.0).unwrap();
+
+        // Execute
+        client.unlock();
+
+        // Assert
+        assert!(!client.locked);
+        assert_eq!(client.total, 6000.0);
+        assert_eq!(client.available, 6000.0);
+        assert_eq!(client.held, 0.0);
+    }
+
+    // Admin adjustments apply to a locked account, and a debit may empty the available funds but not overdraw them.
+    #[test]
+    fn test_admin_adjustments() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
+        client.raise_dispute(4000.0).unwrap();
+        client.chargeback(4000.0).unwrap();
+
+        // Execute
+        client.admin_credit(1000.0);
+        let overdraft = client.admin_debit(7001.0);
+        let debit = client.admin_debit(7000.0);
+
+        // Assert
+        assert!(client.locked);
+        assert!(overdraft.is_err());
+        assert!(debit.is_ok());
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.total, 0.0);
+    }
+
+    // A withdrawal dispute holds the amount without touching available funds, and a resolve returns it to them.
+    #[test]
+    fn test_raise_withdrawal_dispute() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
+        client.withdraw(4000.0).unwrap();
+
+        // Execute
+        client.raise_withdrawal_dispute(4000.0).unwrap();
+        let (available, held, total) = (client.available, client.held, client.total);
+        client.resolve_dispute(4000.0).unwrap();
+
+        // Assert
+        assert_eq!((available, held, total), (6000.0, 4000.0, 10000.0));
+        assert_eq!(client.available, 10000.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 10000.0);
+    }
+
+    // Crediting on chargeback, a released withdrawal dispute leaves the withdrawal in place and a chargeback of one
+    // credits the amount back and locks the account.
+    #[test]
+    fn test_withdrawal_dispute_credit_on_chargeback() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10000.0).unwrap();
+        client.withdraw(4000.0).unwrap();
+        client.withdraw(1000.0).unwrap();
+
+        // Execute
+        client.raise_withdrawal_dispute(4000.0).unwrap();
+        client.release_withdrawal_dispute(4000.0).unwrap();
+        let (available, held, total) = (client.available, client.held, client.total);
+        client.raise_withdrawal_dispute(1000.0).unwrap();
+        client.chargeback_withdrawal(1000.0).unwrap();
+
+        // Assert
+        assert_eq!((available, held, total), (5000.0, 0.0, 5000.0));
+        assert_eq!(client.available, 6000.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 6000.0);
+        assert!(client.locked);
+        assert_eq!(client.chargeback_count, 1);
+    }
+
+    // An account whose available funds were below zero stays flagged after they recover.
+    #[test]
+    fn test_was_negative() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(10.0).unwrap();
+        let flagged_before = client.was_negative;
+
+        // Execute
+        client.deposit(-15.0).unwrap();
+        let flagged_while_negative = client.was_negative;
+        client.deposit(20.0).unwrap();
+
+        // Assert
+        assert!(!flagged_before);
+        assert!(flagged_while_negative);
+        assert_eq!(client.available, 15.0);
+        assert!(client.was_negative);
+    }
+
+    // A client whose total doesn't match its available and held funds is reported, one off by rounding is not.
+    #[test]
+    fn test_check_invariants() {
+        // Prepare
+        let mut client = Client::new(7);
+        client.deposit(0.1).unwrap();
+        client.deposit(0.2).unwrap();
+        client.raise_dispute(0.1).unwrap();
+        let mut broken_client = client.clone();
+        broken_client.held += 1.0;
+
+        // Execute
+        let result = client.check_invariants(DEFAULT_DECIMALS);
+        let broken_result = broken_client.check_invariants(DEFAULT_DECIMALS);
+
+        // Assert
+        assert!(result.is_ok());
+        let violation = broken_result.unwrap_err();
+        assert_eq!(violation.client_id, 7);
+        assert_eq!(violation.total, client.total);
+        assert_eq!(violation.held, client.held + 1.0);
+    }
+
+    // Amounts differing only by floating point rounding compare equal, closer amounts are told apart with more decimals.
+    #[test]
+    fn test_eq_ignores_rounding() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(0.1).unwrap();
+        client.deposit(0.2).unwrap();
+        let mut expected_client = Client::new(1);
+        expected_client.deposit(0.3).unwrap();
+        let mut close_client = expected_client.clone();
+        close_client.deposit(0.00002).unwrap();
+
+        // Assert
+        assert_eq!(client, expected_client);
+        assert_eq!(client, close_client);
+        assert_ne!(client.snapshot(8), close_client.snapshot(8));
+        expected_client.deposit(0.001).unwrap();
+        assert_ne!(client, expected_client);
+    }
+
+    // A client survives a round trip through JSON and bincode, including balances in other currencies.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        // Prepare
+        let mut client = Client::new(4);
+        client.deposit(10.0).unwrap();
+        client.locked = true;
+        client.chargeback_count = 1;
+        client
+            .currencies
+            .push(CurrencyBalance::new("EUR".parse().unwrap()));
+
+        // Execute
+        let json = serde_json::to_string(&client).unwrap();
+        let from_json: Client = serde_json::from_str(&json).unwrap();
+        let from_bincode: Client =
+            bincode::deserialize(&bincode::serialize(&client).unwrap()).unwrap();
+
+        // Assert
+        for decoded in [from_json, from_bincode] {
+            assert_eq!(
+                decoded.snapshot(DEFAULT_DECIMALS),
+                client.snapshot(DEFAULT_DECIMALS)
+            );
+            assert!(decoded.locked);
+            assert_eq!(decoded.chargeback_count, 1);
+            assert_eq!(decoded.currencies, client.currencies);
+        }
+    }
+
+    // A client is displayed as its row of the client table.
+    #[test]
+    fn test_display() {
+        // Prepare
+        let mut client = Client::new(1);
+        client.deposit(1.5).unwrap();
+        let mut row = Vec::new();
+        crate::output::write_table_row(&mut row, &client, 4, false).unwrap();
+
+        // Execute
+        let displayed = client.to_string();
+
+        // Assert
+        assert_eq!(
+            displayed,
+            "     1 |        1.5 |          0 |        1.5 |  false"
+        );
+        assert_eq!(format!("{}\n", displayed), String::from_utf8(row).unwrap());
+    }
 }
@@ -0,0 +1,406 @@
+//! This module contains a Config struct used to tune the behaviour of the transaction processor.
+
+use super::{
+    client::{Client, ClientId},
+    currency::Currency,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Format of the input files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Comma separated values with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+/// Compression of the input files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Uncompressed input.
+    None,
+    /// Gzip compressed input.
+    Gzip,
+    /// Zstandard compressed input.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Format in which client data is shown.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A human readable table written to the output stream.
+    #[default]
+    Table,
+    /// Rows of the `clients` table in the SQLite database at the given path.
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+    /// A Parquet file at the given path. Parquet is written to a file rather than the output stream because the
+    /// writer needs to seek.
+    #[cfg(feature = "parquet")]
+    Parquet(PathBuf),
+}
+
+/// Way amounts are written in the client table. Exports and other machine readable output always use the plain form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NumberLocale {
+    /// As few digits as needed and a decimal point, e.g. `1234567.89`.
+    #[default]
+    Plain,
+    /// Thousands separated by commas and all the decimal places after a point, e.g. `1,234,567.8900`.
+    En,
+    /// Thousands separated by points and all the decimal places after a comma, e.g. `1.234.567,8900`.
+    De,
+    /// Thousands separated by spaces and all the decimal places after a comma, e.g. `1 234 567,8900`.
+    Fr,
+}
+
+impl NumberLocale {
+    /// Returns the separator of groups of thousands, `None` if digits are not grouped.
+    pub fn group_separator(self) -> Option<char> {
+        match self {
+            NumberLocale::Plain => None,
+            NumberLocale::En => Some(','),
+            NumberLocale::De => Some('.'),
+            NumberLocale::Fr => Some(' '),
+        }
+    }
+
+    /// Returns the separator of the decimal places.
+    pub fn decimal_separator(self) -> char {
+        match self {
+            NumberLocale::Plain | NumberLocale::En => '.',
+            NumberLocale::De | NumberLocale::Fr => ',',
+        }
+    }
+}
+
+/// A column of the client table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// Client ID.
+    Client,
+    /// Available funds.
+    Available,
+    /// Held funds.
+    Held,
+    /// Total funds.
+    Total,
+    /// Whether the account is locked.
+    Locked,
+    /// Number of disputes raised against the client.
+    Disputes,
+    /// Number of chargebacks of the client.
+    Chargebacks,
+    /// Whether the available funds were ever negative.
+    WasNegative,
+}
+
+impl Column {
+    /// The columns of the client table unless others are configured.
+    pub const DEFAULT: [Column; 5] = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+    ];
+
+    /// The columns added by [extended output](Config::extended_output).
+    pub const COUNTERS: [Column; 3] = [Column::Disputes, Column::Chargebacks, Column::WasNegative];
+
+    /// Returns the name of the column in the header, e.g. `available`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Disputes => "disputes",
+            Column::Chargebacks => "chargebacks",
+            Column::WasNegative => "was_negative",
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = anyhow::Error;
+
+    /// Parses the name of a column as shown in the header.
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        [Column::DEFAULT.as_slice(), Column::COUNTERS.as_slice()]
+            .concat()
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown column {}.", name))
+    }
+}
+
+/// Fee charged on a withdrawal on top of the withdrawn amount.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeePolicy {
+    /// The same fee for every withdrawal.
+    Flat(f32),
+    /// A percentage of the withdrawn amount, e.g. 1.5 for 1.5%.
+    Percent(f32),
+}
+
+/// How disputes of withdrawals and transfers are handled. The funds already left the client, so an accepted dispute
+/// holds the amount on top of the available funds and the policy decides which outcome credits it to the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WithdrawalDisputes {
+    /// Disputes of withdrawals and transfers are rejected, only deposits can be disputed.
+    #[default]
+    Rejected,
+    /// A resolve credits the held amount to the available funds and a chargeback removes it again.
+    CreditOnResolve,
+    /// A resolve removes the held amount again and a chargeback credits it to the available funds, like a bank
+    /// reversing a withdrawal the client didn't make.
+    CreditOnChargeback,
+}
+
+/// Limit on the number of withdrawals of a client, to stop runaway automation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VelocityRule {
+    /// Maximum number of withdrawals of a client. Further withdrawals are rejected.
+    pub max_withdrawals: u32,
+    /// Number of transactions handled by the processor after which the withdrawals are counted from zero again. Rows
+    /// which can't be parsed are not among them. The withdrawals are counted over the whole run when not set.
+    pub window_rows: Option<u64>,
+}
+
+impl FeePolicy {
+    /// Returns the fee charged on a withdrawal of `amount`.
+    pub fn fee(&self, amount: f32) -> f32 {
+        match self {
+            FeePolicy::Flat(fee) => *fee,
+            FeePolicy::Percent(rate) => amount * rate / 100.0,
+        }
+    }
+}
+
+/// Client IDs allowed to transact. Single IDs are kept in a set, so a long list of registered accounts is looked up
+/// in constant time, and only the wider ranges are scanned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllowedClients {
+    ids: HashSet<ClientId>,
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl AllowedClients {
+    /// Returns whether a client is among the allowed ones.
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.ids.contains(&client_id) || self.ranges.iter().any(|ids| ids.contains(&client_id))
+    }
+}
+
+impl FromIterator<RangeInclusive<ClientId>> for AllowedClients {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<ClientId>>>(iter: I) -> Self {
+        let mut allowed = AllowedClients::default();
+        for ids in iter {
+            if ids.start() == ids.end() {
+                allowed.ids.insert(*ids.start());
+            } else if !ids.is_empty() {
+                allowed.ranges.push(ids);
+            }
+        }
+        allowed
+    }
+}
+
+/// Number of decimal places amounts are rounded to unless configured otherwise.
+pub const DEFAULT_DECIMALS: u32 = 4;
+
+/// Options controlling how transactions are processed and reported.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Report only clients whose account ended the run locked.
+    pub only_locked: bool,
+    /// Add the dispute and chargeback counts of each client and whether its funds were ever negative to the output.
+    pub extended_output: bool,
+    /// Columns of the client table in the order they are shown. The [counters](Column::COUNTERS) which aren't among
+    /// them follow with extended output.
+    pub columns: Vec<Column>,
+    /// Show held funds in the client table as a negative amount and the available funds including them.
+    pub signed_held: bool,
+    /// Format of the input files.
+    pub input_format: InputFormat,
+    /// Format in which client data is shown.
+    pub output_format: OutputFormat,
+    /// Compression of the input files. Detected from each file when not set.
+    pub compression: Option<Compression>,
+    /// Field delimiter of CSV input.
+    pub delimiter: u8,
+    /// Lines of CSV input starting with this byte are comments and skipped. No lines are comments when not set.
+    pub comment: Option<u8>,
+    /// Number of decimal places amounts are rounded to, both when read and when shown.
+    pub decimals: u32,
+    /// Way amounts are written in the client table.
+    pub locale: NumberLocale,
+    /// Maximum amount a client may have held in dispute. Disputes which would exceed it are rejected.
+    pub max_held_per_client: Option<f32>,
+    /// Currency of transactions without one, e.g. all the rows of a file without a `currency` column.
+    pub base_currency: Currency,
+    /// Limit on the number of withdrawals of a client. Withdrawals are not limited when not set.
+    pub velocity_limit: Option<VelocityRule>,
+    /// Deposits and withdrawals of at least this amount are flagged. Nothing is flagged when not set.
+    pub flag_threshold: Option<f32>,
+    /// Amount available funds of a client may go below zero through withdrawals and transfers.
+    pub overdraft_limit: f32,
+    /// Overdraft limits of single clients, used instead of [overdraft_limit](Config::overdraft_limit).
+    pub client_overdraft_limits: HashMap<ClientId, f32>,
+    /// How often a followed file is checked for new data.
+    pub poll_interval: Duration,
+    /// How often the client table is shown while following a file. Not shown until the end when not set.
+    pub snapshot_interval: Option<Duration>,
+    /// Stop processing the input files at the first record which isn't a valid transaction instead of skipping it.
+    pub fail_fast: bool,
+    /// Answer malformed lines sent over TCP with an error instead of only logging them.
+    pub strict: bool,
+    /// Client IDs reserved for system accounts. Transactions of these clients are skipped.
+    pub reserved_client_ids: Vec<RangeInclusive<ClientId>>,
+    /// Client IDs allowed to transact, for a fixed set of registered accounts. Transactions of other clients are
+    /// skipped instead of creating the client. All clients are allowed when not set.
+    pub allowed_client_ids: Option<AllowedClients>,
+    /// Window of transaction IDs to process. Other transactions are skipped, except disputes, resolves, chargebacks
+    /// and reversals, which carry the ID of the transaction they refer to.
+    pub tx_window: Option<RangeInclusive<u32>>,
+    /// Earliest timestamp of the rows to process. Rows with an earlier timestamp are dropped before they reach the
+    /// processor, rows without a timestamp are always processed.
+    pub since: Option<DateTime<Utc>>,
+    /// Timestamp from which on rows are dropped before they reach the processor. Rows without a timestamp are always
+    /// processed.
+    pub until: Option<DateTime<Utc>>,
+    /// Create unknown clients with zero balance when they withdraw.
+    pub create_on_withdrawal: bool,
+    /// Apply `unlock` transactions instead of rejecting them.
+    pub allow_unlock: bool,
+    /// How disputes of withdrawals and transfers are handled. Only deposits can be disputed by default.
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// Allow a transaction to be disputed again after its dispute was resolved.
+    pub redispute_resolved: bool,
+    /// Apply `admin_credit` and `admin_debit` transactions instead of rejecting them.
+    pub allow_admin_ops: bool,
+    /// Skip transactions whose ID was applied before in the run, so an input fed twice is applied once.
+    pub skip_replays: bool,
+    /// Fee deducted from the client on every withdrawal. Withdrawals are free when not set.
+    pub fee_policy: Option<FeePolicy>,
+    /// Process the transactions of every input in timestamp order instead of file order. The whole input is kept in
+    /// memory to sort it.
+    pub order_by_timestamp: bool,
+    /// Maximum number of rows read from all the input files together. Reading stops once it is reached.
+    pub max_rows: Option<usize>,
+    /// Path of the write-ahead log every handled transaction is appended to.
+    pub wal_path: Option<PathBuf>,
+    /// Number of records after which the write-ahead log is synced to disk.
+    pub wal_sync_every: usize,
+    /// Path of the checkpoint written while processing and read by [Gravenche::resume](crate::Gravenche::resume).
+    pub checkpoint_path: Option<PathBuf>,
+    /// Number of applied transactions after which a checkpoint is written. No checkpoints are written when not set.
+    pub checkpoint_every: Option<u64>,
+    /// Read input files through a memory map instead of a buffered reader.
+    #[cfg(feature = "mmap")]
+    pub mmap: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            only_locked: false,
+            extended_output: false,
+            columns: Column::DEFAULT.to_vec(),
+            signed_held: false,
+            input_format: InputFormat::default(),
+            output_format: OutputFormat::default(),
+            compression: None,
+            delimiter: b',',
+            comment: None,
+            decimals: DEFAULT_DECIMALS,
+            locale: NumberLocale::Plain,
+            max_held_per_client: None,
+            base_currency: Currency::default(),
+            velocity_limit: None,
+            flag_threshold: None,
+            overdraft_limit: 0.0,
+            client_overdraft_limits: HashMap::new(),
+            poll_interval: Duration::from_millis(500),
+            snapshot_interval: None,
+            fail_fast: false,
+            strict: false,
+            reserved_client_ids: Vec::new(),
+            allowed_client_ids: None,
+            tx_window: None,
+            since: None,
+            until: None,
+            create_on_withdrawal: false,
+            allow_unlock: false,
+            withdrawal_disputes: WithdrawalDisputes::Rejected,
+            redispute_resolved: false,
+            allow_admin_ops: false,
+            skip_replays: false,
+            fee_policy: None,
+            order_by_timestamp: false,
+            max_rows: None,
+            checkpoint_path: None,
+            checkpoint_every: None,
+            #[cfg(feature = "mmap")]
+            mmap: false,
+            wal_path: None,
+            wal_sync_every: 100,
+        }
+    }
+}
+
+impl Config {
+    /// Returns whether a client is shown, i.e. all clients are shown or it is locked and only locked ones are.
+    pub fn shows(&self, client: &Client) -> bool {
+        !self.only_locked || client.locked
+    }
+
+    /// Returns the overdraft limit of a client.
+    pub fn overdraft_limit_for(&self, client_id: ClientId) -> f32 {
+        self.client_overdraft_limits
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.overdraft_limit)
+    }
+
+    /// Returns whether a client may transact, i.e. all clients are allowed or it is among the allowed ones.
+    pub fn is_allowed_client(&self, client_id: ClientId) -> bool {
+        self.allowed_client_ids
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(client_id))
+    }
+
+    /// Returns the columns of the client table: the configured ones followed, with extended output, by the counters
+    /// not among them.
+    pub fn table_columns(&self) -> Vec<Column> {
+        let mut columns = self.columns.clone();
+        if self.extended_output {
+            let counters = Column::COUNTERS
+                .into_iter()
+                .filter(|counter| !self.columns.contains(counter));
+            columns.extend(counters);
+        }
+        columns
+    }
+
+    /// Returns if a row with `timestamp` is outside the window set by [since](Config::since) and
+    /// [until](Config::until). Rows without a timestamp never are.
+    pub fn outside_time_window(&self, timestamp: Option<DateTime<Utc>>) -> bool {
+        timestamp.is_some_and(|timestamp| {
+            self.since.is_some_and(|since| timestamp < since)
+                || self.until.is_some_and(|until| timestamp >= until)
+        })
+    }
+}
@@ -0,0 +1,69 @@
+//! This module contains the Currency type used to keep balances of different currencies apart.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// An ISO 4217 currency code such as `USD`. Stored inline, so currencies are copied without allocating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// US dollar, the default base currency.
+    pub const USD: Currency = Currency(*b"USD");
+
+    /// Returns the code, e.g. `USD`.
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are accepted when parsing.
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::USD
+    }
+}
+
+impl FromStr for Currency {
+    type Err = anyhow::Error;
+
+    /// Parses a code of three ASCII letters in any case, e.g. `eur`.
+    fn from_str(code: &str) -> anyhow::Result<Self> {
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|byte| byte.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => anyhow::bail!("Invalid currency {}.", code),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Codes are uppercased and anything but three letters is refused.
+    #[test]
+    fn test_parse_currency() {
+        // Execute
+        let eur: Currency = "eur".parse().unwrap();
+
+        // Assert
+        assert_eq!(eur.as_str(), "EUR");
+        assert_eq!(Currency::default(), Currency::USD);
+        for invalid in ["", "EU", "EURO", "E1R", "€UR"] {
+            assert!(invalid.parse::<Currency>().is_err(), "{}", invalid);
+        }
+    }
+}
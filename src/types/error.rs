@@ -0,0 +1,119 @@
+//! This module contains the GravencheError enum returned by the methods of [Gravenche](crate::Gravenche).
+
+#[cfg(feature = "runtime")]
+use super::other::Command;
+use std::{fmt, io};
+#[cfg(feature = "runtime")]
+use tokio::sync::{mpsc::error::SendError, oneshot::error::RecvError};
+#[cfg(feature = "runtime")]
+use tokio::task::JoinError;
+
+/// An error returned by the methods of [Gravenche](crate::Gravenche).
+#[derive(Debug)]
+pub enum GravencheError {
+    /// Reading the input or writing the output failed, e.g. an input file doesn't exist.
+    Io(io::Error),
+    /// The input is not valid CSV, e.g. a record isn't valid UTF-8. Records that are valid CSV but not valid
+    /// transactions are skipped instead, unless processing stops at them with [BadRow](GravencheError::BadRow).
+    Parse(String),
+    /// A record is not a valid transaction and processing was configured to stop at the first one. `line` is the line
+    /// of the record in its input file, starting at 1.
+    BadRow { line: u64, reason: String },
+    /// The configuration doesn't allow the operation, e.g. following the standard input.
+    Config(String),
+    /// The transaction processor stopped unexpectedly.
+    Processing(String),
+}
+
+impl fmt::Display for GravencheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Parse(message) => write!(f, "Invalid input: {}", message),
+            Self::BadRow { line, reason } => write!(f, "Invalid row at line {}: {}", line, reason),
+            Self::Config(message) => write!(f, "Invalid configuration: {}", message),
+            Self::Processing(message) => write!(f, "Processing failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GravencheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GravencheError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<csv::Error> for GravencheError {
+    fn from(error: csv::Error) -> Self {
+        let message = error.to_string();
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => Self::Io(error),
+            _ => Self::Parse(message),
+        }
+    }
+}
+
+/// Classifies the errors of the internals, which use anyhow. The context of the error is kept in the message.
+impl From<anyhow::Error> for GravencheError {
+    fn from(error: anyhow::Error) -> Self {
+        let message = format!("{:#}", error);
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return Self::Io(io::Error::new(error.kind(), message));
+        }
+        match error.downcast::<csv::Error>() {
+            Ok(error) => match error.into_kind() {
+                csv::ErrorKind::Io(error) => Self::Io(io::Error::new(error.kind(), message)),
+                _ => Self::Parse(message),
+            },
+            Err(error) => match error.downcast::<Self>() {
+                Ok(error) => error,
+                Err(_) => Self::Processing(message),
+            },
+        }
+    }
+}
+
+/// A snapshot ending early is invalid input rather than an I/O failure.
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for GravencheError {
+    fn from(error: bincode::Error) -> Self {
+        match *error {
+            bincode::ErrorKind::Io(error) if error.kind() != io::ErrorKind::UnexpectedEof => {
+                Self::Io(error)
+            }
+            error => Self::Parse(error.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl From<SendError<Command>> for GravencheError {
+    fn from(_: SendError<Command>) -> Self {
+        Self::Processing(String::from("The transaction processor is not running"))
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl From<RecvError> for GravencheError {
+    fn from(_: RecvError) -> Self {
+        Self::Processing(String::from(
+            "The transaction processor stopped without replying",
+        ))
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl From<JoinError> for GravencheError {
+    fn from(error: JoinError) -> Self {
+        Self::Processing(error.to_string())
+    }
+}
@@ -1,8 +1,176 @@
+#[cfg(feature = "runtime")]
 use super::transaction::Transaction;
+use super::{
+    client::{round_amount, Client, ClientId},
+    transaction::TransactionType,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::path::PathBuf;
+#[cfg(feature = "runtime")]
+use tokio::sync::oneshot;
 
 /// This represents a command sent to a transaction processor task. The transaction processor decides what to do based on these commands.
+#[cfg(feature = "runtime")]
 #[derive(Debug)]
 pub enum Command {
     Transaction(Transaction),
+    /// Requests a copy of all the clients reflecting the transactions sent before it.
+    Snapshot(oneshot::Sender<Vec<Client>>),
+    /// Requests a copy of the client with the ID, if it exists, reflecting the transactions sent before it.
+    Client(ClientId, oneshot::Sender<Option<Client>>),
+    /// Requests a copy of the processed transaction with the ID, if any, reflecting the transactions sent before it.
+    Lookup(u32, oneshot::Sender<Option<Transaction>>),
+    /// Requests an acknowledgement once all the transactions sent before it are applied.
+    Flush(oneshot::Sender<()>),
     Exit,
 }
+
+/// An event describing a transaction applied to a client and the resulting balances. Amounts are strings so JSON
+/// consumers don't round them.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ClientEvent {
+    /// Type of the applied transaction, e.g. `deposit`.
+    pub event: &'static str,
+    /// Client ID.
+    pub client: ClientId,
+    /// Transaction ID.
+    pub tx: u32,
+    /// Amount of the transaction, or the disputed amount for disputes, resolves and chargebacks.
+    pub amount: String,
+    /// Available funds after the transaction.
+    pub available: String,
+    /// Held funds after the transaction.
+    pub held: String,
+    /// Total funds after the transaction.
+    pub total: String,
+    /// Whether the account is locked after the transaction.
+    pub locked: bool,
+}
+
+impl ClientEvent {
+    pub fn new(_type: &TransactionType, tx: u32, amount: f32, client: &Client) -> Self {
+        ClientEvent {
+            event: _type.as_str(),
+            client: client.id,
+            tx,
+            amount: format!("{:?}", amount),
+            available: format!("{:?}", client.available),
+            held: format!("{:?}", client.held),
+            total: format!("{:?}", client.total),
+            locked: client.locked,
+        }
+    }
+}
+
+/// The clients shown by [show_output](crate::Gravenche::show_output) and totals over all the clients, gathered once so
+/// every output format shows the same data. The totals also cover clients which are not shown, e.g. the unlocked ones
+/// with [only_locked](crate::GravencheBuilder::only_locked). Amounts are in the base currency. Reports are equal when
+/// their clients have equal [snapshots](Client::snapshot) and their totals are equal.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Report {
+    /// Clients to show, ordered by client ID.
+    pub clients: Vec<Client>,
+    /// Number of all the clients, shown or not.
+    pub all_clients: usize,
+    /// Number of all the clients whose account is locked.
+    pub locked_clients: usize,
+    /// Available funds of all the clients.
+    pub available: f32,
+    /// Held funds of all the clients.
+    pub held: f32,
+    /// Total funds of all the clients.
+    pub total: f32,
+    /// Number of disputes raised against all the clients.
+    pub disputes: u32,
+    /// Number of chargebacks of all the clients.
+    pub chargebacks: u32,
+    /// Number of decimal places of the amounts.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub decimals: u32,
+}
+
+impl PartialEq for Report {
+    fn eq(&self, other: &Self) -> bool {
+        let snapshots = |report: &Report| {
+            report
+                .clients
+                .iter()
+                .map(|client| client.snapshot(report.decimals))
+                .collect::<Vec<_>>()
+        };
+        snapshots(self) == snapshots(other)
+            && self.all_clients == other.all_clients
+            && self.locked_clients == other.locked_clients
+            && self.available == other.available
+            && self.held == other.held
+            && self.total == other.total
+            && self.disputes == other.disputes
+            && self.chargebacks == other.chargebacks
+    }
+}
+
+impl Report {
+    /// Builds the report of `clients`, which are ordered by client ID, with the sums rounded to `decimals` decimal
+    /// places. All of them are shown until some are removed from [clients](Report::clients), which leaves the totals
+    /// as they are.
+    pub fn new(clients: Vec<Client>, decimals: u32) -> Self {
+        let sum =
+            |amount: fn(&Client) -> f32| round_amount(clients.iter().map(amount).sum(), decimals);
+        Report {
+            all_clients: clients.len(),
+            locked_clients: clients.iter().filter(|client| client.locked).count(),
+            available: sum(|client| client.available),
+            held: sum(|client| client.held),
+            total: sum(|client| client.total),
+            disputes: clients.iter().map(|client| client.dispute_count).sum(),
+            chargebacks: clients.iter().map(|client| client.chargeback_count).sum(),
+            clients,
+            decimals,
+        }
+    }
+}
+
+/// A summary of the work done by a single run of the transaction processor.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessingSummary {
+    /// Number of records read from the CSV.
+    pub rows_read: usize,
+    /// Number of transactions that modified client data.
+    pub transactions_applied: usize,
+    /// Number of records skipped because they could not be parsed.
+    pub rows_skipped: usize,
+    /// Number of records dropped because their timestamp is outside the configured time window.
+    pub rows_filtered: usize,
+    /// Number of distinct clients modified by applied transactions.
+    pub clients_touched: usize,
+    /// Total of the withdrawal fees charged by the [fee policy](crate::types::config::FeePolicy).
+    pub fees_collected: f32,
+    /// Number of deposits and withdrawals flagged for reaching the flag threshold.
+    pub transactions_flagged: usize,
+    /// Per file statistics in the order files were processed.
+    pub files: Vec<FileSummary>,
+    /// Set when reading stopped at the row limit before the end of the input.
+    pub truncated: bool,
+    /// Set when reading stopped because the run was interrupted before the end of the input.
+    pub interrupted: bool,
+}
+
+/// Statistics of reading a single CSV file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileSummary {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Number of records read from the file.
+    pub rows_read: usize,
+    /// Number of records skipped because they could not be parsed.
+    pub rows_skipped: usize,
+    /// Number of records dropped because their timestamp is outside the configured time window.
+    pub rows_filtered: usize,
+    /// Set when reading stopped at the row limit before the end of the file.
+    pub truncated: bool,
+    /// Set when reading stopped because the run was interrupted before the end of the file.
+    pub interrupted: bool,
+}
@@ -1,63 +1,468 @@
 //! This module contains a Client struct used to store client data.
 
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use super::{client::ClientId, currency::Currency};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+#[cfg(feature = "runtime")]
+use std::{collections::HashMap, sync::Arc};
+#[cfg(feature = "runtime")]
 use tokio::sync::Mutex;
 
 pub const TRANSACTION_TYPE_INDEX: usize = 0;
 pub const CLIENT_ID_INDEX: usize = 1;
 pub const TRANSACTION_ID_INDEX: usize = 2;
 pub const AMOUNT_INDEX: usize = 3;
+pub const TIMESTAMP_INDEX: usize = 4;
+/// Destination client of a transfer. The timestamp of a transfer follows it.
+pub const COUNTERPARTY_INDEX: usize = 4;
+/// Maximum number of characters of a memo kept with a transaction. Longer memos are cut.
+pub const MAX_MEMO_CHARS: usize = 256;
 
 /// A struct to represent a single transaction.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Transaction {
     /// Transaction ID.
     pub id: u32,
     /// Client ID.
-    pub client_id: u16,
-    /// Type of transaction.
+    pub client_id: ClientId,
+    /// Type of transaction. Serialized as `type` like the CSV column.
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     pub _type: TransactionType,
     /// Amount associated with transaction.
     pub amount: f32,
-    /// Flag indicating if transaction is in dispute. This field is useful only when Transaction is stored.
-    pub is_disputed: bool,
+    /// Where the transaction is in the dispute process. This field is useful only when Transaction is stored.
+    pub dispute_state: DisputeState,
+    /// Part of the amount currently in dispute, 0 if the transaction is not in dispute. This field is useful only when
+    /// Transaction is stored.
+    pub disputed_amount: f32,
+    /// Disputes, resolves and chargebacks applied to the transaction, oldest first. Not allocated until the first one.
+    /// This field is useful only when Transaction is stored.
+    pub dispute_events: Option<Vec<DisputeEvent>>,
+    /// Flag indicating if a stored deposit was reversed. A reversed deposit can't be disputed or reversed again.
+    pub is_reversed: bool,
+    /// Time of the transaction, read from a column named `timestamp` or else from the optional fifth column, or the
+    /// sixth for transfers. Given as RFC 3339, e.g. `2024-03-01T12:00:00Z`, or as seconds since the Unix epoch.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Client receiving the amount of a transfer. Not set for other types.
+    pub counterparty: Option<ClientId>,
+    /// Currency of the amount, read from an optional `currency` column. The base currency when not set. Disputes,
+    /// resolves, chargebacks and reversals are applied in the currency of the transaction they refer to.
+    pub currency: Option<Currency>,
+    /// Line of the input file the transaction was read from, starting at 1. Not set for transactions which weren't
+    /// read from a CSV or NDJSON file.
+    pub source_line: Option<u64>,
+    /// Free text describing the transaction, read from a `memo` or `description` column and cut to
+    /// [MAX_MEMO_CHARS]. Only kept for review, it doesn't affect balances. Not allocated without a memo.
+    pub memo: Option<Box<str>>,
+}
+
+/// Trims whitespace (including tabs and non-breaking spaces) and stray byte order marks around a field.
+pub fn trim_field(field: &str) -> &str {
+    field.trim_matches(|c: char| c.is_whitespace() || c == '\u{feff}')
+}
+
+/// Parses a timestamp given as RFC 3339, e.g. `2024-03-01T12:00:00+01:00`, or as an integer number of seconds since
+/// the Unix epoch.
+pub fn parse_timestamp(timestamp: &str) -> anyhow::Result<DateTime<Utc>> {
+    let parsed = match timestamp.parse::<i64>() {
+        Ok(seconds) => DateTime::from_timestamp(seconds, 0),
+        Err(_) => DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc)),
+    };
+    match parsed {
+        Some(timestamp) => Ok(timestamp),
+        None => anyhow::bail!("Invalid timestamp {}.", timestamp),
+    }
+}
+
+/// Returns a trimmed field of a CSV record or an error if the record is too short to have it.
+fn field<'a>(record: &'a csv::StringRecord, index: usize, name: &str) -> anyhow::Result<&'a str> {
+    match record.get(index) {
+        Some(value) => Ok(trim_field(value)),
+        None => anyhow::bail!("Record has no {} field.", name),
+    }
 }
 
 impl Transaction {
-    pub fn new(id: u32, client_id: u16, _type: TransactionType, amount: f32) -> Self {
+    pub fn new(id: u32, client_id: ClientId, _type: TransactionType, amount: f32) -> Self {
         Transaction {
             id,
             client_id,
             _type,
             amount,
-            is_disputed: false,
+            dispute_state: DisputeState::Undisputed,
+            disputed_amount: 0.0,
+            dispute_events: None,
+            is_reversed: false,
+            timestamp: None,
+            counterparty: None,
+            currency: None,
+            source_line: None,
+            memo: None,
+        }
+    }
+
+    /// Sets the time of the transaction.
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the client receiving the amount of a transfer.
+    pub fn with_counterparty(mut self, counterparty: ClientId) -> Self {
+        self.counterparty = Some(counterparty);
+        self
+    }
+
+    /// Sets the currency of the amount.
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// Sets the memo of the transaction, cut to [MAX_MEMO_CHARS]. An empty memo is not kept.
+    pub fn with_memo(mut self, memo: &str) -> Self {
+        let memo = match memo.char_indices().nth(MAX_MEMO_CHARS) {
+            Some((end, _)) => &memo[..end],
+            None => memo,
+        };
+        self.memo = (!memo.is_empty()).then(|| memo.into());
+        self
+    }
+
+    /// Sets the line of the input file the transaction was read from.
+    pub fn with_source_line(mut self, source_line: u64) -> Self {
+        self.source_line = Some(source_line);
+        self
+    }
+
+    /// Converts a CSV record to a Transaction. Returns an error if the record is too short or a field can't be parsed.
+    /// A missing or empty amount, as on disputes, resolves and chargebacks, is treated as 0. An amount which is present
+    /// but can't be parsed is an error, and so is a missing one on types which [require
+    /// it](TransactionType::requires_amount). An explicit amount of 0 is kept, it makes a deposit or withdrawal a no-op.
+    pub fn from_record(record: &csv::StringRecord) -> anyhow::Result<Self> {
+        let id: u32 = match field(record, TRANSACTION_ID_INDEX, "transaction ID")?.parse() {
+            Ok(id) => id,
+            Err(_) => anyhow::bail!("Invalid transaction ID."),
+        };
+
+        // Types are usually lowercase already and matched without allocating a lowercase copy.
+        let _type = field(record, TRANSACTION_TYPE_INDEX, "type")?;
+        let _type = match TransactionType::from_str(_type)
+            .or_else(|_| TransactionType::from_str(&_type.to_lowercase()))
+        {
+            Ok(_type) => _type,
+            Err(_) => anyhow::bail!("Unknown transaction type {}.", _type.to_lowercase()),
+        };
+
+        let client_id: ClientId = match field(record, CLIENT_ID_INDEX, "client ID")?.parse() {
+            Ok(client_id) => client_id,
+            Err(_) => anyhow::bail!("Invalid client ID."),
+        };
+
+        let amount: f32 = match record.get(AMOUNT_INDEX).map(trim_field) {
+            Some(amount) if !amount.is_empty() => match amount.parse() {
+                Ok(amount) => amount,
+                Err(_) => anyhow::bail!("Invalid amount {}.", amount),
+            },
+            _ if _type.requires_amount() => anyhow::bail!("Missing amount."),
+            _ => 0.0,
+        };
+
+        let mut transaction = Transaction::new(id, client_id, _type, amount);
+        let mut timestamp_index = TIMESTAMP_INDEX;
+        if transaction._type == TransactionType::Transfer {
+            let counterparty =
+                match field(record, COUNTERPARTY_INDEX, "destination client ID")?.parse() {
+                    Ok(counterparty) => counterparty,
+                    Err(_) => anyhow::bail!("Invalid destination client ID."),
+                };
+            transaction = transaction.with_counterparty(counterparty);
+            timestamp_index += 1;
+        }
+        match record.get(timestamp_index).map(trim_field) {
+            Some(timestamp) if !timestamp.is_empty() => {
+                transaction = transaction.with_timestamp(parse_timestamp(timestamp)?)
+            }
+            _ => {}
         }
+        Ok(transaction)
     }
 
-    /// Flags transaction as disputed.
-    pub fn mark_disputed(&mut self) {
-        self.is_disputed = true;
+    /// Flags `amount` more of the transaction as disputed.
+    pub fn mark_disputed(&mut self, amount: f32) {
+        self.dispute_state = DisputeState::Disputed;
+        self.disputed_amount += amount;
     }
 
     /// Marks transaction dispute as resolved.
     pub fn mark_resolved(&mut self) {
-        self.is_disputed = false;
+        self.dispute_state = DisputeState::Resolved;
+        self.disputed_amount = 0.0;
+    }
+
+    /// Marks transaction dispute as settled by a chargeback.
+    pub fn mark_charged_back(&mut self) {
+        self.dispute_state = DisputeState::ChargedBack;
+        self.disputed_amount = 0.0;
+    }
+
+    /// Appends a dispute, resolve or chargeback applied as the transaction at position `sequence` of the processor to the
+    /// history.
+    pub fn record_dispute_event(
+        &mut self,
+        kind: TransactionType,
+        sequence: u64,
+        timestamp: Option<DateTime<Utc>>,
+    ) {
+        self.dispute_events
+            .get_or_insert_with(Vec::new)
+            .push(DisputeEvent {
+                kind,
+                sequence,
+                timestamp,
+            });
+    }
+
+    /// Returns the disputes, resolves and chargebacks applied to the transaction, oldest first.
+    pub fn dispute_events(&self) -> &[DisputeEvent] {
+        self.dispute_events.as_deref().unwrap_or_default()
+    }
+
+    /// Returns the part of the amount which is not in dispute and can still be disputed.
+    pub fn undisputed_amount(&self) -> f32 {
+        self.amount - self.disputed_amount
+    }
+
+    /// Marks a deposit as reversed.
+    pub fn mark_reversed(&mut self) {
+        self.is_reversed = true;
+    }
+
+    /// Returns if the transaction was reversed.
+    pub fn is_reversed(&self) -> bool {
+        self.is_reversed
+    }
+
+    /// Returns if the transaction is an admin adjustment, which can't be disputed.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self._type,
+            TransactionType::AdminCredit | TransactionType::AdminDebit
+        )
+    }
+
+    /// Returns if the transaction is a withdrawal or transfer, whose funds left the client.
+    pub fn is_withdrawal(&self) -> bool {
+        matches!(
+            self._type,
+            TransactionType::Withdrawal | TransactionType::Transfer
+        )
+    }
+
+    /// Returns if transaction is disputed, in full or in part.
+    pub fn is_disputed(&self) -> bool {
+        self.dispute_state == DisputeState::Disputed
     }
+}
+
+/// A dispute, resolve or chargeback in the history of a stored transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DisputeEvent {
+    /// Type of the applied transaction: a dispute, resolve or chargeback.
+    pub kind: TransactionType,
+    /// Position of the applied transaction among the transactions handled by the processor, starting at 1. This is not
+    /// the input row: skipped and filtered rows have no position, and the count runs on over several input files.
+    pub sequence: u64,
+    /// Time of the applied transaction, if its row had one.
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Display for DisputeEvent {
+    // Formats the event as e.g. `dispute@1041`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.kind, self.sequence)
+    }
+}
+
+/// Formats the history of a transaction as e.g. `dispute@1041;resolve@2310`. Empty if there are no events.
+pub fn format_dispute_events(events: &[DisputeEvent]) -> String {
+    events
+        .iter()
+        .map(DisputeEvent::to_string)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Stage of the dispute process of a stored transaction. A transaction starts undisputed, a dispute moves it to
+/// disputed and a resolve or chargeback settles it. A charged back transaction can't be disputed again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+/// A transaction as read from a line of NDJSON input. Fields are named after the CSV header.
+#[cfg(feature = "serde")]
+#[derive(Debug, Deserialize)]
+pub struct JsonTransaction {
+    /// Type of transaction.
+    #[serde(rename = "type")]
+    pub _type: String,
+    /// Client ID.
+    pub client: ClientId,
+    /// Transaction ID.
+    pub tx: u32,
+    /// Amount associated with transaction. Missing for disputes, resolves and chargebacks.
+    pub amount: Option<f32>,
+    /// Time of the transaction, as RFC 3339 or seconds since the Unix epoch.
+    pub timestamp: Option<JsonTimestamp>,
+    /// Destination client of a transfer.
+    pub to_client: Option<ClientId>,
+    /// Currency of the amount.
+    pub currency: Option<String>,
+    /// Free text describing the transaction.
+    #[serde(alias = "description")]
+    pub memo: Option<String>,
+}
+
+/// A timestamp of NDJSON input, which may be a number or a string.
+#[cfg(feature = "serde")]
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum JsonTimestamp {
+    /// Seconds since the Unix epoch.
+    Seconds(i64),
+    /// RFC 3339 or seconds since the Unix epoch.
+    Text(String),
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<JsonTransaction> for Transaction {
+    type Error = anyhow::Error;
 
-    /// Returns if transaction is disputed,
-    pub fn is_disputed(&mut self) -> bool {
-        self.is_disputed
+    fn try_from(record: JsonTransaction) -> anyhow::Result<Self> {
+        let _type = &trim_field(&record._type).to_lowercase();
+        let _type = match TransactionType::from_str(_type) {
+            Ok(_type) => _type,
+            Err(_) => anyhow::bail!("Unknown transaction type {}.", _type),
+        };
+
+        let amount = match record.amount {
+            Some(amount) => amount,
+            None if _type.requires_amount() => anyhow::bail!("Missing amount."),
+            None => 0.0,
+        };
+        let mut transaction = Transaction::new(record.tx, record.client, _type, amount);
+        transaction.timestamp = match record.timestamp {
+            Some(JsonTimestamp::Seconds(seconds)) => Some(parse_timestamp(&seconds.to_string())?),
+            Some(JsonTimestamp::Text(timestamp)) => Some(parse_timestamp(trim_field(&timestamp))?),
+            None => None,
+        };
+        if let Some(currency) = record.currency.as_deref().map(trim_field) {
+            if !currency.is_empty() {
+                transaction = transaction.with_currency(currency.parse()?);
+            }
+        }
+        if let Some(memo) = record.memo.as_deref() {
+            transaction = transaction.with_memo(trim_field(memo));
+        }
+        if transaction._type == TransactionType::Transfer {
+            match record.to_client {
+                Some(to_client) => transaction = transaction.with_counterparty(to_client),
+                None => anyhow::bail!("Transfer has no to_client field."),
+            }
+        }
+        Ok(transaction)
     }
 }
 
 /// Enum to represent transaction type.
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TransactionType {
     Deposit,
-    Withdrawl,
+    #[cfg_attr(feature = "serde", serde(alias = "Withdrawl"))]
+    Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Clears the lock of an account after a chargeback. Has no amount.
+    Unlock,
+    /// Moves an amount from the available funds of one client to another.
+    Transfer,
+    /// Undoes a deposit of the same client without a dispute. Refers to the deposit by its ID and has no amount.
+    Reversal,
+    /// Credits a client even if the account is locked. Can't be disputed.
+    AdminCredit,
+    /// Debits a client even if the account is locked. Can't be disputed.
+    AdminDebit,
+}
+
+impl TransactionType {
+    /// Former misspelled name of [TransactionType::Withdrawal], kept so downstream matches still compile.
+    #[deprecated(note = "use TransactionType::Withdrawal")]
+    #[allow(non_upper_case_globals)]
+    pub const Withdrawl: TransactionType = TransactionType::Withdrawal;
+
+    /// All the transaction types.
+    pub const ALL: [TransactionType; 10] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Unlock,
+        TransactionType::Transfer,
+        TransactionType::Reversal,
+        TransactionType::AdminCredit,
+        TransactionType::AdminDebit,
+    ];
+
+    /// Returns the name of the transaction type as used in CSV files.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Unlock => "unlock",
+            Self::Transfer => "transfer",
+            Self::Reversal => "reversal",
+            Self::AdminCredit => "admin_credit",
+            Self::AdminDebit => "admin_debit",
+        }
+    }
+
+    /// Returns if transactions of the type carry the ID of the transaction they refer to instead of their own.
+    pub fn refers_to_transaction(&self) -> bool {
+        matches!(
+            self,
+            Self::Dispute | Self::Resolve | Self::Chargeback | Self::Reversal
+        )
+    }
+
+    /// Returns if transactions of the type move an amount, which must be given even if it is zero.
+    pub fn requires_amount(&self) -> bool {
+        matches!(
+            self,
+            Self::Deposit
+                | Self::Withdrawal
+                | Self::Transfer
+                | Self::AdminCredit
+                | Self::AdminDebit
+        )
+    }
 }
 
 impl FromStr for TransactionType {
@@ -67,14 +472,321 @@ impl FromStr for TransactionType {
     fn from_str(input: &str) -> Result<TransactionType, Self::Err> {
         match input {
             "deposit" => Ok(Self::Deposit),
-            "withdrawal" => Ok(Self::Withdrawl),
+            "withdrawal" => Ok(Self::Withdrawal),
             "dispute" => Ok(Self::Dispute),
             "resolve" => Ok(Self::Resolve),
             "chargeback" => Ok(Self::Chargeback),
+            "unlock" => Ok(Self::Unlock),
+            "transfer" => Ok(Self::Transfer),
+            "reversal" => Ok(Self::Reversal),
+            "admin_credit" => Ok(Self::AdminCredit),
+            "admin_debit" => Ok(Self::AdminDebit),
             _ => Err(()),
         }
     }
 }
 
+impl std::fmt::Display for TransactionType {
+    // Formats the type as in CSV files, which parses back into the same type.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// A list of processed transactions.
+#[cfg(feature = "runtime")]
 pub type ProcessedTransactions = Arc<Mutex<HashMap<u32, Transaction>>>;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    // The README gives the memory needed to sort a file by timestamp from this size, so it has to follow changes.
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_transaction_size() {
+        assert_eq!(std::mem::size_of::<Transaction>(), 104);
+    }
+
+    // Whitespace and BOMs are trimmed from both ends of a field.
+    #[test]
+    fn test_trim_field() {
+        assert_eq!(trim_field("\u{feff}deposit"), "deposit");
+        assert_eq!(trim_field("\t1\u{a0}"), "1");
+        assert_eq!(trim_field(" \u{a0}\t2.5\t "), "2.5");
+    }
+
+    // from_record happy path.
+    #[test]
+    fn test_from_record() {
+        // Prepare
+        let record = csv::StringRecord::from(vec!["deposit", " 2", " 3", " 1.5"]);
+
+        // Execute
+        let transaction = Transaction::from_record(&record).unwrap();
+
+        // Assert
+        assert_eq!(transaction.id, 3);
+        assert_eq!(transaction.client_id, 2);
+        assert_eq!(transaction.amount, 1.5);
+    }
+
+    // The dispute status of two transactions can be read while both are borrowed.
+    #[test]
+    fn test_is_disputed_shared_borrows() {
+        // Prepare
+        let mut transactions = HashMap::new();
+        let mut disputed = Transaction::new(1, 1, TransactionType::Deposit, 1.0);
+        disputed.mark_disputed(1.0);
+        transactions.insert(1, disputed);
+        transactions.insert(2, Transaction::new(2, 1, TransactionType::Deposit, 2.0));
+
+        // Execute
+        let first = &transactions[&1];
+        let second = &transactions[&2];
+
+        // Assert
+        assert!(first.is_disputed());
+        assert!(!second.is_disputed());
+    }
+
+    // from_record returns an error instead of panicking on a record without a transaction ID, and treats a missing
+    // amount like an empty one.
+    #[test]
+    fn test_from_record_truncated() {
+        // Prepare
+        let fields = ["dispute", "2", "7", "1.5"];
+        let records: Vec<csv::StringRecord> = (1..=fields.len())
+            .map(|len| csv::StringRecord::from(fields[..len].to_vec()))
+            .collect();
+
+        // Execute
+        let results: Vec<_> = records.iter().map(Transaction::from_record).collect();
+
+        // Assert
+        assert!(results[0].is_err());
+        assert!(results[1].is_err());
+        let without_amount = results[2].as_ref().unwrap();
+        assert_eq!((without_amount.client_id, without_amount.id), (2, 7));
+        assert_eq!(without_amount.amount, 0.0);
+        assert_eq!(results[3].as_ref().unwrap().amount, 1.5);
+    }
+
+    // An amount which can't be parsed is an error instead of a zero amount.
+    #[test]
+    fn test_from_record_invalid_amount() {
+        // Prepare
+        let record = csv::StringRecord::from(vec!["deposit", "1", "1", "abc"]);
+
+        // Execute
+        let result = Transaction::from_record(&record);
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    // A dispute is parsed with an empty or missing amount.
+    #[test]
+    fn test_from_record_missing_amount() {
+        // Prepare
+        let empty = csv::StringRecord::from(vec!["dispute", "1", "1", " "]);
+        let missing = csv::StringRecord::from(vec!["dispute", "1", "1"]);
+
+        // Execute
+        let empty = Transaction::from_record(&empty).unwrap();
+        let missing = Transaction::from_record(&missing).unwrap();
+
+        // Assert
+        assert_eq!(empty.amount, 0.0);
+        assert_eq!(missing.amount, 0.0);
+    }
+
+    // A deposit needs an amount, but an explicit zero is a valid one.
+    #[test]
+    fn test_from_record_zero_amount() {
+        // Prepare
+        let zero = csv::StringRecord::from(vec!["deposit", "1", "1", "0.0"]);
+        let empty = csv::StringRecord::from(vec!["deposit", "1", "1", ""]);
+
+        // Execute
+        let zero = Transaction::from_record(&zero);
+        let empty = Transaction::from_record(&empty);
+
+        // Assert
+        assert_eq!(zero.unwrap().amount, 0.0);
+        assert!(empty.is_err());
+    }
+
+    // An unlock is parsed without an amount.
+    #[test]
+    fn test_from_record_unlock() {
+        // Prepare
+        let record = csv::StringRecord::from(vec!["unlock", " 4", " 7", ""]);
+
+        // Execute
+        let transaction = Transaction::from_record(&record).unwrap();
+
+        // Assert
+        assert_eq!(transaction._type, TransactionType::Unlock);
+        assert_eq!(transaction.client_id, 4);
+        assert_eq!(transaction.amount, 0.0);
+    }
+
+    // The destination client of a transfer is read from the fifth column and the timestamp moves to the sixth.
+    #[test]
+    fn test_from_record_transfer() {
+        // Prepare
+        let record = csv::StringRecord::from(vec!["transfer", "1", "3", "2.5", " 2", "1700000000"]);
+        let missing_counterparty = csv::StringRecord::from(vec!["transfer", "1", "3", "2.5"]);
+
+        // Execute
+        let transaction = Transaction::from_record(&record).unwrap();
+        let missing_counterparty = Transaction::from_record(&missing_counterparty);
+
+        // Assert
+        assert_eq!(transaction._type, TransactionType::Transfer);
+        assert_eq!(transaction.client_id, 1);
+        assert_eq!(transaction.counterparty, Some(2));
+        assert_eq!(transaction.amount, 2.5);
+        assert_eq!(
+            transaction.timestamp,
+            DateTime::from_timestamp(1700000000, 0)
+        );
+        assert!(missing_counterparty.is_err());
+    }
+
+    // The deprecated misspelled name is the same value as the withdrawal, also as a pattern.
+    #[test]
+    #[allow(deprecated)]
+    fn test_withdrawl_alias() {
+        // Execute
+        let is_withdrawal = matches!(TransactionType::Withdrawal, TransactionType::Withdrawl);
+
+        // Assert
+        assert_eq!(TransactionType::Withdrawl, TransactionType::Withdrawal);
+        assert!(is_withdrawal);
+        assert_eq!(TransactionType::Withdrawl.as_str(), "withdrawal");
+    }
+
+    // Memos are cut to the maximum number of characters, not bytes, and empty memos are not kept.
+    #[test]
+    fn test_with_memo() {
+        // Prepare
+        let long_memo = "é".repeat(MAX_MEMO_CHARS + 1);
+
+        // Execute
+        let long = Transaction::new(1, 1, TransactionType::Deposit, 1.0).with_memo(&long_memo);
+        let empty = Transaction::new(2, 1, TransactionType::Deposit, 1.0).with_memo("");
+
+        // Assert
+        assert_eq!(long.memo.unwrap().chars().count(), MAX_MEMO_CHARS);
+        assert_eq!(empty.memo, None);
+    }
+
+    // The optional timestamp column is parsed when present, as seconds or RFC 3339.
+    #[test]
+    fn test_from_record_timestamp() {
+        // Prepare
+        let with_timestamp =
+            csv::StringRecord::from(vec!["deposit", "1", "1", "1.0", " 1700000000"]);
+        let rfc3339 = csv::StringRecord::from(vec![
+            "deposit",
+            "1",
+            "1",
+            "1.0",
+            "2023-11-14T23:13:20+01:00",
+        ]);
+        let empty_timestamp = csv::StringRecord::from(vec!["dispute", "1", "1", "", ""]);
+        let invalid_timestamp = csv::StringRecord::from(vec!["deposit", "1", "1", "1.0", "noon"]);
+
+        // Execute
+        let with_timestamp = Transaction::from_record(&with_timestamp);
+        let rfc3339 = Transaction::from_record(&rfc3339);
+        let empty_timestamp = Transaction::from_record(&empty_timestamp);
+        let invalid_timestamp = Transaction::from_record(&invalid_timestamp);
+
+        // Assert
+        assert_eq!(
+            with_timestamp.unwrap().timestamp,
+            DateTime::from_timestamp(1700000000, 0)
+        );
+        assert_eq!(
+            rfc3339.unwrap().timestamp,
+            DateTime::from_timestamp(1700000000, 0)
+        );
+        assert_eq!(empty_timestamp.unwrap().timestamp, None);
+        assert!(invalid_timestamp.is_err());
+    }
+
+    // A stored transaction survives a round trip through JSON and bincode, with its type named `type`.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        // Prepare
+        let mut transaction = Transaction::new(3, 1, TransactionType::Deposit, 2.5)
+            .with_counterparty(2)
+            .with_memo("refund");
+        transaction.dispute_state = DisputeState::Disputed;
+        transaction.disputed_amount = 1.0;
+
+        // Execute
+        let json = serde_json::to_string(&transaction).unwrap();
+        let from_json: Transaction = serde_json::from_str(&json).unwrap();
+        let from_bincode: Transaction =
+            bincode::deserialize(&bincode::serialize(&transaction).unwrap()).unwrap();
+
+        // Assert
+        assert!(json.contains("\"type\":\"Deposit\""));
+        assert!(!json.contains("_type"));
+        for decoded in [from_json, from_bincode] {
+            assert_eq!(decoded.id, 3);
+            assert_eq!(decoded.client_id, 1);
+            assert_eq!(decoded._type, TransactionType::Deposit);
+            assert_eq!(decoded.amount, 2.5);
+            assert_eq!(decoded.dispute_state, DisputeState::Disputed);
+            assert_eq!(decoded.disputed_amount, 1.0);
+            assert_eq!(decoded.counterparty, Some(2));
+            assert_eq!(decoded.memo.as_deref(), Some("refund"));
+        }
+    }
+
+    // Transaction types and dispute states survive a round trip through JSON and bincode.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip_enums() {
+        for _type in TransactionType::ALL {
+            let json = serde_json::to_string(&_type).unwrap();
+            assert_eq!(
+                serde_json::from_str::<TransactionType>(&json).unwrap(),
+                _type
+            );
+            let bytes = bincode::serialize(&_type).unwrap();
+            assert_eq!(
+                bincode::deserialize::<TransactionType>(&bytes).unwrap(),
+                _type
+            );
+        }
+        for state in [
+            DisputeState::Undisputed,
+            DisputeState::Disputed,
+            DisputeState::Resolved,
+            DisputeState::ChargedBack,
+        ] {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(serde_json::from_str::<DisputeState>(&json).unwrap(), state);
+            let bytes = bincode::serialize(&state).unwrap();
+            assert_eq!(bincode::deserialize::<DisputeState>(&bytes).unwrap(), state);
+        }
+    }
+
+    // Every transaction type is displayed as its CSV name, which parses back into the same type.
+    #[test]
+    fn test_display_round_trip() {
+        for _type in TransactionType::ALL {
+            assert_eq!(_type.to_string(), _type.as_str());
+            assert_eq!(TransactionType::from_str(&_type.to_string()), Ok(_type));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! This module contains the write-ahead log of the transaction processor. Every transaction the processor handles is
+//! appended to it together with whether it was applied, so the state of a run can be rebuilt with
+//! [Gravenche::replay_wal](crate::Gravenche::replay_wal) and a crash can be diagnosed.
+//!
+//! The log is a sequence of frames. Every frame is the length of a record as a little endian `u32` followed by the
+//! record encoded with bincode.
+
+use crate::source::TransactionSource;
+use crate::types::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// A transaction handled by the processor.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalRecord {
+    /// The transaction as it was received.
+    pub transaction: Transaction,
+    /// Whether the transaction modified client data.
+    pub applied: bool,
+}
+
+/// Number of records queued for the writing task of a [WalWriter] before appending waits for it.
+const WAL_QUEUE: usize = 1024;
+
+/// Appends records to a write-ahead log. The records are written and synced to disk by a blocking task of its own, so
+/// the processor doesn't wait for the disk.
+pub struct WalWriter {
+    /// Queue of the records to write. Dropped to let the task finish.
+    sender: Option<mpsc::Sender<WalRecord>>,
+    /// Writing task. Taken once it finished.
+    writer: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl WalWriter {
+    /// Opens the log at `path` for appending, creating it if needed, and starts the task writing it. The log is synced
+    /// to disk every `sync_every` records. Must be called within a Tokio runtime.
+    pub fn open(path: &Path, sync_every: usize) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, mut receiver) = mpsc::channel(WAL_QUEUE);
+        let writer = tokio::task::spawn_blocking(move || {
+            let mut writer = BufWriter::new(file);
+            let sync_every = sync_every.max(1);
+            let mut unsynced = 0;
+            while let Some(record) = receiver.blocking_recv() {
+                write_record(&mut writer, &record)?;
+                unsynced += 1;
+                if unsynced >= sync_every {
+                    sync(&mut writer)?;
+                    unsynced = 0;
+                }
+            }
+            sync(&mut writer)
+        });
+        Ok(WalWriter {
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Appends a transaction and whether it was applied. Returns the error which stopped the writing task, if any.
+    pub async fn append(&mut self, transaction: Transaction, applied: bool) -> anyhow::Result<()> {
+        let record = WalRecord {
+            transaction,
+            applied,
+        };
+        let sent = match &self.sender {
+            Some(sender) => sender.send(record).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            // The task only stops before it is finished on an error.
+            self.finish().await?;
+            anyhow::bail!("The write-ahead log is closed");
+        }
+        Ok(())
+    }
+
+    /// Writes the queued records, syncs the log to disk and waits for the writing task to finish. Appending fails
+    /// afterwards.
+    pub async fn finish(&mut self) -> anyhow::Result<()> {
+        self.sender = None;
+        match self.writer.take() {
+            Some(writer) => writer.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+// Writes a record as a frame.
+fn write_record(writer: &mut BufWriter<File>, record: &WalRecord) -> anyhow::Result<()> {
+    // The size is computed up front so the record is encoded straight into the writer instead of a new buffer.
+    let len = bincode::serialized_size(record)?;
+    writer.write_all(&(len as u32).to_le_bytes())?;
+    bincode::serialize_into(writer, record)?;
+    Ok(())
+}
+
+// Writes the buffered records and syncs the log to disk.
+fn sync(writer: &mut BufWriter<File>) -> anyhow::Result<()> {
+    writer.flush()?;
+    writer.get_ref().sync_data()?;
+    Ok(())
+}
+
+/// Reads the records of a write-ahead log in the order they were appended.
+pub struct WalReader<R: Read> {
+    /// The log.
+    reader: BufReader<R>,
+    /// Error which stopped reading.
+    error: Option<anyhow::Error>,
+}
+
+impl WalReader<File> {
+    /// Opens the log at `path`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Ok(WalReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> WalReader<R> {
+    pub fn new(reader: R) -> Self {
+        WalReader {
+            reader: BufReader::new(reader),
+            error: None,
+        }
+    }
+
+    /// Returns the next record or `None` at the end of the log. A frame cut short by a crash ends the log.
+    pub fn next_record(&mut self) -> anyhow::Result<Option<WalRecord>> {
+        let mut length = [0; 4];
+        match self.reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+
+        let mut record = vec![0; u32::from_le_bytes(length) as usize];
+        match self.reader.read_exact(&mut record) {
+            Ok(()) => Ok(Some(bincode::deserialize(&record)?)),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                tracing::warn!("Ignoring a partially written record at the end of the log");
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Replays the transactions which were applied. Rejected ones left the clients alone, so the replay doesn't apply them
+/// again: the outcome of a transaction can depend on the configuration, and a replay under a different one would
+/// otherwise apply a transaction the logged run rejected.
+impl<R: Read + Send> TransactionSource for WalReader<R> {
+    async fn next(&mut self) -> Option<Transaction> {
+        loop {
+            match self.next_record() {
+                Ok(Some(record)) if !record.applied => continue,
+                Ok(record) => return record.map(|record| record.transaction),
+                Err(error) => {
+                    self.error = Some(error);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+}
@@ -1,9 +1,10 @@
-use std::process::Command;
-use std::str;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 
 #[test]
 fn test_execution() {
-    let output = Command::new("./target/release/gravenche")
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
         .args(["test_data.csv"])
         .output()
         .expect("Failed to execute Gravenche.");
@@ -14,3 +15,555 @@ fn test_execution() {
     assert_ne!(_stdout.len(), 0);
     assert_eq!(_stderr.len(), 0);
 }
+
+#[test]
+fn test_only_locked() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/locked_clients.csv", "--only-locked"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    // Header plus two locked clients.
+    assert_eq!(_stdout.lines().count(), 3);
+    assert!(_stdout.lines().skip(1).all(|line| line.ends_with("true")));
+}
+
+#[test]
+fn test_summary_top() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["summary", "--top", "2", "tests/data/top_clients.csv"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    let client_ids: Vec<&str> = _stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split('|').next().unwrap().trim())
+        .collect();
+    assert_eq!(client_ids, vec!["2", "4"]);
+}
+
+#[test]
+fn test_summary_only_locked() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args([
+            "summary",
+            "--top",
+            "1",
+            "--only-locked",
+            "tests/data/locked_clients.csv",
+        ])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    // The label with the totals of all the clients goes to stderr, stdout has only the table.
+    let _stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(_stderr.contains(
+        "Filtered to locked clients, 2 of 5 clients. Totals of all clients: available 105, held 0, total 105"
+    ));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(_stdout.lines().count(), 2);
+    assert!(_stdout
+        .lines()
+        .nth(1)
+        .unwrap()
+        .trim_start()
+        .starts_with("2 |"));
+}
+
+#[test]
+fn test_export_transactions() {
+    let export_path = std::env::temp_dir().join("gravenche_export_transactions.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/disputes.csv", "--export-transactions"])
+        .arg(&export_path)
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let exported = std::fs::read_to_string(&export_path).unwrap();
+    assert_eq!(exported.lines().count(), 6);
+    assert!(exported.contains("3,2,deposit,20,false"));
+}
+
+#[test]
+fn test_export_disputes() {
+    let export_path = std::env::temp_dir().join("gravenche_export_disputes.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/open_disputes.csv", "--export-disputes"])
+        .arg(&export_path)
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let exported = std::fs::read_to_string(&export_path).unwrap();
+    assert_eq!(exported, "tx,client,amount,held\n5,3,7,7\n");
+}
+
+#[test]
+fn test_small_buffer() {
+    // A file much larger than the queue forces the reader to wait for the processor.
+    let csv_path = std::env::temp_dir().join("gravenche_small_buffer.csv");
+    let mut csv = String::from("type, client, tx, amount\n");
+    for tx in 1..=5000 {
+        csv.push_str(&format!("deposit, {}, {}, 1.0\n", tx % 50, tx));
+    }
+    std::fs::write(&csv_path, csv).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .arg(&csv_path)
+        .args(["--buffer", "10"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    // Header plus fifty clients.
+    assert_eq!(_stdout.lines().count(), 51);
+    assert!(_stdout.lines().skip(1).all(|line| line.contains("100")));
+}
+
+#[test]
+fn test_stdin_input() {
+    let file_output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["test_data.csv"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    let csv = std::fs::read("test_data.csv").unwrap();
+    child.stdin.take().unwrap().write_all(&csv).unwrap();
+    let stdin_output = child.wait_with_output().unwrap();
+
+    assert_eq!(stdin_output.status.code(), Some(0));
+    // Clients are not reported in any particular order.
+    let mut file_lines: Vec<String> = String::from_utf8(file_output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut stdin_lines: Vec<String> = String::from_utf8(stdin_output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    file_lines.sort();
+    stdin_lines.sort();
+    assert_eq!(file_lines, stdin_lines);
+}
+
+#[test]
+fn test_stdin_between_files() {
+    // Transactions piped through "-" are applied after the file before it.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/disputes_1.csv", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    let csv = std::fs::read("tests/data/disputes_2.csv").unwrap();
+    child.stdin.take().unwrap().write_all(&csv).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    let _stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(_stdout.contains("     1 |         14 |          0 |         14 |  false"));
+    assert!(_stdout.contains("     2 |        2.5 |          0 |        2.5 |   true"));
+    assert!(_stderr.contains("-: 4 rows read, 0 rows skipped"));
+}
+
+#[test]
+fn test_multiple_files() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/disputes_1.csv", "tests/data/disputes_2.csv"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    let _stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(_stdout.contains("true"));
+    assert!(_stderr.contains("disputes_1.csv: 5 rows read, 0 rows skipped"));
+    assert!(_stderr.contains("disputes_2.csv: 4 rows read, 0 rows skipped"));
+}
+
+#[test]
+fn test_input_glob() {
+    let dir = std::env::temp_dir().join("gravenche_input_glob");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // Each file deposits for its own client.
+    for (name, client) in [("2024-03.csv", 3), ("2024-01.csv", 1), ("2024-02.csv", 2)] {
+        let csv = format!(
+            "type, client, tx, amount\ndeposit, {}, {}, 1.0\n",
+            client, client
+        );
+        std::fs::write(dir.join(name), csv).unwrap();
+    }
+    let pattern = dir.join("2024-*.csv").display().to_string();
+    let overlapping = dir.join("2024-0[12].csv").display().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["--input", &pattern, "--input", &overlapping])
+        .output()
+        .expect("Failed to execute Gravenche.");
+
+    assert_eq!(output.status.code(), Some(0));
+    let _stderr = String::from_utf8(output.stderr).unwrap();
+    let processed: Vec<&str> = _stderr
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(processed.len(), 3);
+    assert!(processed[0].ends_with("2024-01.csv"));
+    assert!(processed[1].ends_with("2024-02.csv"));
+    assert!(processed[2].ends_with("2024-03.csv"));
+
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    let mut client_ids: Vec<&str> = _stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split('|').next().unwrap().trim())
+        .collect();
+    client_ids.sort();
+    assert_eq!(client_ids, vec!["1", "2", "3"]);
+}
+
+#[test]
+fn test_input_glob_no_match() {
+    let pattern = std::env::temp_dir()
+        .join("gravenche_no_such_dir/*.csv")
+        .display()
+        .to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["--input", &pattern])
+        .output()
+        .expect("Failed to execute Gravenche.");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["--input", &pattern, "--allow-empty"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn test_verify() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/disputes.csv", "--verify"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_strict_exit_code() {
+    for path in ["tests/data/malformed.csv", "tests/data/unknown_dispute.csv"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+            .args([path])
+            .output()
+            .expect("Failed to execute Gravenche.");
+        assert_eq!(output.status.code(), Some(0));
+
+        let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+            .args([path, "--strict"])
+            .output()
+            .expect("Failed to execute Gravenche.");
+        assert_eq!(output.status.code(), Some(2));
+        // The client table is still shown.
+        assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/disputes.csv", "--strict"])
+        .output()
+        .expect("Failed to execute Gravenche.");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_strict_exports_before_exit() {
+    let database_path =
+        std::env::temp_dir().join(format!("gravenche_strict_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&database_path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["tests/data/malformed.csv", "--strict", "--export-sqlite"])
+        .arg(&database_path)
+        .output()
+        .expect("Failed to execute Gravenche.");
+    assert_eq!(output.status.code(), Some(2));
+    // The database is written even though the exit code reports the skipped rows.
+    assert!(database_path.exists());
+    std::fs::remove_file(&database_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_serve() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["serve", "--listen", "127.0.0.1:0", "--strict"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    // The server logs the address it listens on once it is ready.
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap().to_string();
+
+    let stream = tokio::net::TcpStream::connect(&address).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    writer
+        .write_all(
+            b"deposit, 1, 1, 10.0\ndeposit, 2, 2, 3.0\nwithdrawal, 1, 3, 4.0\nbogus, 1\nSNAPSHOT\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        if line.is_empty() {
+            break;
+        }
+        response.push(line);
+    }
+    drop(writer);
+
+    assert!(response[0].starts_with("ERROR"));
+    assert_eq!(response.len(), 4);
+    assert!(
+        response.contains(&"     1 |          6 |          0 |          6 |  false".to_string())
+    );
+    assert!(
+        response.contains(&"     2 |          3 |          0 |          3 |  false".to_string())
+    );
+
+    // Interrupting the server shows the final state.
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let _stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(_stdout.contains("     1 |          6 |          0 |          6 |  false"));
+}
+
+// Sends an HTTP/1.1 request and returns the status code and the body of the response.
+#[cfg(feature = "http")]
+async fn http_request(address: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+    use tokio::io::AsyncReadExt;
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        address,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+    (status, body)
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_http() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["--http", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    // The server logs the address it listens on once it is ready.
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap().to_string();
+
+    for body in [
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 10.0}"#,
+        r#"{"type": "deposit", "client": 2, "tx": 2, "amount": 3.0}"#,
+        r#"{"type": "withdrawal", "client": 1, "tx": 3, "amount": 4.0}"#,
+    ] {
+        let (status, _) = http_request(&address, "POST", "/transactions", body).await;
+        assert_eq!(status, 202);
+    }
+    let (status, _) = http_request(
+        &address,
+        "POST",
+        "/transactions",
+        r#"{"type": "bogus", "client": 1, "tx": 4}"#,
+    )
+    .await;
+    assert_eq!(status, 422);
+
+    let (status, body) = http_request(&address, "GET", "/clients", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"[{"client":1,"total":6.0,"available":6.0,"held":0.0,"locked":false},{"client":2,"total":3.0,"available":3.0,"held":0.0,"locked":false}]"#
+    );
+    let (status, body) = http_request(&address, "GET", "/clients/2", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"client":2,"total":3.0,"available":3.0,"held":0.0,"locked":false}"#
+    );
+    let (status, _) = http_request(&address, "GET", "/clients/3", "").await;
+    assert_eq!(status, 404);
+
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn test_http_events() {
+    use futures_util::StreamExt;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args(["--http", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap().to_string();
+    let (mut events, _) = tokio_tungstenite::connect_async(format!("ws://{}/events", address))
+        .await
+        .unwrap();
+
+    // Replay a small file through the API.
+    let csv = std::fs::read_to_string("tests/data/disputes.csv").unwrap();
+    for row in csv.lines().skip(1) {
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let body = format!(
+            r#"{{"type": "{}", "client": {}, "tx": {}, "amount": {}}}"#,
+            fields[0],
+            fields[1],
+            fields[2],
+            if fields[3].is_empty() {
+                "null"
+            } else {
+                fields[3]
+            }
+        );
+        let (status, _) = http_request(&address, "POST", "/transactions", &body).await;
+        assert_eq!(status, 202);
+    }
+
+    // Every row of the file is applied, so there are nine events.
+    let mut client_events = Vec::new();
+    for _ in 0..9 {
+        let message = events.next().await.unwrap().unwrap();
+        let event: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        if event["client"] == 1 {
+            client_events.push(format!(
+                "{} {} {} {}",
+                event["event"].as_str().unwrap(),
+                event["tx"],
+                event["available"].as_str().unwrap(),
+                event["held"].as_str().unwrap()
+            ));
+        }
+    }
+    assert_eq!(
+        client_events,
+        [
+            "deposit 1 10.0 0.0",
+            "deposit 2 15.0 0.0",
+            "withdrawal 5 14.0 0.0",
+            "dispute 1 4.0 10.0",
+            "resolve 1 14.0 0.0",
+        ]
+    );
+
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gravenche"))
+        .args([
+            "tests/data/disputes.csv",
+            "--follow",
+            "--metrics-addr",
+            "127.0.0.1:0",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute Gravenche.");
+    // The address is logged once the endpoint is ready.
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap().to_string();
+
+    // The file is followed in the background, so scrape until all of it is processed.
+    let mut metrics = String::new();
+    for _ in 0..100 {
+        let mut stream = tokio::net::TcpStream::connect(&address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        metrics.clear();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut metrics)
+            .await
+            .unwrap();
+        if metrics.contains("gravenche_processing_latency_seconds_count 9") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+    assert!(metrics.contains("gravenche_transactions_processed_total{type=\"deposit\"} 4\n"));
+    assert!(metrics.contains("gravenche_transactions_processed_total{type=\"withdrawal\"} 1\n"));
+    assert!(metrics.contains("gravenche_transactions_processed_total{type=\"dispute\"} 2\n"));
+    assert!(metrics.contains("gravenche_transactions_processed_total{type=\"resolve\"} 1\n"));
+    assert!(metrics.contains("gravenche_transactions_processed_total{type=\"chargeback\"} 1\n"));
+    assert!(metrics
+        .contains("gravenche_transactions_rejected_total{reason=\"insufficient_funds\"} 0\n"));
+    assert!(metrics.contains("gravenche_accounts_locked_total 1\n"));
+    assert!(metrics.contains("gravenche_processing_latency_seconds_count 9\n"));
+}